@@ -0,0 +1,13 @@
+// Generates the permissions of the Tauri plugin's commands (allow-start-capture, ...)
+#[cfg(feature = "tauri")]
+const COMMANDS: &[&str] = &[
+    "start_capture",
+    "stop_capture",
+    "permission_status",
+    "request_permission",
+];
+
+fn main() {
+    #[cfg(feature = "tauri")]
+    tauri_plugin::Builder::new(COMMANDS).build();
+}
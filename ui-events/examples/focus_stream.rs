@@ -0,0 +1,38 @@
+use futures_util::StreamExt;
+use std::future::ready;
+use ui_events::platform::event_stream;
+use ui_events::{EventType, ListenerConfig};
+
+// Prints the first 10 focus changes using the stream API, then exits
+fn main() {
+    let config = ListenerConfig {
+        print_events: false,
+        ..Default::default()
+    };
+    let (events, listener) = event_stream(config);
+
+    // The listener needs the main thread, so the stream is consumed on another one
+    std::thread::spawn(move || {
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        rt.block_on(async {
+            events
+                .filter(|event| {
+                    ready(matches!(
+                        event.event_type,
+                        EventType::ElementFocused | EventType::WindowFocused
+                    ))
+                })
+                .take(10)
+                .for_each(|event| {
+                    println!("{}", event);
+                    ready(())
+                })
+                .await;
+        });
+        std::process::exit(0);
+    });
+
+    if let Err(e) = listener.run() {
+        eprintln!("listener failed: {}", e);
+    }
+}
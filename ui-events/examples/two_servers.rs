@@ -0,0 +1,34 @@
+use tokio::sync::mpsc;
+use ui_events::platform::listener_run_with_config;
+use ui_events::sink::fanout;
+use ui_events::{ListenerConfig, ServerConfig, run_server_with_config};
+
+// Serves one listener's events on two ports: 9001 for a consumer app, 9002 for debugging
+fn main() {
+    let _ = tracing_subscriber::fmt::try_init();
+    let (tx, rx) = mpsc::channel(100);
+
+    let rt = tokio::runtime::Runtime::new().unwrap();
+    rt.spawn(async move {
+        let mut outputs = fanout(rx, 2).into_iter();
+        let consumer = outputs.next().unwrap();
+        let debug = outputs.next().unwrap();
+        let debug_config = ServerConfig {
+            omit_null_fields: true,
+            ..Default::default()
+        };
+        let (consumer, debug) = tokio::join!(
+            run_server_with_config(9001, consumer, ServerConfig::default()),
+            run_server_with_config(9002, debug, debug_config),
+        );
+        if let Err(e) = consumer.and(debug) {
+            eprintln!("server failed: {}", e);
+        }
+        std::process::exit(0);
+    });
+
+    // The listener needs the main thread
+    if let Err(e) = listener_run_with_config(tx, ListenerConfig::default(), None) {
+        eprintln!("listener failed: {}", e);
+    }
+}
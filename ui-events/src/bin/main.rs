@@ -1,21 +1,416 @@
-use clap::Parser;
+use clap::{Parser, Subcommand, ValueEnum};
+use std::fs::File;
+use std::path::PathBuf;
+use std::time::Duration;
 
-use tracing::info;
-use ui_events::run;
+use tracing::{error, info};
+use ui_events::sink::write_lines;
+use ui_events::{
+    AttributeMask, EventType, ListenerConfig, ReplayPace, ServerConfig, run_server_from_file,
+    run_to_writer, run_with_server_config,
+};
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum Output {
+    /// Serve events over a websocket
+    Ws,
+    /// Write one JSON event per line to stdout (or --output-file), no server
+    Ndjson,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// List running apps and whether their events can be captured, then exit
+    ListApps,
+}
 
 #[derive(Parser, Debug)]
 #[clap(author, version, about, long_about = None)]
 struct Args {
+    #[clap(subcommand)]
+    command: Option<Command>,
+
+    /// Where events go
+    #[clap(long, value_enum, default_value_t = Output::Ws)]
+    output: Output,
+
+    /// Write events as JSON lines to this file; in addition to the server with
+    /// --output ws, instead of stdout with --output ndjson
+    #[clap(long, value_name = "PATH")]
+    output_file: Option<PathBuf>,
+
     /// WebSocket server port
     #[clap(short, long, value_parser, default_value_t = 9001)]
     port: u16,
+
+    /// Serve the events recorded in this JSONL file instead of capturing
+    #[clap(long, value_name = "FILE")]
+    replay: Option<PathBuf>,
+
+    /// With --replay, send events without the recorded pauses between them
+    #[clap(long, requires = "replay")]
+    replay_fast: bool,
+
+    /// Address to listen on (0.0.0.0 for all interfaces)
+    #[clap(long, default_value = "127.0.0.1")]
+    host: std::net::IpAddr,
+
+    /// Emit CaretMoved events with the text caret's screen rect
+    #[clap(long)]
+    track_caret: bool,
+
+    /// Only emit text field values once editing is finished (on focus loss)
+    #[clap(long)]
+    value_on_commit: bool,
+
+    /// Replace captured text with salted hashes
+    #[clap(long)]
+    hash_values: bool,
+
+    /// Salt for --hash-values (random per session if omitted)
+    #[clap(long, requires = "hash_values")]
+    hash_salt: Option<String>,
+
+    /// Report element coordinates in pixels instead of points
+    #[clap(long)]
+    pixels: bool,
+
+    /// Merge a focus event and an immediately following value change into one event
+    #[clap(long)]
+    merge_focus_value: bool,
+
+    /// Include the actions each element supports
+    #[clap(long)]
+    include_actions: bool,
+
+    /// Answer "describe" queries from clients with a full element attribute dump
+    #[clap(long)]
+    enable_describe: bool,
+
+    /// Emit a ContextSample of the frontmost app/window every N seconds
+    #[clap(long, value_name = "SECS")]
+    context_sample_secs: Option<u64>,
+
+    /// Report failed accessibility attribute reads with each event
+    #[clap(long)]
+    diagnostics: bool,
+
+    /// Leave null fields out of the JSON instead of sending them as null
+    #[clap(long)]
+    omit_nulls: bool,
+
+    /// Do not compress events for websocket clients offering permessage-deflate
+    #[clap(long)]
+    no_compression: bool,
+
+    /// Do not read element values (typed text, selections); see ListenerConfig::capture_values
+    #[clap(long)]
+    no_values: bool,
+
+    /// Only capture this app, by bundle id or pid (repeatable)
+    #[clap(long = "only-app", value_name = "BUNDLE_ID|PID")]
+    only_apps: Vec<String>,
+
+    /// Never capture this app, by bundle id or pid (repeatable)
+    #[clap(long = "ignore-app", value_name = "BUNDLE_ID|PID")]
+    ignore_apps: Vec<String>,
+
+    /// Only capture apps whose bundle id or path matches this regex (repeatable)
+    #[cfg(feature = "regex")]
+    #[clap(long = "only-app-pattern", value_name = "REGEX", value_parser = regex::Regex::new)]
+    only_app_patterns: Vec<regex::Regex>,
+
+    /// Never capture apps whose bundle id or path matches this regex (repeatable)
+    #[cfg(feature = "regex")]
+    #[clap(long = "ignore-app-pattern", value_name = "REGEX", value_parser = regex::Regex::new)]
+    ignore_app_patterns: Vec<regex::Regex>,
+
+    /// Only emit these event types, comma-separated (e.g. WindowFocused,ValueChanged)
+    #[clap(long = "events", value_name = "TYPES", value_delimiter = ',', value_parser = parse_event_type)]
+    event_types: Vec<String>,
+
+    /// Drop focus events repeating the previous event within this many milliseconds
+    #[clap(long, value_name = "MS")]
+    focus_dedup_ms: Option<u64>,
+
+    /// Cut element values to this many characters
+    #[clap(long, value_name = "CHARS")]
+    max_value_length: Option<usize>,
+
+    /// Send at most this many events per second, dropping the excess
+    #[clap(long, value_name = "N")]
+    max_events_per_sec: Option<u32>,
+
+    /// Cap one event type, e.g. MouseMoved=20 (events per second; repeatable)
+    #[clap(long = "type-rate", value_name = "TYPE=N", value_parser = parse_type_rate)]
+    event_type_rates: Vec<(String, u32)>,
+
+    /// Element attributes to read, comma-separated: role, identifier, value, position, size,
+    /// path, enabled, focused, children_count, default, all or none (default: default)
+    #[clap(long, value_name = "ATTRS", value_delimiter = ',', value_parser = parse_attribute)]
+    attributes: Vec<AttributeMask>,
+
+    /// Emit a Heartbeat event after this many idle seconds (15 when given without a value)
+    #[clap(long, value_name = "SECS", num_args = 0..=1, default_missing_value = "15")]
+    heartbeat: Option<u64>,
+
+    /// Observe all running apps, not only the frontmost one
+    #[clap(long)]
+    watch_all_apps: bool,
+
+    /// Require websocket clients to present this token (see ServerConfig::auth_token)
+    #[clap(long, value_name = "TOKEN")]
+    auth_token: Option<String>,
+
+    /// Also serve events as Server-Sent Events at http://<host>:<PORT>/events
+    #[clap(long, value_name = "PORT")]
+    sse_port: Option<u16>,
+
+    /// Also observe this raw AX notification (repeatable), reported as a Custom event
+    #[clap(long = "extra-notification", value_name = "NAME")]
+    extra_notifications: Vec<String>,
+
+    /// Include the active keyboard input source with text-related events
+    #[clap(long)]
+    input_source: bool,
+
+    /// Include the placeholder text of focused text fields
+    #[clap(long)]
+    include_placeholder: bool,
+
+    /// Tag events with an interaction id; a session ends after this many idle milliseconds
+    #[clap(long, value_name = "MS")]
+    interaction_gap_ms: Option<u64>,
+
+    /// Report the frontmost app as unresponsive when it takes longer than this to answer
+    #[clap(long, value_name = "MS")]
+    hang_timeout_ms: Option<u64>,
+
+    /// Emit key press and mouse events (needs Input Monitoring permission)
+    #[clap(long)]
+    capture_input: bool,
+
+    /// Serve wss:// with this PEM certificate (chain); requires --tls-key
+    #[cfg(feature = "tls")]
+    #[clap(long, value_name = "PATH", requires = "tls_key")]
+    tls_cert: Option<PathBuf>,
+
+    /// PEM private key for --tls-cert
+    #[cfg(feature = "tls")]
+    #[clap(long, value_name = "PATH", requires = "tls_cert")]
+    tls_key: Option<PathBuf>,
+
+    /// Also write every event to Parquet files in this directory (with --output ws)
+    #[cfg(feature = "parquet")]
+    #[clap(long, value_name = "DIR")]
+    parquet_out: Option<PathBuf>,
+}
+
+// An `EventType` name for --events, or `Custom:<notification>`
+fn parse_event_type(name: &str) -> Result<String, String> {
+    if EventType::NAMES.contains(&name) || name.starts_with("Custom:") {
+        Ok(name.to_owned())
+    } else {
+        Err(format!(
+            "unknown event type, expected one of: {}",
+            EventType::NAMES.join(", ")
+        ))
+    }
+}
+
+// An element attribute name for --attributes
+fn parse_attribute(name: &str) -> Result<AttributeMask, String> {
+    AttributeMask::from_name(name).ok_or_else(|| {
+        "unknown attribute, expected one of: role, identifier, value, position, size, path, \
+         enabled, focused, children_count, default, all, none"
+            .to_owned()
+    })
+}
+
+// `<event type>=<events per second>` for --type-rate
+fn parse_type_rate(value: &str) -> Result<(String, u32), String> {
+    let (name, rate) = value
+        .split_once('=')
+        .ok_or_else(|| "expected TYPE=N".to_owned())?;
+    let rate = rate
+        .parse()
+        .map_err(|e| format!("invalid rate {:?}: {}", rate, e))?;
+    Ok((parse_event_type(name)?, rate))
+}
+
+// Print `platform::list_apps` as a table
+fn list_apps() {
+    let granted = ui_events::accessibility_granted();
+    println!(
+        "accessibility permission: {}",
+        if granted { "granted" } else { "missing" }
+    );
+    let apps = match ui_events::platform::list_apps() {
+        Ok(apps) => apps,
+        Err(e) => {
+            error!(error = %e, "failed to list apps");
+            std::process::exit(1);
+        }
+    };
+    println!(
+        "{:>7}  {:<30}  {:<40}  OBSERVABLE",
+        "PID", "NAME", "BUNDLE ID"
+    );
+    for app in apps {
+        let application = &app.application;
+        let observable = match &app.error {
+            None => "yes".to_owned(),
+            Some(e) => format!("no ({})", e),
+        };
+        println!(
+            "{:>7}  {:<30}  {:<40}  {}",
+            application
+                .pid
+                .map(|pid| pid.to_string())
+                .unwrap_or_default(),
+            application.name.as_deref().unwrap_or("-"),
+            application.bundle_id.as_deref().unwrap_or("-"),
+            observable
+        );
+    }
 }
 
 fn main() {
-    tracing_subscriber::fmt::init();
+    let args = Args::parse();
+    if let Some(Command::ListApps) = args.command {
+        tracing_subscriber::fmt()
+            .with_writer(std::io::stderr)
+            .init();
+        list_apps();
+        return;
+    }
+    let ndjson_on_stdout = args.output == Output::Ndjson && args.output_file.is_none();
+    if ndjson_on_stdout {
+        // Keep stdout for the events
+        tracing_subscriber::fmt()
+            .with_writer(std::io::stderr)
+            .init();
+    } else {
+        tracing_subscriber::fmt::init();
+    }
     info!("starting ui-events...");
 
-    let port = Args::parse().port;
+    let output_file = args
+        .output_file
+        .as_ref()
+        .map(|path| match File::create(path) {
+            Ok(file) => file,
+            Err(e) => {
+                error!(path = %path.display(), error = %e, "failed to create output file");
+                std::process::exit(1);
+            }
+        });
+
+    let config = ListenerConfig {
+        track_caret: args.track_caret,
+        value_on_commit: args.value_on_commit,
+        hash_values: args.hash_values,
+        hash_salt: args.hash_salt,
+        coordinates_in_pixels: args.pixels,
+        merge_focus_value: args.merge_focus_value,
+        include_actions: args.include_actions,
+        enable_describe: args.enable_describe,
+        context_sample_interval: args.context_sample_secs.map(Duration::from_secs),
+        diagnostics: args.diagnostics,
+        extra_notifications: args.extra_notifications,
+        include_input_source: args.input_source,
+        include_placeholder: args.include_placeholder,
+        interaction_gap: args.interaction_gap_ms.map(Duration::from_millis),
+        hang_timeout: args.hang_timeout_ms.map(Duration::from_millis),
+        capture_input: args.capture_input,
+        capture_values: !args.no_values,
+        only_apps: args.only_apps,
+        ignore_apps: args.ignore_apps,
+        #[cfg(feature = "regex")]
+        only_app_patterns: args.only_app_patterns,
+        #[cfg(feature = "regex")]
+        ignore_app_patterns: args.ignore_app_patterns,
+        watch_all_apps: args.watch_all_apps,
+        event_types: args.event_types,
+        focus_dedup_window: args.focus_dedup_ms.map(Duration::from_millis),
+        max_value_length: args.max_value_length,
+        max_events_per_sec: args.max_events_per_sec,
+        event_type_rates: args.event_type_rates,
+        heartbeat_interval: args.heartbeat.map(Duration::from_secs),
+        attributes: if args.attributes.is_empty() {
+            AttributeMask::DEFAULT
+        } else {
+            args.attributes
+                .into_iter()
+                .fold(AttributeMask::NONE, |mask, attribute| mask | attribute)
+        },
+        print_events: !ndjson_on_stdout,
+        ..Default::default()
+    };
+
+    if args.output == Output::Ndjson {
+        match output_file {
+            Some(file) => run_to_writer(config, file, args.omit_nulls),
+            None => run_to_writer(config, std::io::stdout(), args.omit_nulls),
+        }
+        return;
+    }
+
+    let mut server_config = ServerConfig {
+        host: Some(args.host),
+        omit_null_fields: args.omit_nulls,
+        compression: !args.no_compression,
+        auth_token: args.auth_token,
+        sse_port: args.sse_port,
+        ..Default::default()
+    };
+
+    if let Some(file) = output_file {
+        let (sink_tx, sink_rx) = tokio::sync::mpsc::channel(1024);
+        server_config.reliable_sinks.push(sink_tx);
+        std::thread::spawn(move || {
+            if let Err(e) = write_lines(sink_rx, file) {
+                error!(error = %e, "failed to write events to output file");
+            }
+        });
+    }
+
+    #[cfg(feature = "tls")]
+    if let (Some(cert_path), Some(key_path)) = (args.tls_cert, args.tls_key) {
+        server_config.tls = Some(ui_events::server::TlsConfig {
+            cert_path,
+            key_path,
+        });
+    }
+
+    #[cfg(feature = "parquet")]
+    if let Some(dir) = args.parquet_out {
+        use ui_events::parquet::{DEFAULT_ROWS_PER_FILE, run_parquet_sink};
+        let (sink_tx, sink_rx) = tokio::sync::mpsc::channel(1024);
+        server_config.reliable_sinks.push(sink_tx);
+        std::thread::spawn(move || {
+            if let Err(e) = run_parquet_sink(&dir, sink_rx, DEFAULT_ROWS_PER_FILE) {
+                error!(error = %e, "parquet sink failed");
+            }
+        });
+    }
+
+    if let Some(path) = args.replay {
+        let pace = if args.replay_fast {
+            ReplayPace::AsFastAsPossible
+        } else {
+            ReplayPace::Recorded
+        };
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        if let Err(e) = rt.block_on(run_server_from_file(args.port, path, pace, server_config)) {
+            error!(error = %e, "replay failed");
+            std::process::exit(1);
+        }
+        return;
+    }
 
-    run(port);
+    if let Err(e) = run_with_server_config(args.port, config, server_config) {
+        error!(error = %e, "failed to run");
+        std::process::exit(1);
+    }
 }
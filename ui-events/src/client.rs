@@ -0,0 +1,88 @@
+// Helpers for consuming the ui-events websocket stream from Rust
+
+use rand::Rng;
+use std::time::Duration;
+
+#[cfg(feature = "client")]
+mod events;
+#[cfg(feature = "client")]
+pub use events::{ClientConfig, EventClient};
+
+/// Reconnect backoff parameters.
+///
+/// Delays grow exponentially from `initial` and are capped at `max`. Each delay is drawn
+/// uniformly from `[initial, ceiling]` ("full jitter" with a floor), so a fleet of clients
+/// reconnecting after a server restart spreads out instead of retrying in lockstep.
+#[derive(Debug, Clone, Copy)]
+pub struct BackoffConfig {
+    pub initial: Duration,
+    pub max: Duration,
+    pub multiplier: u32,
+}
+
+impl Default for BackoffConfig {
+    fn default() -> Self {
+        Self {
+            initial: Duration::from_millis(250),
+            max: Duration::from_secs(30),
+            multiplier: 2,
+        }
+    }
+}
+
+/// Stateful backoff sequence built from a [`BackoffConfig`].
+///
+/// ```
+/// use std::time::Duration;
+/// use ui_events::client::{Backoff, BackoffConfig};
+///
+/// let config = BackoffConfig {
+///     initial: Duration::from_millis(250),
+///     max: Duration::from_secs(30),
+///     ..Default::default()
+/// };
+/// let mut backoff = Backoff::new(config);
+/// for _ in 0..10_000 {
+///     let delay = backoff.next_delay();
+///     assert!(delay >= config.initial && delay <= config.max);
+/// }
+/// backoff.reset();
+/// assert_eq!(backoff.next_delay(), config.initial);
+/// ```
+#[derive(Debug, Clone)]
+pub struct Backoff {
+    config: BackoffConfig,
+    ceiling: Duration,
+}
+
+impl Backoff {
+    pub fn new(config: BackoffConfig) -> Self {
+        Self {
+            config,
+            ceiling: config.initial.min(config.max),
+        }
+    }
+
+    /// Returns the delay to wait before the next attempt and advances the sequence.
+    pub fn next_delay(&mut self) -> Duration {
+        let floor = self.config.initial.min(self.config.max);
+        let ceiling = self.ceiling;
+        self.ceiling = ceiling
+            .saturating_mul(self.config.multiplier.max(1))
+            .min(self.config.max);
+
+        let (low, high) = (floor.as_millis() as u64, ceiling.as_millis() as u64);
+        Duration::from_millis(rand::thread_rng().gen_range(low..=high))
+    }
+
+    /// Starts over from `initial`, e.g. after a successful connection.
+    pub fn reset(&mut self) {
+        self.ceiling = self.config.initial.min(self.config.max);
+    }
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Self::new(BackoffConfig::default())
+    }
+}
@@ -0,0 +1,174 @@
+// Reconnecting websocket client yielding typed events (feature "client")
+
+use super::{Backoff, BackoffConfig};
+use crate::event::UiEvent;
+use futures_util::{SinkExt, Stream, StreamExt};
+use std::time::Duration;
+use tokio::net::TcpStream;
+use tokio::sync::mpsc;
+use tokio::time::{Instant, interval_at};
+use tokio_stream::wrappers::ReceiverStream;
+use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+use tokio_tungstenite::tungstenite::http::HeaderValue;
+use tokio_tungstenite::tungstenite::protocol::Message;
+use tokio_tungstenite::tungstenite::protocol::frame::coding::CloseCode;
+use tokio_tungstenite::{MaybeTlsStream, WebSocketStream, connect_async};
+use tracing::{debug, info, warn};
+
+// Events buffered between the connection task and the stream's consumer
+const CLIENT_BUFFER: usize = 256;
+
+/// Options for [`EventClient::connect_with_config`].
+#[derive(Debug, Clone, Default)]
+pub struct ClientConfig {
+    /// Delays between reconnect attempts; starts over after each successful handshake.
+    pub backoff: BackoffConfig,
+    /// Ping the server at this interval and reconnect when the previous ping is still
+    /// unanswered. `None` (default) sends no pings and relies on TCP to notice a dead server.
+    pub ping_interval: Option<Duration>,
+    /// Sent as `Authorization: Bearer <token>` (see `ServerConfig::auth_token`).
+    pub auth_token: Option<String>,
+}
+
+/// Typed consumer of a ui-events websocket server.
+///
+/// ```no_run
+/// use futures_util::StreamExt;
+/// use ui_events::client::EventClient;
+///
+/// # async fn example() {
+/// let mut events = Box::pin(EventClient::connect("ws://localhost:9001"));
+/// while let Some(event) = events.next().await {
+///     println!("#{} {}", event.seq, event);
+/// }
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct EventClient;
+
+impl EventClient {
+    /// [`connect_with_config`](Self::connect_with_config) with default options.
+    pub fn connect(url: impl Into<String>) -> impl Stream<Item = UiEvent> {
+        Self::connect_with_config(url, ClientConfig::default())
+    }
+
+    /// Stream of the events of the server at `url` (e.g. `ws://localhost:9001`), reconnecting
+    /// with backoff whenever the connection drops. Messages that are not events (query
+    /// replies, lag notices) are skipped.
+    ///
+    /// After a reconnect the server replays its backlog, so recent events can arrive twice;
+    /// compare `UiEvent::seq` to skip them. The stream ends when the server rejects the
+    /// client (close code 1008, e.g. a wrong token) or `url` is invalid. Dropping it closes
+    /// the connection. Must be called within a Tokio runtime.
+    pub fn connect_with_config(
+        url: impl Into<String>,
+        config: ClientConfig,
+    ) -> impl Stream<Item = UiEvent> {
+        let (tx, rx) = mpsc::channel(CLIENT_BUFFER);
+        tokio::spawn(run(url.into(), config, tx));
+        ReceiverStream::new(rx)
+    }
+}
+
+// How a connection ended
+enum Disconnect {
+    Reconnect,
+    GiveUp,
+}
+
+// Connect, forward, reconnect until the consumer goes away or the server rejects the client
+async fn run(url: String, config: ClientConfig, tx: mpsc::Sender<UiEvent>) {
+    let mut backoff = Backoff::new(config.backoff);
+    loop {
+        let mut request = match url.as_str().into_client_request() {
+            Ok(request) => request,
+            Err(e) => {
+                warn!(%url, error = %e, "invalid ui-events server url");
+                return;
+            }
+        };
+        if let Some(token) = config.auth_token.as_deref() {
+            match HeaderValue::from_str(&format!("Bearer {}", token)) {
+                Ok(value) => {
+                    request.headers_mut().insert("authorization", value);
+                }
+                Err(e) => {
+                    warn!(error = %e, "auth token is not a valid header value");
+                    return;
+                }
+            }
+        }
+
+        match connect_async(request).await {
+            Ok((ws_stream, _response)) => {
+                info!(%url, "connected to ui-events server");
+                backoff.reset();
+                if let Disconnect::GiveUp = forward_events(ws_stream, &config, &tx).await {
+                    return;
+                }
+            }
+            Err(e) => debug!(%url, error = %e, "failed to connect to ui-events server"),
+        }
+        if tx.is_closed() {
+            return;
+        }
+
+        let delay = backoff.next_delay();
+        debug!(?delay, "reconnecting to ui-events server");
+        tokio::time::sleep(delay).await;
+    }
+}
+
+async fn forward_events(
+    ws_stream: WebSocketStream<MaybeTlsStream<TcpStream>>,
+    config: &ClientConfig,
+    tx: &mpsc::Sender<UiEvent>,
+) -> Disconnect {
+    let (mut ws_sender, mut ws_receiver) = ws_stream.split();
+    // The period only matters when pings are enabled (the select branch is disabled otherwise)
+    let ping_period = config.ping_interval.unwrap_or(Duration::from_secs(3600));
+    let mut ping_timer = interval_at(Instant::now() + ping_period, ping_period);
+    let mut awaiting_pong = false;
+
+    loop {
+        tokio::select! {
+            message = ws_receiver.next() => match message {
+                Some(Ok(Message::Text(text))) => match serde_json::from_str::<UiEvent>(&text) {
+                    Ok(event) => {
+                        if tx.send(event).await.is_err() {
+                            return Disconnect::GiveUp;
+                        }
+                    }
+                    Err(_) => debug!(message = %text, "skipping message that is not an event"),
+                },
+                Some(Ok(Message::Pong(_))) => awaiting_pong = false,
+                Some(Ok(Message::Close(frame))) => {
+                    if frame.as_ref().is_some_and(|f| f.code == CloseCode::Policy) {
+                        warn!(?frame, "ui-events server rejected the client");
+                        return Disconnect::GiveUp;
+                    }
+                    info!(?frame, "ui-events server closed the connection");
+                    return Disconnect::Reconnect;
+                }
+                // Pings are answered by tungstenite
+                Some(Ok(_)) => {}
+                Some(Err(e)) => {
+                    warn!(error = %e, "websocket error");
+                    return Disconnect::Reconnect;
+                }
+                None => return Disconnect::Reconnect,
+            },
+            _ = ping_timer.tick(), if config.ping_interval.is_some() => {
+                if awaiting_pong {
+                    warn!("ui-events server did not answer ping, reconnecting");
+                    return Disconnect::Reconnect;
+                }
+                if ws_sender.send(Message::Ping(Vec::new())).await.is_err() {
+                    return Disconnect::Reconnect;
+                }
+                awaiting_pong = true;
+            }
+            _ = tx.closed() => return Disconnect::GiveUp,
+        }
+    }
+}
@@ -0,0 +1,395 @@
+// Runtime options for the platform listener
+
+use crate::event::{ApplicationInfo, EventType};
+use std::ops::{BitOr, BitOrAssign};
+use std::time::Duration;
+
+/// Element attributes read for each event, see `ListenerConfig::attributes`. Combine with
+/// `|`, e.g. `AttributeMask::ROLE | AttributeMask::IDENTIFIER`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct AttributeMask(u16);
+
+impl AttributeMask {
+    /// `role` and `normalized_role`.
+    pub const ROLE: Self = Self(1);
+    pub const IDENTIFIER: Self = Self(1 << 1);
+    pub const VALUE: Self = Self(1 << 2);
+    pub const POSITION: Self = Self(1 << 3);
+    pub const SIZE: Self = Self(1 << 4);
+    pub const PATH: Self = Self(1 << 5);
+    pub const ENABLED: Self = Self(1 << 6);
+    pub const FOCUSED: Self = Self(1 << 7);
+    pub const CHILDREN_COUNT: Self = Self(1 << 8);
+    pub const NONE: Self = Self(0);
+    /// What events always carried: role, identifier, value, position, size and path. The
+    /// element states (`ENABLED`, `FOCUSED`, `CHILDREN_COUNT`) are opt-in.
+    pub const DEFAULT: Self = Self((1 << 6) - 1);
+    pub const ALL: Self = Self((1 << 9) - 1);
+
+    /// Whether every attribute of `other` is in `self`.
+    pub fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    /// Parses a lowercase attribute name (`role`, `identifier`, `value`, `position`, `size`,
+    /// `path`, `enabled`, `focused`, `children_count`), or `default` / `all` / `none`.
+    pub fn from_name(name: &str) -> Option<Self> {
+        Some(match name {
+            "role" => Self::ROLE,
+            "identifier" => Self::IDENTIFIER,
+            "value" => Self::VALUE,
+            "position" => Self::POSITION,
+            "size" => Self::SIZE,
+            "path" => Self::PATH,
+            "enabled" => Self::ENABLED,
+            "focused" => Self::FOCUSED,
+            "children_count" => Self::CHILDREN_COUNT,
+            "default" => Self::DEFAULT,
+            "all" => Self::ALL,
+            "none" => Self::NONE,
+            _ => return None,
+        })
+    }
+}
+
+impl Default for AttributeMask {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
+
+impl BitOr for AttributeMask {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self {
+        Self(self.0 | rhs.0)
+    }
+}
+
+impl BitOrAssign for AttributeMask {
+    fn bitor_assign(&mut self, rhs: Self) {
+        self.0 |= rhs.0;
+    }
+}
+
+/// Options controlling what the platform listener captures.
+///
+/// `Default` matches the historical behavior: every opt-in mode is off.
+#[derive(Debug, Clone)]
+pub struct ListenerConfig {
+    /// Emit `CaretMoved` with the caret's screen rect whenever the focused text element's
+    /// selection range moves (typing, arrow keys, clicks).
+    pub track_caret: bool,
+    /// Minimum time between two `CaretMoved` events; moves inside the window are dropped.
+    pub caret_throttle: Duration,
+    /// Hold back per-keystroke `ValueChanged` events for text inputs and emit a single event
+    /// with the final value once the element loses focus (tagged `"committed": true`).
+    pub value_on_commit: bool,
+    /// Replace element values, identifiers and window titles with salted one-way hashes.
+    pub hash_values: bool,
+    /// Salt for `hash_values`. `None` picks a random salt per session, so hashes only
+    /// compare equal within one run.
+    pub hash_salt: Option<String>,
+    /// Report element positions and sizes in pixels instead of points, using the backing
+    /// scale factor of the screen the element is on. `scale_factor` is reported either way.
+    pub coordinates_in_pixels: bool,
+    /// Fold a `ValueChanged` that follows an `ElementFocused` on the same element within
+    /// `merge_window` into one `ElementFocused` event carrying the new value
+    /// (`"value_changed": true` in `event_specific_data`).
+    pub merge_focus_value: bool,
+    /// How long a focus event waits for a matching value change. Focus events are delayed
+    /// by up to this much when merging is on.
+    pub merge_window: Duration,
+    /// Include the element's supported actions (`AXPress`, `AXIncrement`, ...) in
+    /// `ElementDetails::actions`. Costs an extra AX call per event.
+    pub include_actions: bool,
+    /// Remember recently seen elements so clients can ask for a full attribute dump with
+    /// `{"query":"describe","element_key":"..."}`.
+    pub enable_describe: bool,
+    /// Minimum time between two `ValueChanged` events of the same progress indicator. The
+    /// update reaching 100% is always sent. Each event carries `"progress"` (0..1).
+    pub progress_throttle: Duration,
+    /// Emit a `ContextSample` with the frontmost app and window title at this interval,
+    /// even when nothing changes. `None` (default) disables sampling.
+    pub context_sample_interval: Option<Duration>,
+    /// Record failed accessibility attribute reads (attribute name and AX error) in each
+    /// event's `diagnostics` field and log them at debug level.
+    pub diagnostics: bool,
+    /// Raw AX notification names (e.g. `AXLayoutChanged`) to observe in addition to the
+    /// built-in ones, reported as `EventType::Custom` with the usual element data.
+    pub extra_notifications: Vec<String>,
+    /// Wall-clock budget for element tree walks done while building a single event (e.g. the
+    /// sheet summary). A walk that runs out stops early and marks its result
+    /// `truncated: true`, so one huge subtree cannot stall event delivery.
+    pub traversal_budget: Duration,
+    /// Add the selected keyboard input source (e.g. `com.apple.keylayout.US`, or an input
+    /// method) to focus, value and text selection events as `input_source`.
+    pub include_input_source: bool,
+    /// Read the placeholder text (`AXPlaceholderValue`) of text fields when they get focus.
+    pub include_placeholder: bool,
+    /// Group element events into interaction sessions and tag them with `interaction_id`. A
+    /// new session starts when focus moves to a different element or window, or when no
+    /// element event arrived for this long. `None` (default) disables tagging.
+    pub interaction_gap: Option<Duration>,
+    /// Probe the frontmost app every second and report `ApplicationUnresponsive` when a
+    /// simple AX read takes longer than this (and `ApplicationResponsive` once it answers
+    /// again). Idle apps answer immediately, so only hung apps trip it. `None` (default)
+    /// disables the watchdog.
+    pub hang_timeout: Option<Duration>,
+    /// When a menu's owner (menu bar item, pop-up button) cannot be read, an element focused
+    /// at most this long before `MenuOpened` is reported as its trigger.
+    pub menu_trigger_window: Duration,
+    /// Print a one-line summary of each event to stdout. Turn off when stdout carries the
+    /// events themselves (NDJSON output).
+    pub print_events: bool,
+    /// Collapse `ValueChanged` events of one element arriving within this window (e.g. while
+    /// typing) into the latest one. Other events are never delayed; a held value is sent
+    /// before them. `Duration::ZERO` sends every value change.
+    pub value_debounce: Duration,
+    /// Emit raw input events through an event tap: `KeyPressed` for every key down
+    /// (keycode, modifiers, and the typed characters with `capture_values`), `MouseClicked`
+    /// for every button press and `MouseMoved` (throttled by `mouse_move_interval`). Needs
+    /// the Input Monitoring permission in addition to Accessibility; the listener fails to
+    /// start without it.
+    /// Keystrokes typed while an app has secure input on (password fields) are not seen.
+    pub capture_input: bool,
+    /// Minimum time between two `MouseMoved` events (default 1/60 s); moves inside the
+    /// window are dropped.
+    pub mouse_move_interval: Duration,
+    /// Once events could be queued again after the channel was full, emit `EventsDropped`
+    /// with how many were lost since the last report (`dropped`) and in total (`total`, see
+    /// `platform::dropped_events`), plus those dropped by the rate limit since the last
+    /// report (`rate_limited`).
+    pub report_dropped_events: bool,
+    /// Read element values (`ElementDetails::value`), i.e. whatever the user typed or
+    /// selected. On by default for compatibility; turn it off when events may leave the
+    /// machine or reach untrusted consumers, since values carry user content. Value-derived
+    /// data (stepper direction, progress) and the `characters` of `KeyPressed` are then
+    /// missing too.
+    ///
+    /// Password fields are never read, whatever this says: their value is always `None`,
+    /// also in `describe` replies.
+    pub capture_values: bool,
+    /// Only capture these applications, by bundle id (`com.apple.Safari`) or pid. Other apps
+    /// get no observer and produce no events. Empty (default) captures every app.
+    pub only_apps: Vec<String>,
+    /// Never capture these applications (bundle ids or pids); wins over `only_apps`.
+    pub ignore_apps: Vec<String>,
+    /// Like `only_apps`, for applications whose bundle id or path (bundle, or executable
+    /// without one) matches one of these patterns, e.g. `^com\.apple\.`. An app passes if it
+    /// matches either list.
+    #[cfg(feature = "regex")]
+    pub only_app_patterns: Vec<regex::Regex>,
+    /// Like `ignore_apps`, by pattern, e.g. `^/System/Library/CoreServices/`.
+    #[cfg(feature = "regex")]
+    pub ignore_app_patterns: Vec<regex::Regex>,
+    /// Observe every running application (and apps launched later) instead of only the
+    /// frontmost one, so background windows and value changes are seen too. Costs one
+    /// observer per app and more events; off by default.
+    pub watch_all_apps: bool,
+    /// Show the system's accessibility permission dialog when the permission is missing at
+    /// startup (the listener fails to start either way). Turn it off to drive onboarding
+    /// yourself with `platform::accessibility_granted` / `request_accessibility`.
+    pub prompt_for_accessibility: bool,
+    /// Only emit these event types, by name (see [`EventType::NAMES`]; `Custom` selects every
+    /// custom notification, `Custom:<name>` a single one). Notifications that can only produce
+    /// excluded types are not observed at all. `ListenerReady`, `EventsDropped` and
+    /// `Heartbeat` are always emitted. Empty (default) emits every type.
+    pub event_types: Vec<String>,
+    /// Drop an `ElementFocused` or `WindowFocused` that repeats the event just before it
+    /// (same type, app, window and element) within this window, as sent when focus bounces
+    /// within one element. `None` (default) sends every focus event.
+    pub focus_dedup_window: Option<Duration>,
+    /// Cut string values (`ElementDetails::value`) to this many characters and mark the
+    /// event with `"truncated": true` in `event_specific_data`. Text fields and areas report
+    /// their whole text otherwise, however long. Values are cut before value diffs are
+    /// computed. `None` (default) keeps them whole.
+    pub max_value_length: Option<usize>,
+    /// Send at most this many events per second, averaged over one second (a token bucket,
+    /// so short bursts up to the rate pass). Excess events are dropped right away and
+    /// counted (`platform::rate_limited_events`, and `rate_limited` of `EventsDropped` with
+    /// `report_dropped_events`), instead of piling up until the channel is full and arbitrary
+    /// events get lost. `ListenerReady`, `EventsDropped` and `Heartbeat` are never limited.
+    /// `None` (default) sends everything.
+    pub max_events_per_sec: Option<u32>,
+    /// Per event type caps, applied like `max_events_per_sec` and before it, e.g.
+    /// `("MouseMoved", 20)`. Names match like `event_types`.
+    pub event_type_rates: Vec<(String, u32)>,
+    /// Element attributes read for each event. Each is a separate accessibility round trip
+    /// to the app, so leaving out what a consumer does not use makes capture cheaper; left
+    /// out fields are `None`. Features built on an attribute (value diffs and
+    /// `value_on_commit` on values, `include_placeholder` on roles) see it missing too. The
+    /// role is still read internally when values are, to keep secure text fields out.
+    /// Default: [`AttributeMask::DEFAULT`].
+    pub attributes: AttributeMask,
+    /// Emit `Heartbeat` whenever no event was sent for this long, so consumers can tell an
+    /// idle desktop from a dead listener (see [`DEFAULT_HEARTBEAT_INTERVAL`] for a typical
+    /// value). Not subject to `event_types` or the rate limits. `None` (default) sends none.
+    pub heartbeat_interval: Option<Duration>,
+}
+
+/// Suggested `ListenerConfig::heartbeat_interval`.
+pub const DEFAULT_HEARTBEAT_INTERVAL: Duration = Duration::from_secs(15);
+
+impl Default for ListenerConfig {
+    fn default() -> Self {
+        Self {
+            track_caret: false,
+            caret_throttle: Duration::from_millis(50),
+            value_on_commit: false,
+            hash_values: false,
+            hash_salt: None,
+            coordinates_in_pixels: false,
+            merge_focus_value: false,
+            merge_window: Duration::from_millis(150),
+            include_actions: false,
+            enable_describe: false,
+            progress_throttle: Duration::from_secs(1),
+            context_sample_interval: None,
+            diagnostics: false,
+            extra_notifications: Vec::new(),
+            traversal_budget: Duration::from_millis(10),
+            include_input_source: false,
+            include_placeholder: false,
+            interaction_gap: None,
+            hang_timeout: None,
+            menu_trigger_window: Duration::from_millis(500),
+            print_events: true,
+            value_debounce: Duration::from_millis(150),
+            capture_input: false,
+            mouse_move_interval: Duration::from_micros(16_667),
+            report_dropped_events: false,
+            capture_values: true,
+            only_apps: Vec::new(),
+            ignore_apps: Vec::new(),
+            #[cfg(feature = "regex")]
+            only_app_patterns: Vec::new(),
+            #[cfg(feature = "regex")]
+            ignore_app_patterns: Vec::new(),
+            watch_all_apps: false,
+            prompt_for_accessibility: true,
+            event_types: Vec::new(),
+            focus_dedup_window: None,
+            max_value_length: None,
+            max_events_per_sec: None,
+            event_type_rates: Vec::new(),
+            attributes: AttributeMask::DEFAULT,
+            heartbeat_interval: None,
+        }
+    }
+}
+
+impl ListenerConfig {
+    /// Whether events of `app` pass `only_apps` and `ignore_apps` (and their `_patterns`
+    /// counterparts).
+    pub fn captures_app(&self, app: &ApplicationInfo) -> bool {
+        let pid = app.pid.map(|pid| pid.to_string());
+        let matches =
+            |entry: &String| app.bundle_id.as_ref() == Some(entry) || pid.as_ref() == Some(entry);
+        #[cfg(feature = "regex")]
+        let matches_pattern = |pattern: &regex::Regex| {
+            [app.bundle_id.as_deref(), app.path.as_deref()]
+                .into_iter()
+                .flatten()
+                .any(|name| pattern.is_match(name))
+        };
+
+        let ignored = self.ignore_apps.iter().any(matches);
+        #[cfg(feature = "regex")]
+        let ignored = ignored || self.ignore_app_patterns.iter().any(matches_pattern);
+        if ignored {
+            return false;
+        }
+
+        let unrestricted = self.only_apps.is_empty();
+        let selected = self.only_apps.iter().any(matches);
+        #[cfg(feature = "regex")]
+        let (unrestricted, selected) = (
+            unrestricted && self.only_app_patterns.is_empty(),
+            selected || self.only_app_patterns.iter().any(matches_pattern),
+        );
+        unrestricted || selected
+    }
+
+    /// Whether events of `event_type` pass `event_types`.
+    pub fn captures_event_type(&self, event_type: &EventType) -> bool {
+        if self.event_types.is_empty()
+            || matches!(
+                event_type,
+                EventType::ListenerReady | EventType::EventsDropped | EventType::Heartbeat
+            )
+        {
+            return true;
+        }
+        self.event_types
+            .iter()
+            .any(|entry| event_type_matches(entry, event_type))
+    }
+}
+
+// Whether an `event_types`/`event_type_rates` entry names `event_type`: its exact name, or
+// its variant for `Custom:<name>`
+pub(crate) fn event_type_matches(entry: &str, event_type: &EventType) -> bool {
+    let name = event_type.to_string();
+    let variant = name.split(':').next().unwrap_or(&name);
+    entry == name || entry == variant
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn app(bundle_id: &str, pid: i32) -> ApplicationInfo {
+        ApplicationInfo {
+            bundle_id: Some(bundle_id.to_owned()),
+            pid: Some(pid),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn every_app_is_captured_without_filters() {
+        assert!(ListenerConfig::default().captures_app(&app("com.apple.Safari", 42)));
+        assert!(ListenerConfig::default().captures_app(&ApplicationInfo::default()));
+    }
+
+    #[test]
+    fn only_apps_selects_by_bundle_id_or_pid() {
+        let config = ListenerConfig {
+            only_apps: vec!["com.apple.Safari".to_owned(), "7".to_owned()],
+            ..Default::default()
+        };
+        assert!(config.captures_app(&app("com.apple.Safari", 42)));
+        assert!(config.captures_app(&app("com.apple.Notes", 7)));
+        assert!(!config.captures_app(&app("com.apple.Notes", 42)));
+    }
+
+    #[test]
+    fn ignore_apps_wins_over_only_apps() {
+        let config = ListenerConfig {
+            only_apps: vec!["com.apple.Safari".to_owned()],
+            ignore_apps: vec!["42".to_owned()],
+            ..Default::default()
+        };
+        assert!(!config.captures_app(&app("com.apple.Safari", 42)));
+        assert!(config.captures_app(&app("com.apple.Safari", 43)));
+    }
+
+    #[cfg(feature = "regex")]
+    #[test]
+    fn app_patterns_match_bundle_id_or_path() {
+        let config = ListenerConfig {
+            only_app_patterns: vec![regex::Regex::new(r"^com\.apple\.").unwrap()],
+            ignore_app_patterns: vec![regex::Regex::new("^/System/").unwrap()],
+            ..Default::default()
+        };
+        assert!(config.captures_app(&app("com.apple.Safari", 42)));
+        assert!(!config.captures_app(&app("org.mozilla.firefox", 42)));
+
+        let system_app = ApplicationInfo {
+            path: Some("/System/Library/CoreServices/Finder.app".to_owned()),
+            ..app("com.apple.finder", 42)
+        };
+        assert!(!config.captures_app(&system_app));
+    }
+}
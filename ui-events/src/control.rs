@@ -0,0 +1,113 @@
+// Requests from websocket clients that need an answer from the platform listener
+
+use serde::Deserialize;
+use tokio::sync::oneshot;
+
+/// Queries a client can send as a JSON text message, e.g.
+/// `{"query":"describe","element_key":"1234:5f3a9c"}`.
+///
+/// The server answers on the same connection with
+/// `{"query":"describe","element_key":"...","result":{...}}`, or with an `"error"` field
+/// instead of `"result"` if the query could not be answered.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "query", rename_all = "snake_case")]
+pub enum ClientQuery {
+    /// Full attribute and action dump of an element seen in a previous event
+    /// (`ElementDetails::element_key`). Only answered while the element still exists and was
+    /// seen recently; requires `ListenerConfig::enable_describe`.
+    Describe { element_key: String },
+}
+
+/// Limits which events a client receives, sent as a JSON text message, e.g.
+/// `{"subscribe":["WindowFocused","ApplicationActivated"]}`.
+///
+/// Names are `EventType` variant names; `Custom` selects every custom notification and
+/// `Custom:<notification>` a single one. A later message replaces the filter and an empty
+/// list removes it. The server confirms with `{"subscribed":[...]}`. Without a filter a
+/// client receives every event.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Subscribe {
+    pub subscribe: Vec<String>,
+}
+
+/// First message of a client when the server requires a token
+/// (`ServerConfig::auth_token`), e.g. `{"token":"s3cret"}`. Not needed when the client sent
+/// `Authorization: Bearer <token>` with the websocket handshake. The server confirms with
+/// `{"authenticated":true}`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Authenticate {
+    pub token: String,
+}
+
+/// Encoding of the events a client receives. JSON text frames by default; a client switches
+/// with `{"format":"msgpack"}` (or by connecting with `?format=msgpack`) and then receives
+/// each `UiEvent` as a MessagePack map in a binary frame. The server confirms with
+/// `{"format":"msgpack"}`, sent as text like all other replies. MessagePack needs the
+/// `msgpack` feature; without it the server answers with an `"error"` field and keeps JSON.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WireFormat {
+    #[default]
+    Json,
+    Msgpack,
+}
+
+impl WireFormat {
+    /// Parses the value of the `format` query parameter.
+    pub fn from_query(query: &str) -> Option<Self> {
+        query
+            .split('&')
+            .find_map(|pair| match pair.split_once('=') {
+                Some(("format", "json")) => Some(WireFormat::Json),
+                Some(("format", "msgpack")) => Some(WireFormat::Msgpack),
+                _ => None,
+            })
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            WireFormat::Json => "json",
+            WireFormat::Msgpack => "msgpack",
+        }
+    }
+}
+
+/// Selects the `WireFormat` for the rest of the connection, e.g. `{"format":"msgpack"}`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct SetFormat {
+    pub format: WireFormat,
+}
+
+/// Commands about the server itself, e.g. `{"cmd":"stats"}`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "cmd", rename_all = "snake_case")]
+pub enum ServerCommand {
+    /// Lightweight health check, answered with
+    /// `{"cmd":"stats","clients":2,"uptime_secs":360,"source_running":true}`: connected
+    /// websocket clients (including the asking one), seconds since the server started, and
+    /// whether the listener is still sending events.
+    Stats,
+    /// Versions, answered with `{"cmd":"version","crate_version":"0.1.0","schema_version":1}`
+    /// (see `event::SCHEMA_VERSION`), so clients can detect servers they cannot parse.
+    Version,
+    /// Process-wide counters (see `metrics::Metrics`), answered with `{"cmd":"metrics",...}`
+    /// and the fields of `Metrics`.
+    Metrics,
+}
+
+/// Any text message a client can send.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+pub enum ClientMessage {
+    Query(ClientQuery),
+    Subscribe(Subscribe),
+    SetFormat(SetFormat),
+    Command(ServerCommand),
+}
+
+/// A query forwarded from the server to the listener, with the channel to answer on.
+#[derive(Debug)]
+pub struct ControlRequest {
+    pub query: ClientQuery,
+    pub reply: oneshot::Sender<Result<serde_json::Value, String>>,
+}
@@ -1,3 +1,71 @@
-// Placeholder for error handling
+// Errors of the public API that callers may want to tell apart
 
-// We can just use anyhow::Error for now, or define custom errors later.
+use std::fmt;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+
+/// Why the listener or server could not start.
+///
+/// Converts from `anyhow::Error` (as [`UiEventsError::Other`]) and into it, so it works with
+/// `?` on either side.
+#[derive(Debug)]
+pub enum UiEventsError {
+    /// The user has not granted this process the Accessibility permission.
+    AccessibilityDenied,
+    /// Input capture was requested without the Input Monitoring permission.
+    InputMonitoringDenied,
+    /// The websocket server could not listen on its address (in use, not permitted, ...).
+    Bind {
+        addr: SocketAddr,
+        source: std::io::Error,
+    },
+    /// The websocket server could not listen on its Unix domain socket.
+    BindSocket {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    /// No listener is available for this operating system.
+    UnsupportedPlatform,
+    /// Anything else, e.g. an unreadable TLS certificate.
+    Other(anyhow::Error),
+}
+
+impl fmt::Display for UiEventsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            UiEventsError::AccessibilityDenied => {
+                write!(f, "accessibility permissions not granted by user")
+            }
+            UiEventsError::InputMonitoringDenied => {
+                write!(f, "input monitoring permission not granted by user")
+            }
+            UiEventsError::Bind { addr, .. } => {
+                write!(f, "failed to bind websocket server to {}", addr)
+            }
+            UiEventsError::BindSocket { path, .. } => {
+                write!(f, "failed to bind websocket server to {}", path.display())
+            }
+            UiEventsError::UnsupportedPlatform => {
+                write!(f, "no listener for {}", std::env::consts::OS)
+            }
+            UiEventsError::Other(e) => write!(f, "{:#}", e),
+        }
+    }
+}
+
+impl std::error::Error for UiEventsError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            UiEventsError::Bind { source, .. } | UiEventsError::BindSocket { source, .. } => {
+                Some(source)
+            }
+            _ => None,
+        }
+    }
+}
+
+impl From<anyhow::Error> for UiEventsError {
+    fn from(e: anyhow::Error) -> Self {
+        UiEventsError::Other(e)
+    }
+}
@@ -1,5 +1,30 @@
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::sync::atomic::{AtomicI64, Ordering};
+
+/// Version of the event wire format, sent with every event as `UiEvent::schema_version`.
+///
+/// Bumped whenever a change could break an existing client: a field removed, renamed or
+/// retyped, or a variant's meaning changed. Additive changes (new optional fields, new
+/// `EventType` variants, new keys in `event_specific_data`) keep the version, so clients
+/// should ignore what they do not know and only reject versions newer than they support.
+pub const SCHEMA_VERSION: u32 = 1;
+
+// Latest timestamp handed out, in nanoseconds since the epoch
+static LAST_TIMESTAMP_NANOS: AtomicI64 = AtomicI64::new(0);
+
+// The current time, strictly later than any timestamp returned before in this process, so
+// events created back to back never tie (the clock may have microsecond granularity)
+fn next_timestamp() -> DateTime<Utc> {
+    let now = Utc::now().timestamp_nanos_opt().unwrap_or(i64::MAX);
+    let previous = LAST_TIMESTAMP_NANOS
+        .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |last| {
+            Some(now.max(last + 1))
+        })
+        .unwrap_or_default();
+    DateTime::from_timestamp_nanos(now.max(previous + 1))
+}
 
 // TODO: Define more specific event types and details based on AXObserver/UIA/AT-SPI capabilities
 
@@ -7,6 +32,8 @@ use serde::{Deserialize, Serialize};
 pub enum EventType {
     ApplicationActivated,
     ApplicationDeactivated,
+    ApplicationHidden,
+    ApplicationUnhidden,
     WindowFocused,
     WindowCreated,
     WindowMoved,
@@ -18,55 +45,453 @@ pub enum EventType {
     MenuOpened,
     MenuClosed,
     MenuItemSelected,
-    SelectionChanged,
+    SelectionChanged, // Selected items of a list, table or outline changed
     SelectedTextChanged,
     TitleChanged,
+    PageLoaded,   // Web area finished loading (AXLoadComplete)
+    CaretMoved,   // Opt-in, see ListenerConfig::track_caret
+    SpaceChanged, // Active Space (virtual desktop) switched
+    SheetOpened,  // Modal sheet attached to a window; window is the parent
+    SheetClosed,
+    ObservationFailed,       // Could not attach an AX observer to the application
+    ItemReordered,           // Heuristic: a list/table row moved from old_index to new_index
+    SourceStopped,           // Sent by the server: the listener stopped, no more events will follow
+    ContextSample,           // Periodic frontmost app + window snapshot (opt-in)
+    ListenerReady,           // Capture is live; first event, also replayed to each new client
+    ApplicationUnresponsive, // Frontmost app stopped answering AX reads (opt-in watchdog)
+    ApplicationResponsive,   // A previously unresponsive app answers again
+    KeyPressed,              // Key down seen by the input event tap (opt-in capture_input)
+    MouseClicked,            // Mouse button down (opt-in capture_input)
+    MouseMoved,              // Pointer moved or dragged, throttled (opt-in capture_input)
+    EventsDropped,           // Events were lost to a full channel since the last report (opt-in)
+    Heartbeat,               // Listener alive, nothing sent for heartbeat_interval (opt-in)
+    // Any observed notification without a dedicated variant (e.g. from
+    // ListenerConfig::extra_notifications), by its raw name
+    Custom { name: String },
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+impl EventType {
+    /// Variant names as printed by `Display` (`Custom` without its notification name), for
+    /// selecting event types by name, e.g. `ListenerConfig::event_types`.
+    pub const NAMES: &[&str] = &[
+        "ApplicationActivated",
+        "ApplicationDeactivated",
+        "ApplicationHidden",
+        "ApplicationUnhidden",
+        "WindowFocused",
+        "WindowCreated",
+        "WindowMoved",
+        "WindowResized",
+        "ElementFocused",
+        "ValueChanged",
+        "ElementDestroyed",
+        "MenuOpened",
+        "MenuClosed",
+        "MenuItemSelected",
+        "SelectionChanged",
+        "SelectedTextChanged",
+        "TitleChanged",
+        "PageLoaded",
+        "CaretMoved",
+        "SpaceChanged",
+        "SheetOpened",
+        "SheetClosed",
+        "ObservationFailed",
+        "ItemReordered",
+        "SourceStopped",
+        "ContextSample",
+        "ListenerReady",
+        "ApplicationUnresponsive",
+        "ApplicationResponsive",
+        "KeyPressed",
+        "MouseClicked",
+        "MouseMoved",
+        "EventsDropped",
+        "Heartbeat",
+        "Custom",
+    ];
+}
+
+impl fmt::Display for EventType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            // Raw notification name, e.g. `Custom:AXLayoutChanged`
+            EventType::Custom { name } => write!(f, "Custom:{}", name),
+            // Variant name, e.g. `WindowFocused`
+            _ => fmt::Debug::fmt(self, f),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct ApplicationInfo {
     pub name: Option<String>,
     pub pid: Option<i32>, // Or appropriate type
-                          // pub path: Option<String>,
+    /// Bundle identifier, e.g. `com.apple.Safari`
+    pub bundle_id: Option<String>,
+    /// Path of the application bundle (or executable for apps without one)
+    pub path: Option<String>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct WindowInfo {
     pub title: Option<String>,
     pub id: Option<String>, // Platform-specific ID
-                            // pub position: Option<Position>,
-                            // pub size: Option<Size>,
+    /// Window frame, same units as `ElementDetails::position`/`size`. `None` when the window
+    /// does not report it.
+    pub position: Option<Position>,
+    pub size: Option<Size>,
+    /// macOS Space the window is on. Only set for window events, using the active Space at
+    /// the time of the event; public APIs do not expose Space ids, so this relies on a private
+    /// SkyLight call and may be `None` or change meaning across OS releases.
+    pub space_id: Option<u64>,
+    /// Backing scale factor of the screen the window is on (2.0 on Retina).
+    pub scale_factor: Option<f64>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct ElementDetails {
-    pub role: Option<String>,             // Standardized role if possible
+    /// Opaque key identifying the element while it exists (`<pid>:<hash>`); can be passed
+    /// back to the server in a `describe` query.
+    pub element_key: Option<String>,
+    pub role: Option<String>, // Raw platform role, e.g. `AXButton`
+    /// `role` mapped to a platform-independent role.
+    pub normalized_role: Option<NormalizedRole>,
     pub identifier: Option<String>,       // Accessibility Label/Name
     pub value: Option<serde_json::Value>, // Current value (flexible type)
+    // Screen coordinates in points (top-left origin) unless the listener was configured
+    // with `coordinates_in_pixels`
     pub position: Option<Position>,
     pub size: Option<Size>,
+    /// Backing scale factor of the element's screen; multiply points by it to get pixels.
+    pub scale_factor: Option<f64>,
+    /// Actions the element supports (e.g. `AXPress`). Only filled when the listener is
+    /// configured with `include_actions`.
+    pub actions: Option<Vec<String>>,
+    /// Placeholder text of a focused text field. Only filled when the listener is configured
+    /// with `include_placeholder` and the field has one.
+    pub placeholder: Option<String>,
+    /// Ancestry from the element's window down to the element, one `role[label]` segment per
+    /// level (label being the title or description, `role` alone without one), e.g.
+    /// `["AXWindow[Untitled]", "AXGroup", "AXButton[Save]"]`. Capped at 20 levels, so very
+    /// deep elements start below the window.
+    pub path: Option<Vec<String>>,
+    /// Whether the element accepts input. Like `focused` and `children_count`, only filled
+    /// when `ListenerConfig::attributes` asks for it.
+    pub enabled: Option<bool>,
+    /// Whether the element has keyboard focus.
+    pub focused: Option<bool>,
+    /// Number of direct children in the accessibility tree.
+    pub children_count: Option<usize>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+/// Role of an element independent of the platform's accessibility API, for branching on
+/// element kinds without knowing each platform's role names. Roles without a counterpart
+/// here are kept as `Unknown` with the raw name.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum NormalizedRole {
+    Application,
+    Window,
+    Sheet,
+    Dialog,
+    Button,
+    CheckBox,
+    RadioButton,
+    TextField,
+    TextArea,
+    ComboBox,
+    PopUpButton,
+    Slider,
+    Stepper,
+    ProgressIndicator,
+    Link,
+    Image,
+    StaticText,
+    MenuBar,
+    MenuBarItem,
+    Menu,
+    MenuItem,
+    List,
+    Table,
+    Outline,
+    Row,
+    Cell,
+    TabGroup,
+    ScrollArea,
+    Toolbar,
+    Group,
+    WebArea,
+    Unknown(String),
+}
+
+impl NormalizedRole {
+    /// The role for a macOS accessibility role name (`AXButton`, ...).
+    pub fn from_ax_role(role: &str) -> Self {
+        match role {
+            "AXApplication" => Self::Application,
+            "AXWindow" => Self::Window,
+            "AXSheet" => Self::Sheet,
+            "AXSystemDialog" => Self::Dialog,
+            "AXButton" | "AXMenuButton" | "AXDisclosureTriangle" => Self::Button,
+            "AXCheckBox" | "AXSwitch" => Self::CheckBox,
+            "AXRadioButton" => Self::RadioButton,
+            "AXTextField" | "AXSearchField" | "AXSecureTextField" => Self::TextField,
+            "AXTextArea" => Self::TextArea,
+            "AXComboBox" => Self::ComboBox,
+            "AXPopUpButton" => Self::PopUpButton,
+            "AXSlider" => Self::Slider,
+            "AXIncrementor" => Self::Stepper,
+            "AXProgressIndicator" | "AXBusyIndicator" => Self::ProgressIndicator,
+            "AXLink" => Self::Link,
+            "AXImage" => Self::Image,
+            "AXStaticText" | "AXHeading" => Self::StaticText,
+            "AXMenuBar" => Self::MenuBar,
+            "AXMenuBarItem" => Self::MenuBarItem,
+            "AXMenu" => Self::Menu,
+            "AXMenuItem" => Self::MenuItem,
+            "AXList" => Self::List,
+            "AXTable" | "AXGrid" => Self::Table,
+            "AXOutline" | "AXBrowser" => Self::Outline,
+            "AXRow" => Self::Row,
+            "AXCell" => Self::Cell,
+            "AXTabGroup" => Self::TabGroup,
+            "AXScrollArea" => Self::ScrollArea,
+            "AXToolbar" => Self::Toolbar,
+            "AXGroup" | "AXSplitGroup" | "AXRadioGroup" => Self::Group,
+            "AXWebArea" => Self::WebArea,
+            other => Self::Unknown(other.to_string()),
+        }
+    }
+}
+
+/// A display (monitor). The frame is in points in the global desktop space (top-left origin
+/// at the primary display), whatever `coordinates_in_pixels` says.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct DisplayInfo {
+    /// Platform display id (`CGDirectDisplayID` on macOS); changes when displays are
+    /// reconnected.
+    pub id: u32,
+    pub position: Position,
+    pub size: Size,
+    /// Backing scale factor (2.0 on Retina).
+    pub scale_factor: f64,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct Position {
     pub x: f64,
     pub y: f64,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct Size {
     pub width: f64,
     pub height: f64,
 }
 
+impl Position {
+    pub fn scaled(self, factor: f64) -> Self {
+        Self {
+            x: self.x * factor,
+            y: self.y * factor,
+        }
+    }
+}
+
+impl Size {
+    pub fn scaled(self, factor: f64) -> Self {
+        Self {
+            width: self.width * factor,
+            height: self.height * factor,
+        }
+    }
+}
+
+/// One event as sent over the websocket. Clients can deserialize messages straight into it:
+///
+/// ```
+/// use ui_events::{EventType, UiEvent};
+///
+/// let json = r#"{
+///     "event_type": "WindowFocused",
+///     "timestamp": 1700000000000,
+///     "application": { "name": "Safari", "pid": 123 },
+///     "window": { "title": "Example Page", "id": null },
+///     "element": null,
+///     "event_specific_data": null,
+///     "seq": 7
+/// }"#;
+/// let event: UiEvent = serde_json::from_str(json).unwrap();
+/// assert!(matches!(event.event_type, EventType::WindowFocused));
+/// assert_eq!(event.seq, 7);
+/// assert_eq!(event.to_string(), r#"[WindowFocused] Safari — "Example Page""#);
+///
+/// let round_trip: UiEvent = serde_json::from_str(&serde_json::to_string(&event).unwrap()).unwrap();
+/// assert_eq!(round_trip.timestamp, event.timestamp);
+/// ```
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct UiEvent {
+    /// [`SCHEMA_VERSION`] of the sender; `0` from servers predating it.
+    #[serde(default)]
+    pub schema_version: u32,
     pub event_type: EventType,
     #[serde(with = "chrono::serde::ts_milliseconds")]
     pub timestamp: DateTime<Utc>,
+    /// `timestamp` in nanoseconds since the Unix epoch, for ordering and latency analysis
+    /// below a millisecond. Unique and increasing across the events of one listener process.
+    #[serde(default)]
+    pub timestamp_nanos: Option<i64>,
     pub application: Option<ApplicationInfo>,
     pub window: Option<WindowInfo>,
     pub element: Option<ElementDetails>,
     // Specific data not fitting above, use sparingly
     pub event_specific_data: Option<serde_json::Value>,
+    /// Position in the stream, assigned when the event leaves the listener channel (starts at
+    /// 1 and counts every event regardless of type for the lifetime of the server or sink).
+    /// Every connection receives events in strictly increasing `seq` order, also across
+    /// reconnects, so a client can tell what it missed: gaps mean events were skipped for that
+    /// client (lagging, disconnected, or filtered out by its subscription). `0` means not
+    /// sent yet.
+    #[serde(default)]
+    pub seq: u64,
+    /// Attribute reads that failed while building this event. Only filled when the listener
+    /// runs with `diagnostics` on; explains gaps such as a missing title or value.
+    #[serde(default)]
+    pub diagnostics: Option<Vec<AttributeError>>,
+    /// Interaction session the event belongs to (focus, typing, ... on one element until
+    /// focus moves or the listener's `interaction_gap` passes). Increases per session; only
+    /// set on element events and when the listener is configured with `interaction_gap`.
+    #[serde(default)]
+    pub interaction_id: Option<u64>,
+    /// Display the event happened on: the one containing the element's top-left corner (or
+    /// the window's when there is no element position). `None` when neither has a position.
+    #[serde(default)]
+    pub display: Option<DisplayInfo>,
+}
+
+/// A failed accessibility attribute read, e.g. `AXTitle` with `kAXErrorAttributeUnsupported`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AttributeError {
+    pub attribute: String,
+    pub error: String,
+}
+
+impl UiEvent {
+    /// Creates an event of the given type stamped with the current time and no context.
+    pub fn new(event_type: EventType) -> Self {
+        let timestamp = next_timestamp();
+        Self {
+            schema_version: SCHEMA_VERSION,
+            event_type,
+            timestamp,
+            timestamp_nanos: timestamp.timestamp_nanos_opt(),
+            application: None,
+            window: None,
+            element: None,
+            event_specific_data: None,
+            seq: 0,
+            diagnostics: None,
+            interaction_id: None,
+            display: None,
+        }
+    }
+
+    /// Sets `timestamp` and `timestamp_nanos` to now, e.g. for an event held back and sent
+    /// later.
+    pub fn restamp(&mut self) {
+        self.timestamp = next_timestamp();
+        self.timestamp_nanos = self.timestamp.timestamp_nanos_opt();
+    }
+}
+
+/// One-line summary for logs and stdout, e.g. `[WindowFocused] Safari — "Example Page"`.
+///
+/// The format is `[<EventType>]`, followed by the application name (if known), followed by
+/// ` — "<window title>"` (if known). Missing parts are omitted.
+impl fmt::Display for UiEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "[{}]", self.event_type)?;
+        if let Some(name) = self.application.as_ref().and_then(|a| a.name.as_deref()) {
+            write!(f, " {}", name)?;
+        }
+        if let Some(title) = self.window.as_ref().and_then(|w| w.title.as_deref()) {
+            write!(f, " — {:?}", title)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn back_to_back_events_have_distinct_timestamps() {
+        let mut last = UiEvent::new(EventType::ValueChanged)
+            .timestamp_nanos
+            .unwrap();
+        for _ in 0..1000 {
+            let nanos = UiEvent::new(EventType::ValueChanged)
+                .timestamp_nanos
+                .unwrap();
+            assert!(nanos > last, "{} after {}", nanos, last);
+            last = nanos;
+        }
+    }
+
+    #[test]
+    fn heartbeat_survives_serde() {
+        let json = serde_json::to_value(UiEvent::new(EventType::Heartbeat)).unwrap();
+        assert_eq!(json["event_type"], "Heartbeat");
+        let event: UiEvent = serde_json::from_value(json).unwrap();
+        assert!(matches!(event.event_type, EventType::Heartbeat));
+    }
+
+    #[test]
+    fn element_state_survives_serde() {
+        let event = UiEvent {
+            element: Some(ElementDetails {
+                enabled: Some(false),
+                focused: Some(true),
+                children_count: Some(3),
+                ..Default::default()
+            }),
+            ..UiEvent::new(EventType::ElementFocused)
+        };
+        let json = serde_json::to_value(&event).unwrap();
+        assert_eq!(json["element"]["enabled"], false);
+        assert_eq!(json["element"]["focused"], true);
+        assert_eq!(json["element"]["children_count"], 3);
+
+        let element = serde_json::from_value::<UiEvent>(json)
+            .unwrap()
+            .element
+            .unwrap();
+        assert_eq!(element.enabled, Some(false));
+        assert_eq!(element.focused, Some(true));
+        assert_eq!(element.children_count, Some(3));
+    }
+
+    #[test]
+    fn ax_roles_map_to_normalized_roles() {
+        for (role, expected) in [
+            ("AXButton", NormalizedRole::Button),
+            ("AXMenuButton", NormalizedRole::Button),
+            ("AXSecureTextField", NormalizedRole::TextField),
+            ("AXIncrementor", NormalizedRole::Stepper),
+            ("AXBusyIndicator", NormalizedRole::ProgressIndicator),
+            ("AXGrid", NormalizedRole::Table),
+            ("AXWebArea", NormalizedRole::WebArea),
+        ] {
+            assert_eq!(NormalizedRole::from_ax_role(role), expected, "{}", role);
+        }
+    }
+
+    #[test]
+    fn unknown_ax_roles_keep_their_name() {
+        assert_eq!(
+            NormalizedRole::from_ax_role("AXLevelIndicator"),
+            NormalizedRole::Unknown("AXLevelIndicator".to_string())
+        );
+    }
 }
@@ -1,32 +1,122 @@
+pub mod client;
+pub mod config;
+pub mod control;
 pub mod error;
 pub mod event;
+pub mod metrics;
+#[cfg(feature = "parquet")]
+pub mod parquet;
 pub mod platform;
+#[cfg(feature = "tauri")]
+pub mod plugin;
+pub mod privacy;
 pub mod server;
+pub mod sink;
 
-pub use platform::create_listener;
-pub use server::run_server;
+pub use config::{AttributeMask, ListenerConfig};
+pub use error::UiEventsError;
+pub use event::{
+    ApplicationInfo, DisplayInfo, ElementDetails, EventType, NormalizedRole, Position,
+    SCHEMA_VERSION, Size, UiEvent, WindowInfo,
+};
+pub use metrics::{Metrics, metrics_snapshot};
+pub use platform::{
+    accessibility_granted, create_listener, record_for, record_for_with_config,
+    request_accessibility,
+};
+#[cfg(feature = "tauri")]
+pub use plugin::{tauri_plugin, tauri_plugin_with_config};
+pub use server::{
+    ContentType, EventSerializer, JsonSerializer, LagBehavior, ReplayPace, ServerConfig,
+    SourceClosedBehavior, run_server, run_server_from_file, run_server_with_config,
+};
+#[cfg(unix)]
+pub use server::{run_server_uds, run_server_uds_with_config};
 use tokio::sync::mpsc;
 use tracing::info;
 
-pub fn run(port: u16) {
+pub fn run(port: u16) -> Result<(), UiEventsError> {
+    run_with_config(port, ListenerConfig::default())
+}
+
+pub fn run_with_config(port: u16, config: ListenerConfig) -> Result<(), UiEventsError> {
+    run_with_server_config(port, config, ServerConfig::default())
+}
+
+/// Runs the listener on the current (main) thread and the websocket server next to it,
+/// until the server stops. Fails when either cannot start, e.g. with
+/// [`UiEventsError::Bind`] when the port is taken.
+pub fn run_with_server_config(
+    port: u16,
+    config: ListenerConfig,
+    server_config: ServerConfig,
+) -> Result<(), UiEventsError> {
     let _ = tracing_subscriber::fmt::try_init();
     info!("starting ui-events...");
 
     // Create a channel for communication between listener and server
     let (tx, rx) = mpsc::channel(100); // Buffer size 100
 
+    // Channel for client queries the listener has to answer
+    let (control_tx, control_rx) = if config.enable_describe {
+        let (control_tx, control_rx) = mpsc::channel(16);
+        (Some(control_tx), Some(control_rx))
+    } else {
+        (None, None)
+    };
+    let server_config = ServerConfig {
+        control: control_tx,
+        ..server_config
+    };
+
     let rt = tokio::runtime::Builder::new_multi_thread()
         .enable_all()
         .worker_threads(2)
         .build()
         .unwrap();
 
+    let handle = platform::ListenerHandle::new();
+    let server_handle = handle.clone();
+    let server = rt.spawn(async move {
+        let result = run_server_with_config(port, rx, server_config).await;
+        // Whether it failed to start or is done serving, there is no one to listen for
+        server_handle.stop();
+        result
+    });
+
+    platform::listener_run_stoppable(tx, config, control_rx, &handle)?;
+    // Only the server stops the listener, so it has finished or is about to
+    if !handle.is_stopped() {
+        return Ok(());
+    }
+    match rt.block_on(server) {
+        Ok(result) => result,
+        Err(e) => Err(UiEventsError::Other(e.into())),
+    }
+}
+
+/// Runs the listener without a websocket server, writing every event as a JSON line to
+/// `writer` (see [`sink::run_line_sink`]). Exits once writing fails.
+pub fn run_to_writer(
+    config: ListenerConfig,
+    writer: impl std::io::Write + Send + 'static,
+    omit_null_fields: bool,
+) {
+    let _ = tracing_subscriber::fmt::try_init();
+    info!("starting ui-events...");
+
+    let (tx, rx) = mpsc::channel(100);
+
     use cidre::ns;
 
-    rt.spawn(async move {
-        run_server(port, rx).await.unwrap();
+    std::thread::spawn(move || {
+        if let Err(e) = sink::run_line_sink(rx, writer, omit_null_fields) {
+            tracing::error!(error = %e, "failed to write events");
+        }
         ns::App::shared().terminate(None);
     });
 
-    platform::listener_run(tx);
+    if let Err(e) = platform::listener_run_with_config(tx, config, None) {
+        tracing::error!(error = %e, "failed to run listener");
+    }
 }
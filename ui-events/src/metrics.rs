@@ -0,0 +1,72 @@
+// Process-wide counters for monitoring (e.g. a Prometheus exporter polling `metrics_snapshot`)
+
+use crate::event::EventType;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+
+static EVENTS_EMITTED: AtomicU64 = AtomicU64::new(0);
+static CLIENTS_CONNECTED: AtomicUsize = AtomicUsize::new(0);
+static OBSERVERS_ACTIVE: AtomicUsize = AtomicUsize::new(0);
+// Emitted events by variant name
+static EVENTS_BY_TYPE: Mutex<BTreeMap<String, u64>> = Mutex::new(BTreeMap::new());
+
+/// Counters of the listener and server, for the whole process. Counters only grow; gauges
+/// (`clients_connected`, `observers_active`) go up and down.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Metrics {
+    /// Events the listener queued for consumers.
+    pub events_emitted: u64,
+    /// Events lost to a full or closed channel (`platform::dropped_events`).
+    pub events_dropped: u64,
+    /// Events dropped by the rate limits (`platform::rate_limited_events`).
+    pub events_rate_limited: u64,
+    /// Open websocket and SSE connections, over every running server.
+    pub clients_connected: usize,
+    /// Accessibility observers attached to applications.
+    pub observers_active: usize,
+    /// `events_emitted` by event type (`Custom` covering every custom notification).
+    pub events_by_type: BTreeMap<String, u64>,
+}
+
+/// Current values of every counter.
+pub fn metrics_snapshot() -> Metrics {
+    Metrics {
+        events_emitted: EVENTS_EMITTED.load(Ordering::Relaxed),
+        events_dropped: crate::platform::dropped_events(),
+        events_rate_limited: crate::platform::rate_limited_events(),
+        clients_connected: CLIENTS_CONNECTED.load(Ordering::Relaxed),
+        observers_active: OBSERVERS_ACTIVE.load(Ordering::Relaxed),
+        events_by_type: EVENTS_BY_TYPE.lock().unwrap().clone(),
+    }
+}
+
+pub(crate) fn count_emitted_event(event_type: &EventType) {
+    EVENTS_EMITTED.fetch_add(1, Ordering::Relaxed);
+    let name = event_type.to_string();
+    let variant = name.split(':').next().unwrap_or(&name);
+    *EVENTS_BY_TYPE
+        .lock()
+        .unwrap()
+        .entry(variant.to_owned())
+        .or_default() += 1;
+}
+
+pub(crate) fn client_connected() {
+    CLIENTS_CONNECTED.fetch_add(1, Ordering::Relaxed);
+}
+
+pub(crate) fn client_disconnected() {
+    CLIENTS_CONNECTED.fetch_sub(1, Ordering::Relaxed);
+}
+
+pub(crate) fn observer_attached() {
+    OBSERVERS_ACTIVE.fetch_add(1, Ordering::Relaxed);
+}
+
+// Saturating, in case an observer is detached that was never counted
+pub(crate) fn observer_detached() {
+    let _ =
+        OBSERVERS_ACTIVE.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |n| n.checked_sub(1));
+}
@@ -0,0 +1,138 @@
+// Columnar sink: events as Parquet files for dataframe / SQL tooling (feature "parquet")
+
+use crate::event::UiEvent;
+use anyhow::{Context, Result};
+use arrow::array::{
+    ArrayRef, Float64Array, Int32Array, StringArray, TimestampMillisecondArray, UInt64Array,
+};
+use arrow::datatypes::{DataType, Field, Schema, SchemaRef, TimeUnit};
+use arrow::record_batch::RecordBatch;
+use chrono::Utc;
+use parquet::arrow::ArrowWriter;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::sync::mpsc;
+use tracing::{error, info};
+
+/// Rows per file before rotating to a new one.
+pub const DEFAULT_ROWS_PER_FILE: usize = 10_000;
+
+/// Flattened columns: scalar fields of the event, application, window and element.
+/// `element_value` and `event_specific_data` are untyped and stored as JSON strings.
+pub fn schema() -> SchemaRef {
+    Arc::new(Schema::new(vec![
+        Field::new("seq", DataType::UInt64, false),
+        Field::new("event_type", DataType::Utf8, false),
+        Field::new(
+            "timestamp",
+            DataType::Timestamp(TimeUnit::Millisecond, Some("UTC".into())),
+            false,
+        ),
+        Field::new("app_name", DataType::Utf8, true),
+        Field::new("app_pid", DataType::Int32, true),
+        Field::new("window_title", DataType::Utf8, true),
+        Field::new("element_role", DataType::Utf8, true),
+        Field::new("element_identifier", DataType::Utf8, true),
+        Field::new("element_value", DataType::Utf8, true),
+        Field::new("element_x", DataType::Float64, true),
+        Field::new("element_y", DataType::Float64, true),
+        Field::new("element_width", DataType::Float64, true),
+        Field::new("element_height", DataType::Float64, true),
+        Field::new("event_specific_data", DataType::Utf8, true),
+    ]))
+}
+
+fn to_batch(events: &[UiEvent]) -> Result<RecordBatch> {
+    let strings = |f: &dyn Fn(&UiEvent) -> Option<String>| -> ArrayRef {
+        Arc::new(events.iter().map(f).collect::<StringArray>())
+    };
+    let floats = |f: &dyn Fn(&UiEvent) -> Option<f64>| -> ArrayRef {
+        Arc::new(events.iter().map(f).collect::<Float64Array>())
+    };
+
+    let columns: Vec<ArrayRef> = vec![
+        Arc::new(UInt64Array::from_iter_values(events.iter().map(|e| e.seq))),
+        strings(&|e| Some(e.event_type.to_string())),
+        Arc::new(
+            TimestampMillisecondArray::from_iter_values(
+                events.iter().map(|e| e.timestamp.timestamp_millis()),
+            )
+            .with_timezone("UTC"),
+        ),
+        strings(&|e| e.application.as_ref()?.name.clone()),
+        Arc::new(
+            events
+                .iter()
+                .map(|e| e.application.as_ref()?.pid)
+                .collect::<Int32Array>(),
+        ),
+        strings(&|e| e.window.as_ref()?.title.clone()),
+        strings(&|e| e.element.as_ref()?.role.clone()),
+        strings(&|e| e.element.as_ref()?.identifier.clone()),
+        strings(&|e| Some(e.element.as_ref()?.value.as_ref()?.to_string())),
+        floats(&|e| Some(e.element.as_ref()?.position.as_ref()?.x)),
+        floats(&|e| Some(e.element.as_ref()?.position.as_ref()?.y)),
+        floats(&|e| Some(e.element.as_ref()?.size.as_ref()?.width)),
+        floats(&|e| Some(e.element.as_ref()?.size.as_ref()?.height)),
+        strings(&|e| Some(e.event_specific_data.as_ref()?.to_string())),
+    ];
+
+    RecordBatch::try_new(schema(), columns).context("failed to build record batch")
+}
+
+// `index` counts the files written by this sink, so files started within the same
+// millisecond still get distinct names.
+fn write_file(dir: &Path, index: usize, events: &[UiEvent]) -> Result<PathBuf> {
+    let path = dir.join(format!(
+        "ui-events-{}-{index:06}.parquet",
+        Utc::now().format("%Y%m%dT%H%M%S%.3f")
+    ));
+    let batch = to_batch(events)?;
+    let file =
+        File::create(&path).with_context(|| format!("failed to create {}", path.display()))?;
+    let mut writer = ArrowWriter::try_new(file, batch.schema(), None)?;
+    writer.write(&batch)?;
+    writer.close()?;
+    Ok(path)
+}
+
+/// Writes serialized events (as sent to websocket clients) to Parquet files in `dir`,
+/// starting a new file every `rows_per_file` events. Blocks until `rx` closes, then writes
+/// the remaining events. Meant to be fed through `ServerConfig::reliable_sinks` from its own
+/// thread.
+///
+/// Events still buffered when the process exits without closing the channel are lost.
+pub fn run_parquet_sink(
+    dir: &Path,
+    mut rx: mpsc::Receiver<String>,
+    rows_per_file: usize,
+) -> Result<()> {
+    std::fs::create_dir_all(dir).with_context(|| format!("failed to create {}", dir.display()))?;
+    info!(dir = %dir.display(), "parquet sink started");
+
+    let mut events = Vec::with_capacity(rows_per_file);
+    let mut files = 0;
+    while let Some(json_str) = rx.blocking_recv() {
+        match serde_json::from_str::<UiEvent>(&json_str) {
+            Ok(event) => events.push(event),
+            Err(e) => {
+                error!(error = %e, "failed to parse event for parquet sink");
+                continue;
+            }
+        }
+        if events.len() >= rows_per_file {
+            let path = write_file(dir, files, &events)?;
+            files += 1;
+            info!(path = %path.display(), rows = events.len(), "wrote parquet file");
+            events.clear();
+        }
+    }
+
+    if !events.is_empty() {
+        let path = write_file(dir, files, &events)?;
+        info!(path = %path.display(), rows = events.len(), "wrote parquet file");
+    }
+    info!("parquet sink finished");
+    Ok(())
+}
@@ -0,0 +1,486 @@
+// Last stage of the listener, the same on every platform: app and event type filters, focus
+// dedup, value debounce, focus/value merging, rate limit, privacy transforms and the channel.
+//
+// Events held back (debounce, merging) are sent by one timer thread per listener. It owns a
+// reference to the `Delivery`, so a listener stopping while an event is held cannot leave
+// the timer with a dangling pointer.
+
+use super::{RateLimiter, count_dropped_event, dropped_events};
+use crate::config::ListenerConfig;
+use crate::event::{EventType, UiEvent};
+use crate::metrics;
+use crate::privacy::ValueHasher;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc;
+use tracing::{debug, error};
+
+// What two events must share to count as repeats for ListenerConfig::focus_dedup_window
+#[derive(PartialEq)]
+struct EventIdentity<K> {
+    event_type: String,
+    pid: Option<i32>,
+    // Window id, or its title for windows without one
+    window: Option<String>,
+    element: Option<K>,
+}
+
+impl<K> EventIdentity<K> {
+    fn of(event: &UiEvent, key: Option<K>) -> Self {
+        Self {
+            event_type: event.event_type.to_string(),
+            pid: event.application.as_ref().and_then(|a| a.pid),
+            window: event
+                .window
+                .as_ref()
+                .and_then(|w| w.id.clone().or_else(|| w.title.clone())),
+            element: key,
+        }
+    }
+}
+
+// ListenerConfig::focus_dedup_window: drops a focus event that repeats the event sent just
+// before it
+struct FocusDedup<K> {
+    window: Duration,
+    last_sent: Option<(EventIdentity<K>, Instant)>,
+}
+
+impl<K: PartialEq> FocusDedup<K> {
+    fn new(window: Duration) -> Self {
+        Self {
+            window,
+            last_sent: None,
+        }
+    }
+
+    // Whether `event` is a repeated focus event; every event is remembered as the last one
+    fn is_repeat(&mut self, event: &UiEvent, key: Option<K>, now: Instant) -> bool {
+        let identity = EventIdentity::of(event, key);
+        let repeat = matches!(
+            event.event_type,
+            EventType::ElementFocused | EventType::WindowFocused
+        ) && self.last_sent.as_ref().is_some_and(|(previous, at)| {
+            *previous == identity && now.saturating_duration_since(*at) < self.window
+        });
+        self.last_sent = Some((identity, now));
+        repeat
+    }
+}
+
+// Latest ValueChanged of an element, held for ListenerConfig::value_debounce
+struct PendingValue<K> {
+    key: K,
+    event: UiEvent,
+    // When the first value of the burst was held; the window is not extended by later ones
+    at: Instant,
+}
+
+// ElementFocused waiting to be merged with a following ValueChanged
+struct PendingFocus<K> {
+    key: K,
+    event: UiEvent,
+    at: Instant,
+}
+
+// Fold a ValueChanged into the focus event that preceded it. The focus event's own data
+// (input source, interaction, ...) is kept, the value change is added next to it.
+fn merge_focus_value(mut focus: UiEvent, value: UiEvent) -> UiEvent {
+    focus.timestamp = value.timestamp;
+    focus.timestamp_nanos = value.timestamp_nanos;
+    focus.element = value.element.or(focus.element);
+    let mut data = match focus.event_specific_data.take() {
+        Some(serde_json::Value::Object(data)) => data,
+        // Listeners only attach objects
+        _ => serde_json::Map::new(),
+    };
+    data.insert("value_changed".to_owned(), true.into());
+    if let Some(value_data) = value.event_specific_data {
+        data.insert("value_change".to_owned(), value_data);
+    }
+    focus.event_specific_data = Some(data.into());
+    focus
+}
+
+// Events held back, under one lock so the timer cannot reorder them with new events
+struct Held<K> {
+    dedup: Option<FocusDedup<K>>,
+    value: Option<PendingValue<K>>,
+    focus: Option<PendingFocus<K>>,
+}
+
+/// Sends a listener's events to its channel. `K` identifies the element an event was
+/// produced for (e.g. its `CFHash` on macOS).
+pub(crate) struct Delivery<K> {
+    tx: mpsc::Sender<UiEvent>,
+    config: ListenerConfig,
+    hasher: Option<ValueHasher>,
+    limiter: Option<RateLimiter>,
+    held: Mutex<Held<K>>,
+    // Wakes the timer thread when an event is held or the listener stops
+    timer: Condvar,
+    stopped: AtomicBool,
+    // Drops not yet reported with an EventsDropped event (report_dropped_events), to a full
+    // channel and to the rate limit
+    unreported_drops: AtomicU64,
+    unreported_rate_limited: AtomicU64,
+    // When an event was last sent, for heartbeat_interval
+    last_delivery: Mutex<Instant>,
+}
+
+impl<K: Copy + PartialEq + Send + 'static> Delivery<K> {
+    pub(crate) fn new(tx: mpsc::Sender<UiEvent>, config: &ListenerConfig) -> Arc<Self> {
+        let delivery = Arc::new(Self {
+            tx,
+            config: config.clone(),
+            hasher: config
+                .hash_values
+                .then(|| ValueHasher::new(config.hash_salt.as_deref())),
+            limiter: RateLimiter::new(config),
+            held: Mutex::new(Held {
+                dedup: config.focus_dedup_window.map(FocusDedup::new),
+                value: None,
+                focus: None,
+            }),
+            timer: Condvar::new(),
+            stopped: AtomicBool::new(false),
+            unreported_drops: AtomicU64::new(0),
+            unreported_rate_limited: AtomicU64::new(0),
+            last_delivery: Mutex::new(Instant::now()),
+        });
+        if !config.value_debounce.is_zero() || config.merge_focus_value {
+            let timer = delivery.clone();
+            std::thread::spawn(move || timer.run_timer());
+        }
+        delivery
+    }
+
+    /// Sends an event produced for the element `key`, if the config captures it.
+    ///
+    /// With debouncing on, a ValueChanged is held for the debounce window and replaced by
+    /// later values of the same element; any other event sends it first to keep the order.
+    pub(crate) fn send(&self, event: UiEvent, key: Option<K>) {
+        // Events without an app (Space changes, ...) are not tied to a filtered app
+        if let Some(app) = event.application.as_ref() {
+            if !self.config.captures_app(app) {
+                return;
+            }
+        }
+        if !self.config.captures_event_type(&event.event_type) {
+            return;
+        }
+
+        let mut held = self.held.lock().unwrap();
+        let now = Instant::now();
+        if held
+            .dedup
+            .as_mut()
+            .is_some_and(|dedup| dedup.is_repeat(&event, key, now))
+        {
+            debug!(event_type = %event.event_type, "dropping repeated focus event");
+            return;
+        }
+
+        // Nothing flushes held values once stopped
+        let debounce = !self.config.value_debounce.is_zero() && !self.is_stopped();
+        match (&event.event_type, key) {
+            (EventType::ValueChanged, Some(key)) if debounce => {
+                if let Some(value) = held.value.as_mut().filter(|value| value.key == key) {
+                    value.event = event;
+                    return;
+                }
+                if let Some(value) = held.value.take() {
+                    self.merge_and_deliver(&mut held, value.event, Some(value.key));
+                }
+                held.value = Some(PendingValue {
+                    key,
+                    event,
+                    at: Instant::now(),
+                });
+                self.timer.notify_one();
+            }
+            _ => {
+                if let Some(value) = held.value.take() {
+                    self.merge_and_deliver(&mut held, value.event, Some(value.key));
+                }
+                self.merge_and_deliver(&mut held, event, key);
+            }
+        }
+    }
+
+    // Sends held events once their window is over, until the listener stops
+    fn run_timer(&self) {
+        let mut held = self.held.lock().unwrap();
+        while !self.is_stopped() {
+            let now = Instant::now();
+            let focus_due = held
+                .focus
+                .as_ref()
+                .map(|focus| focus.at + self.config.merge_window);
+            let value_due = held
+                .value
+                .as_ref()
+                .map(|value| value.at + self.config.value_debounce);
+            // A held value is never older than the held focus, so the focus goes first
+            if focus_due.is_some_and(|due| due <= now) {
+                let focus = held.focus.take().unwrap();
+                self.deliver(focus.event);
+                continue;
+            }
+            if value_due.is_some_and(|due| due <= now) {
+                let value = held.value.take().unwrap();
+                self.merge_and_deliver(&mut held, value.event, Some(value.key));
+                continue;
+            }
+            held = match focus_due.into_iter().chain(value_due).min() {
+                Some(due) => self.timer.wait_timeout(held, due - now).unwrap().0,
+                None => self.timer.wait(held).unwrap(),
+            };
+        }
+    }
+
+    // With focus/value merging on, ElementFocused is held back for the merge window so a
+    // ValueChanged on the same element can be folded into it.
+    fn merge_and_deliver(&self, held: &mut Held<K>, event: UiEvent, key: Option<K>) {
+        if !self.config.merge_focus_value {
+            return self.deliver(event);
+        }
+
+        if let Some(focus) = held.focus.take() {
+            let same_element = key.is_some() && key == Some(focus.key);
+            if matches!(event.event_type, EventType::ValueChanged)
+                && same_element
+                && focus.at.elapsed() <= self.config.merge_window
+            {
+                return self.deliver(merge_focus_value(focus.event, event));
+            }
+            self.deliver(focus.event);
+        }
+
+        match (&event.event_type, key) {
+            // The timer sends it on its own if nothing merges with it in time
+            (EventType::ElementFocused, Some(key)) if !self.is_stopped() => {
+                held.focus = Some(PendingFocus {
+                    key,
+                    event,
+                    at: Instant::now(),
+                });
+                self.timer.notify_one();
+            }
+            _ => self.deliver(event),
+        }
+    }
+
+    // Apply the rate limit and privacy transforms and send the event (non-blocking),
+    // skipping filters and held events
+    fn deliver(&self, mut event: UiEvent) {
+        if self
+            .limiter
+            .as_ref()
+            .is_some_and(|limiter| !limiter.allow(&event.event_type))
+        {
+            self.unreported_rate_limited.fetch_add(1, Ordering::Relaxed);
+            debug!(event_type = %event.event_type, "event over the rate limit, dropped");
+            return;
+        }
+        if let Some(hasher) = self.hasher.as_ref() {
+            hasher.apply(&mut event);
+        }
+        // Only what is actually sent, so filtered, held back and hashed values never show
+        if self.config.print_events {
+            println!("{event}");
+        }
+        *self.last_delivery.lock().unwrap() = Instant::now();
+        self.report_drops();
+        let event_type = event.event_type.clone();
+        match self.tx.try_send(event) {
+            Ok(()) => metrics::count_emitted_event(&event_type),
+            Err(e) => {
+                let total = count_dropped_event();
+                self.unreported_drops.fetch_add(1, Ordering::Relaxed);
+                error!(error = %e, total, "failed to send event");
+            }
+        }
+    }
+
+    // Queue an EventsDropped ahead of the next event if drops happened since the last one
+    fn report_drops(&self) {
+        if !self.config.report_dropped_events {
+            return;
+        }
+        let dropped = self.unreported_drops.swap(0, Ordering::Relaxed);
+        let rate_limited = self.unreported_rate_limited.swap(0, Ordering::Relaxed);
+        if dropped == 0 && rate_limited == 0 {
+            return;
+        }
+        let report = UiEvent {
+            event_specific_data: Some(serde_json::json!({
+                "dropped": dropped,
+                "total": dropped_events(),
+                "rate_limited": rate_limited,
+            })),
+            ..UiEvent::new(EventType::EventsDropped)
+        };
+        if self.tx.try_send(report).is_ok() {
+            metrics::count_emitted_event(&EventType::EventsDropped);
+        } else {
+            // Still full: report these together with the next drops
+            self.unreported_drops.fetch_add(dropped, Ordering::Relaxed);
+            self.unreported_rate_limited
+                .fetch_add(rate_limited, Ordering::Relaxed);
+        }
+    }
+
+    /// Time since an event was last sent (or since the listener started).
+    pub(crate) fn idle(&self) -> Duration {
+        self.last_delivery.lock().unwrap().elapsed()
+    }
+
+    fn is_stopped(&self) -> bool {
+        self.stopped.load(Ordering::Relaxed)
+    }
+
+    /// Ends the timer thread and sends the held events. Later events are sent right away.
+    pub(crate) fn shutdown(&self) {
+        let mut held = self.held.lock().unwrap();
+        self.stopped.store(true, Ordering::Relaxed);
+        self.timer.notify_one();
+        if let Some(value) = held.value.take() {
+            self.merge_and_deliver(&mut held, value.event, Some(value.key));
+        }
+        if let Some(focus) = held.focus.take() {
+            self.deliver(focus.event);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn value_changed(value: &str) -> UiEvent {
+        UiEvent {
+            event_specific_data: Some(serde_json::json!({ "value": value })),
+            ..UiEvent::new(EventType::ValueChanged)
+        }
+    }
+
+    #[test]
+    fn identical_focus_events_are_sent_once() {
+        let mut dedup = FocusDedup::new(Duration::from_millis(100));
+        let start = Instant::now();
+        let focused = UiEvent::new(EventType::ElementFocused);
+        assert!(!dedup.is_repeat(&focused, Some(1), start));
+        assert!(dedup.is_repeat(&focused, Some(1), start));
+        // Another element, or the same one once the window passed, is new
+        assert!(!dedup.is_repeat(&focused, Some(2), start));
+        let later = start + Duration::from_millis(200);
+        assert!(!dedup.is_repeat(&focused, Some(2), later));
+        // Only focus events are deduplicated
+        let value = UiEvent::new(EventType::ValueChanged);
+        assert!(!dedup.is_repeat(&value, Some(2), later));
+        assert!(!dedup.is_repeat(&value, Some(2), later));
+    }
+
+    #[test]
+    fn metrics_count_sent_events() {
+        let (tx, mut rx) = mpsc::channel(16);
+        let delivery = Delivery::<u64>::new(tx, &ListenerConfig::default());
+        let by_type = |metrics: &metrics::Metrics| {
+            metrics
+                .events_by_type
+                .get("ApplicationHidden")
+                .copied()
+                .unwrap_or(0)
+        };
+        let before = metrics::metrics_snapshot();
+        for _ in 0..3 {
+            delivery.send(UiEvent::new(EventType::ApplicationHidden), None);
+        }
+        let after = metrics::metrics_snapshot();
+        // Other tests emit events too, but none of this type
+        assert!(after.events_emitted >= before.events_emitted + 3);
+        assert_eq!(by_type(&after), by_type(&before) + 3);
+        for _ in 0..3 {
+            assert!(rx.try_recv().is_ok());
+        }
+        delivery.shutdown();
+    }
+
+    #[test]
+    fn full_channel_counts_dropped_events() {
+        let (tx, mut rx) = mpsc::channel(1);
+        let delivery = Delivery::<u64>::new(tx, &ListenerConfig::default());
+        let before = dropped_events();
+        delivery.deliver(UiEvent::new(EventType::WindowFocused));
+        delivery.deliver(UiEvent::new(EventType::WindowFocused));
+        // Other tests drop events too, so only a lower bound holds
+        assert!(dropped_events() > before);
+        assert!(rx.try_recv().is_ok());
+        assert!(rx.try_recv().is_err());
+        delivery.shutdown();
+    }
+
+    #[test]
+    fn debounce_sends_only_the_latest_value() {
+        let (tx, mut rx) = mpsc::channel(16);
+        let config = ListenerConfig {
+            value_debounce: Duration::from_millis(20),
+            ..Default::default()
+        };
+        let delivery = Delivery::new(tx, &config);
+        for value in ["a", "ab", "abc"] {
+            delivery.send(value_changed(value), Some(1));
+        }
+        assert!(rx.try_recv().is_err());
+
+        std::thread::sleep(Duration::from_millis(200));
+        let sent = rx.try_recv().unwrap();
+        assert_eq!(sent.event_specific_data.unwrap()["value"], "abc");
+        assert!(rx.try_recv().is_err());
+        delivery.shutdown();
+    }
+
+    #[test]
+    fn focus_is_merged_with_a_following_value_or_sent_alone() {
+        let (tx, mut rx) = mpsc::channel(16);
+        let config = ListenerConfig {
+            value_debounce: Duration::ZERO,
+            merge_focus_value: true,
+            merge_window: Duration::from_millis(20),
+            ..Default::default()
+        };
+        let delivery = Delivery::new(tx, &config);
+        delivery.send(UiEvent::new(EventType::ElementFocused), Some(1));
+        delivery.send(value_changed("a"), Some(1));
+        let merged = rx.try_recv().unwrap();
+        assert!(matches!(merged.event_type, EventType::ElementFocused));
+        assert_eq!(
+            merged.event_specific_data.unwrap()["value_change"]["value"],
+            "a"
+        );
+
+        // Data the focus event carries is kept next to the value change
+        let focus = UiEvent {
+            event_specific_data: Some(
+                serde_json::json!({ "input_source": "com.apple.keylayout.US" }),
+            ),
+            ..UiEvent::new(EventType::ElementFocused)
+        };
+        delivery.send(focus, Some(3));
+        delivery.send(value_changed("b"), Some(3));
+        let data = rx.try_recv().unwrap().event_specific_data.unwrap();
+        assert_eq!(data["input_source"], "com.apple.keylayout.US");
+        assert_eq!(data["value_changed"], true);
+        assert_eq!(data["value_change"]["value"], "b");
+
+        delivery.send(UiEvent::new(EventType::ElementFocused), Some(2));
+        assert!(rx.try_recv().is_err());
+        std::thread::sleep(Duration::from_millis(200));
+        let alone = rx.try_recv().unwrap();
+        assert!(matches!(alone.event_type, EventType::ElementFocused));
+        assert!(alone.event_specific_data.is_none());
+        delivery.shutdown();
+    }
+}
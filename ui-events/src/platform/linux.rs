@@ -1,6 +1,7 @@
 #![cfg(target_os = "linux")]
 
 use super::PlatformListener;
+use crate::error::UiEventsError;
 use crate::event::UiEvent;
 use anyhow::Result;
 use tokio::sync::mpsc;
@@ -8,8 +9,9 @@ use tokio::sync::mpsc;
 pub struct LinuxListener {}
 
 impl LinuxListener {
-    pub fn new() -> Result<Self> {
-        anyhow::bail!("linux listener not implemented")
+    pub fn new() -> Result<Self, UiEventsError> {
+        // TODO: Implement using AT-SPI
+        Err(UiEventsError::UnsupportedPlatform)
     }
 }
 
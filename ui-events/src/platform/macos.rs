@@ -1,11 +1,11 @@
 /*
-This file implements the `PlatformListener` trait for macOS.
+This file implements the macOS listener, `MacosListener`.
 It leverages Apple's Accessibility API (AXUIElement, AXObserver) via the `cidre` crate
 to capture UI events such as application activation, window focus changes,
 and UI element interactions (focus, value changes).
 
-Events are captured using callbacks registered with `AXObserver` on a dedicated thread
-running a `CFRunLoop`. Captured event data is then structured into a `UiEvent`
+Events are captured using callbacks registered with `AXObserver` on the main thread's run
+loop. Captured event data is then structured into a `UiEvent`
 and sent asynchronously through an `mpsc::Sender` provided during initialization.
 
 Key components:
@@ -14,40 +14,125 @@ Key components:
 - `cf`: Core Foundation types (RunLoop, String, etc.) within `cidre`.
 - `ns`: AppKit types (Workspace, Application) within `cidre`.
 - `tokio::sync::mpsc`: Used for sending events back to the main application logic.
-- `thread_local!`: Used to store the sender and observer state within the C callback context.
+- `thread_local!`: Used to collect failed attribute reads while an event is extracted.
 */
 
 #![cfg(target_os = "macos")]
 
-use super::PlatformListener;
+use super::AppObservability;
+use super::delivery::Delivery;
+use crate::config::{AttributeMask, ListenerConfig};
+use crate::control::{ClientQuery, ControlRequest};
+use crate::error::UiEventsError;
 use crate::event::{
-    ApplicationInfo, ElementDetails, EventType, Position, Size, UiEvent, WindowInfo,
+    ApplicationInfo, AttributeError, DisplayInfo, ElementDetails, EventType, NormalizedRole,
+    Position, Size, UiEvent, WindowInfo,
 };
+use crate::metrics;
 use anyhow::{Result, anyhow};
-use chrono::Utc;
-use cidre::arc::{self, Retained};
+use cidre::arc;
 use cidre::objc::Obj;
 use cidre::{ax, cf, ns, objc::ar_pool};
 use std::cell::RefCell;
+use std::collections::{HashMap, VecDeque};
 use std::ffi::c_void;
 use std::marker::PhantomPinned;
 use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, AtomicPtr, AtomicU64, Ordering};
 use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 use tokio::sync::mpsc;
-use tracing::{error, info, warn};
+use tracing::{debug, error, info, warn};
 
-// Store sender and current observer for the CFRunLoop thread
 thread_local! {
-    static SENDER: RefCell<Option<mpsc::Sender<UiEvent>>> = RefCell::new(None);
-    // Store the active AXObserver and the top-level element it observes (the app element)
-    static CURRENT_AX_OBSERVER: RefCell<Option<(Retained<ax::Observer>, Retained<ax::UiElement>)>> = RefCell::new(None);
-    // Store the NSWorkspace observer token to remove it on cleanup
-    static WORKSPACE_OBSERVER_TOKEN: RefCell<Option<Retained<ns::Id>>> = RefCell::new(None);
+    // Failed attribute reads of the event being extracted, when diagnostics are on
+    static AX_FAILURES: RefCell<Option<Vec<AttributeError>>> = RefCell::new(None);
 }
 
 // Define the reference date epoch seconds (Unix timestamp for 2001-01-01T00:00:00Z)
 const CF_ABSOLUTE_TIME_EPOCH_OFFSET: i64 = 978307200;
 
+// Role reported by stepper controls (kAXIncrementorRole)
+const STEPPER_ROLE: &str = "AXIncrementor";
+
+// Role reported by progress bars and spinners
+const PROGRESS_ROLE: &str = "AXProgressIndicator";
+
+// Posted by web areas (browsers, web views) when a page finishes loading
+const LOAD_COMPLETE_NOTIFICATION: &str = "AXLoadComplete";
+
+// Role of attached modal dialogs (save/print panels, alerts on a window)
+const SHEET_ROLE: &str = "AXSheet";
+
+// Posted when a sheet is attached to a window (kAXSheetCreatedNotification)
+const SHEET_CREATED_NOTIFICATION: &str = "AXSheetCreated";
+
+// Posted by tables/outlines/lists when the selected rows change; reported as SelectionChanged,
+// or as ItemReordered when it reveals a row move
+const SELECTED_ROWS_CHANGED_NOTIFICATION: &str = "AXSelectedRowsChanged";
+
+// Most selected items described in a SelectionChanged event
+const SELECTION_MAX_ITEMS: usize = 32;
+
+// Notifications registered by raw name. Not every app supports them (e.g. AXLoadComplete
+// needs web content), so registration failures are expected.
+const OPTIONAL_NOTIFICATIONS: [&str; 3] = [
+    LOAD_COMPLETE_NOTIFICATION,
+    SHEET_CREATED_NOTIFICATION,
+    SELECTED_ROWS_CHANGED_NOTIFICATION,
+];
+
+// Bounds for the sheet content summary
+const SHEET_SUMMARY_DEPTH: usize = 4;
+const SHEET_SUMMARY_ITEMS: usize = 16;
+
+// Roles whose ValueChanged events are held back in value-on-commit mode
+const TEXT_INPUT_ROLES: [&str; 3] = ["AXTextField", "AXTextArea", "AXComboBox"];
+
+// Roles whose value is read in full even when the app shortens AXValue
+const FULL_TEXT_ROLES: [&str; 2] = ["AXTextField", "AXTextArea"];
+
+// Password fields: role of some, subrole of AXTextField for NSSecureTextField
+const SECURE_TEXT_FIELD: &str = "AXSecureTextField";
+
+// Observer creation can fail while an app is still launching; retry a few times.
+// Retries block the run loop, so keep the total wait well under a second.
+const OBSERVER_CREATE_ATTEMPTS: u32 = 4;
+const OBSERVER_RETRY_INITIAL_DELAY: Duration = Duration::from_millis(50);
+
+// A menu item selection this long after the last menu open/close, with no menu open,
+// is attributed to a keyboard shortcut
+const MENU_SHORTCUT_QUIET_PERIOD: Duration = Duration::from_millis(500);
+
+// Roles of elements that open a menu and own it as their child
+const MENU_OWNER_ROLES: [&str; 4] = [
+    "AXMenuBarItem",
+    "AXPopUpButton",
+    "AXMenuButton",
+    "AXMenuItem",
+];
+
+// Nesting limit when walking up from a menu to the menu bar
+const MENU_PATH_MAX_DEPTH: usize = 10;
+
+// AXMenuItemCmdModifiers bits (kAXMenuItemModifier*); Command is implied unless NoCommand
+const MENU_MODIFIER_SHIFT: u32 = 1 << 0;
+const MENU_MODIFIER_OPTION: u32 = 1 << 1;
+const MENU_MODIFIER_CONTROL: u32 = 1 << 2;
+const MENU_MODIFIER_NO_COMMAND: u32 = 1 << 3;
+
+// How often the hang watchdog probes the frontmost app
+const HANG_PROBE_INTERVAL: Duration = Duration::from_secs(1);
+
+// How many recently seen elements can be described on request
+const DESCRIBE_REGISTRY_CAPACITY: usize = 256;
+
+// Upper bound on remembered element values; the cache is cleared when exceeded
+const VALUE_CACHE_CAPACITY: usize = 1024;
+
+// Levels of `ElementDetails::path`, guarding against parent cycles and very deep trees
+const ELEMENT_PATH_MAX_DEPTH: usize = 20;
+
 extern "C" fn observer_callback2(
     _observer: &mut ax::Observer,
     element: &mut ax::UiElement,
@@ -58,88 +143,6 @@ extern "C" fn observer_callback2(
     listener.handle_ui_event(element, notification);
 }
 
-// The C callback function for AXObserver notifications
-extern "C" fn observer_callback(
-    _observer: &mut ax::Observer,
-    element: &mut ax::UiElement,
-    notification: &ax::Notification,
-    _user_info: *mut c_void,
-) {
-    ar_pool(|| {
-        // Directly use the Ref, or retain if ownership/longer lifetime is needed
-        let element = element.retained();
-        let notification = notification.retained();
-        let notification_name = notification.to_string();
-
-        info!(%notification_name, "observer_callback received");
-
-        SENDER.with(|cell| {
-            let r = cell.borrow();
-            let sender = match r.as_ref() {
-                Some(s) => s,
-                None => {
-                    error!("sender not available in observer callback");
-                    return;
-                }
-            };
-
-            // Map AX notifications (cf::String constants) to our event types
-            let event_type = if notification.equal(ax::notification::focused_window_changed()) {
-                EventType::WindowFocused
-            } else if notification.equal(ax::notification::focused_ui_element_changed()) {
-                EventType::ElementFocused
-            } else if notification.equal(ax::notification::value_changed()) {
-                EventType::ValueChanged
-            } else if notification.equal(ax::notification::window_created()) {
-                EventType::WindowCreated
-            } else if notification.equal(ax::notification::window_moved()) {
-                EventType::WindowMoved
-            } else if notification.equal(ax::notification::window_resized()) {
-                EventType::WindowResized
-            } else if notification.equal(ax::notification::ui_element_destroyed()) {
-                EventType::ElementDestroyed
-            } else if notification.equal(ax::notification::menu_opened()) {
-                EventType::MenuOpened
-            } else if notification.equal(ax::notification::menu_closed()) {
-                EventType::MenuClosed
-            } else if notification.equal(ax::notification::menu_item_selected()) {
-                EventType::MenuItemSelected
-            } else if notification.equal(ax::notification::selected_text_changed()) {
-                EventType::SelectedTextChanged
-            } else if notification.equal(ax::notification::title_changed()) {
-                EventType::TitleChanged
-            } else {
-                info!(%notification_name, "ignoring unhandled ax notification");
-                return;
-            };
-
-            // Extract contextual data from the element
-            match extract_event_data(&element) {
-                Ok((app_info, window_info, element_details)) => {
-                    let event = UiEvent {
-                        event_type,
-                        timestamp: Utc::now(),
-                        application: app_info,
-                        window: window_info,
-                        element: element_details,
-                        event_specific_data: None, // Populate if needed
-                    };
-
-                    // Send the event (non-blocking)
-                    if let Err(e) = sender.try_send(event) {
-                        error!(error = %e, "failed to send event from callback");
-                    }
-
-                    info!(%notification_name, "event sent");
-                }
-                Err(e) => {
-                    error!(error = %e, "failed to extract event data in callback");
-                }
-            }
-        });
-    });
-}
-
 // Convert common CF types to serde_json::Value
 fn cf_value_to_json(cf_value: &cf::Type) -> Option<serde_json::Value> {
     ar_pool(|| {
@@ -165,6 +168,11 @@ fn cf_value_to_json(cf_value: &cf::Type) -> Option<serde_json::Value> {
         } else if type_id == cf::Boolean::type_id() {
             let b_ptr = cf_value as *const cf::Type as *const cf::Boolean;
             Some(serde_json::json!(unsafe { &*b_ptr }.value()))
+        } else if type_id == cf::Url::type_id() {
+            let u_ptr = cf_value as *const cf::Type as *const cf::Url;
+            Some(serde_json::Value::String(
+                unsafe { &*u_ptr }.string().to_string(),
+            ))
         } else if type_id == cf::Date::type_id() {
             let d_ptr = cf_value as *const cf::Type as *const cf::Date;
             let d_date = unsafe { &*d_ptr };
@@ -181,10 +189,48 @@ fn cf_value_to_json(cf_value: &cf::Type) -> Option<serde_json::Value> {
     })
 }
 
+// Start recording failed attribute reads on this thread
+fn collect_ax_failures() {
+    AX_FAILURES.with(|cell| *cell.borrow_mut() = Some(Vec::new()));
+}
+
+// Stop recording and return what failed since collect_ax_failures()
+fn take_ax_failures() -> Vec<AttributeError> {
+    AX_FAILURES.with(|cell| cell.borrow_mut().take().unwrap_or_default())
+}
+
+fn note_ax_failure(attribute: &str, error: &impl std::fmt::Debug) {
+    AX_FAILURES.with(|cell| {
+        if let Some(failures) = cell.borrow_mut().as_mut() {
+            failures.push(AttributeError {
+                attribute: attribute.to_string(),
+                error: format!("{:?}", error),
+            });
+        }
+    });
+}
+
+// attr_value that records failures for diagnostics instead of silently dropping them
+fn read_attr(element: &ax::UiElement, attribute: &ax::Attr) -> Option<arc::R<cf::Type>> {
+    element
+        .attr_value(attribute)
+        .map_err(|e| note_ax_failure(&attribute.to_string(), &e))
+        .ok()
+}
+
+// Helper to get a boolean attribute (e.g. AXEnabled)
+fn get_bool_attribute(element: &ax::UiElement, attribute: &ax::Attr) -> Option<bool> {
+    ar_pool(|| {
+        read_attr(element, attribute)
+            .and_then(|val| cf_value_to_json(&*val))
+            .and_then(|value| value.as_bool())
+    })
+}
+
 // Helper to safely get a string attribute from an AXUIElement
 fn get_string_attribute(element: &ax::UiElement, attribute: &ax::Attr) -> Option<String> {
     ar_pool(|| {
-        element.attr_value(attribute).ok().and_then(|val| {
+        read_attr(element, attribute).and_then(|val| {
             if val.get_type_id() == cf::String::type_id() {
                 let s_ptr = &*val as *const cf::Type as *const cf::String;
                 let string = unsafe { &*s_ptr }.to_string();
@@ -200,10 +246,60 @@ fn get_string_attribute(element: &ax::UiElement, attribute: &ax::Attr) -> Option
     })
 }
 
+// String attribute without recording failures, for reads expected to fail often
+fn string_attr_quiet(element: &ax::UiElement, attribute: &ax::Attr) -> Option<String> {
+    let val = element.attr_value(attribute).ok()?;
+    if val.get_type_id() != cf::String::type_id() {
+        return None;
+    }
+    let s_ptr = &*val as *const cf::Type as *const cf::String;
+    let string = unsafe { &*s_ptr }.to_string();
+    (!string.is_empty()).then_some(string)
+}
+
+// Whether the element is a password field, whose value must never be read
+fn is_secure_text_field(element: &ax::UiElement, role: Option<&str>) -> bool {
+    match role {
+        Some(SECURE_TEXT_FIELD) => true,
+        Some("AXTextField") => ar_pool(|| {
+            string_attr_quiet(element, &attr_named("AXSubrole")).as_deref()
+                == Some(SECURE_TEXT_FIELD)
+        }),
+        _ => false,
+    }
+}
+
+// `role[label]` segments from the window down to the element (see ElementDetails::path).
+// Most ancestors have no title, so their reads are not reported as diagnostics.
+fn element_path(element: &ax::UiElement) -> Vec<String> {
+    ar_pool(|| {
+        let mut segments = Vec::new();
+        let ancestry = std::iter::successors(Some(element.retained()), |el| el.parent().ok());
+        for el in ancestry.take(ELEMENT_PATH_MAX_DEPTH) {
+            let role = el.role().ok();
+            let is_window = role.as_ref().is_some_and(|r| r.equal(ax::role::window()));
+            let role = role
+                .map(|r| r.to_string())
+                .unwrap_or_else(|| "?".to_string());
+            let label = string_attr_quiet(&el, ax::attr::title())
+                .or_else(|| string_attr_quiet(&el, ax::attr::desc()));
+            segments.push(match label {
+                Some(label) => format!("{}[{}]", role, label),
+                None => role,
+            });
+            if is_window {
+                break;
+            }
+        }
+        segments.reverse();
+        segments
+    })
+}
+
 // Helper to get position
 fn get_element_position(element: &ax::UiElement) -> Option<Position> {
     ar_pool(|| {
-        element.attr_value(ax::attr::pos()).ok().and_then(|val| {
+        read_attr(element, ax::attr::pos()).and_then(|val| {
             // Check if the value is an AXValue encoding a CGPoint
             if val.get_type_id() == ax::Value::type_id() {
                 let value_ptr = &*val as *const cf::Type as *const ax::Value;
@@ -229,7 +325,7 @@ fn get_element_position(element: &ax::UiElement) -> Option<Position> {
 // Helper to get size
 fn get_element_size(element: &ax::UiElement) -> Option<Size> {
     ar_pool(|| {
-        element.attr_value(ax::attr::size()).ok().and_then(|val| {
+        read_attr(element, ax::attr::size()).and_then(|val| {
             if val.get_type_id() == ax::Value::type_id() {
                 let value_ptr = &*val as *const cf::Type as *const ax::Value;
                 let ax_value = unsafe { &*value_ptr };
@@ -251,288 +347,2331 @@ fn get_element_size(element: &ax::UiElement) -> Option<Size> {
     })
 }
 
-// Enhanced helper - NOT wrapped entirely in ar_pool anymore
-fn extract_event_data(
-    element: &ax::UiElement,
-) -> Result<(
-    Option<ApplicationInfo>,
-    Option<WindowInfo>,
-    Option<ElementDetails>,
-)> {
-    let pid = element.pid().ok(); // Use ok() to handle potential error
+fn is_text_input(event: &UiEvent) -> bool {
+    event
+        .element
+        .as_ref()
+        .and_then(|e| e.role.as_deref())
+        .is_some_and(|role| TEXT_INPUT_ROLES.contains(&role))
+}
 
-    // --- Application Info ---
-    let app_info: Option<ApplicationInfo> = pid.and_then(|p| {
-        ar_pool(|| {
-            // Pool for NS object access
-            let app = ns::running_application::RunningApp::with_pid(p);
-            let app_name = app.and_then(|a| a.localized_name()).map(|s| s.to_string());
-            Some(ApplicationInfo {
-                name: app_name,
-                pid: Some(p),
-            })
-        })
+// URL and title of a web area that finished loading
+fn page_loaded_data(element: &ax::UiElement, window: &Option<WindowInfo>) -> serde_json::Value {
+    let url = ar_pool(|| {
+        element
+            .attr_value(&attr_named("AXURL"))
+            .ok()
+            .and_then(|val| cf_value_to_json(&*val))
     });
+    let title = get_string_attribute(element, ax::attr::title())
+        .or_else(|| window.as_ref().and_then(|w| w.title.clone()));
+    serde_json::json!({
+        "url": url,
+        "title": title,
+    })
+}
 
-    // --- Window Info ---
-    // ar_pool might be needed here due to element access & retention
-    let window_info: Option<WindowInfo> = ar_pool(|| {
-        // 1. Check if the element itself is the window
-        let mut window_element = element
-            .role()
+// The app receiving key events. More reliable than scanning running apps for `is_active`,
+// which can be stale or match nothing while apps are switching.
+fn frontmost_app() -> Option<arc::R<ns::RunningApp>> {
+    ns::Workspace::shared().frontmost_app()
+}
+
+// Frontmost application and its focused window, queried on demand
+fn frontmost_context() -> (Option<ApplicationInfo>, Option<WindowInfo>) {
+    ar_pool(|| {
+        let Some(app) = frontmost_app() else {
+            return (None, None);
+        };
+        let app_element = ax::UiElement::with_app_pid(app.pid());
+        let window = app_element
+            .attr_value(ax::attr::focused_window())
             .ok()
-            .filter(|r| r.equal(ax::role::window()))
-            .map(|_| element.retained()); // Retain if it's a window
+            .filter(|val| val.get_type_id() == ax::UiElement::type_id())
+            .map(|val| {
+                let win_ptr = &*val as *const cf::Type as *const ax::UiElement;
+                window_info_of(unsafe { &*win_ptr })
+            });
+        (Some(app_info(&app)), window)
+    })
+}
 
-        // 2. If not, traverse parents
-        if window_element.is_none() {
-            window_element = std::iter::successors(Some(element.retained()), |el| el.parent().ok())
-                .find(|el| {
-                    el.role()
-                        .map(|r| r.equal(ax::role::window()))
-                        .unwrap_or(false) // Default to false if role fetch fails
-                });
-        }
+// The running app carried by an NSWorkspace notification
+fn notification_app(n: &ns::Notification) -> Option<arc::R<ns::RunningApp>> {
+    let Some(user_info) = n.user_info() else {
+        warn!("no user info");
+        return None;
+    };
+    let Some(app) = user_info.get(ns::workspace::notification::app_key()) else {
+        warn!("no app in notification");
+        return None;
+    };
+    let Some(app) = app.try_cast(ns::RunningApp::cls()) else {
+        warn!("wrong app class");
+        return None;
+    };
+    Some(app.retained())
+}
 
-        // 3. If still no window, try getting the app's focused window
-        if window_element.is_none() {
-            if let Some(p) = pid {
-                // Requires access to AX API inside pool
-                let app_element = ax::UiElement::with_app_pid(p);
-                window_element = app_element
-                    .attr_value(ax::attr::focused_window())
-                    .ok()
-                    .and_then(|val| {
-                        // The attribute value should be an AXUIElement
-                        if val.get_type_id() == ax::UiElement::type_id() {
-                            let win_ptr = &*val as *const cf::Type as *const ax::UiElement;
-                            Some(unsafe { &*win_ptr }.retained()) // Retain the window element
-                        } else {
-                            None
-                        }
-                    });
-            }
+// Title and frame of a window element
+fn window_info_of(window: &ax::UiElement) -> WindowInfo {
+    WindowInfo {
+        title: get_string_attribute(window, ax::attr::title()),
+        position: get_element_position(window),
+        size: get_element_size(window),
+        ..Default::default()
+    }
+}
+
+// Whether the app answers a simple attribute read within `timeout`. Idle apps answer right
+// away; a hung app (spinning beachball) lets the read time out with kAXErrorCannotComplete.
+// Other errors (e.g. the app quit) do not count as a hang.
+fn app_responds(pid: i32, timeout: Duration) -> bool {
+    ar_pool(|| {
+        let mut app_element = ax::UiElement::with_app_pid(pid);
+        if let Err(e) = app_element.set_messaging_timeout_secs(timeout.as_secs_f32()) {
+            debug!(pid, error = ?e, "failed to set ax messaging timeout");
         }
+        match app_element.role() {
+            Ok(_) => true,
+            Err(e) => e != ax::Error::CANNOT_COMPLETE,
+        }
+    })
+}
 
-        // Extract title if we found a window element
-        window_element.and_then(|win| {
-            let title = get_string_attribute(&win, ax::attr::title());
-            Some(WindowInfo { title, id: None })
-            // Note: win (Retained<UiElement>) goes out of scope here, pool handles release
-        })
-    });
+// Stop an observer's callbacks before releasing it. The main run loop retains the source, so
+// dropping the observer alone keeps delivering the previous app's notifications.
+fn detach_observer(observer: &ax::Observer) {
+    metrics::observer_detached();
+    cf::RunLoop::main().remove_src(observer.run_loop_src(), cf::RunLoopMode::default());
+}
 
-    // --- Element Details ---
-    // These helpers use ar_pool internally
-    let role = ar_pool(|| element.role().map(|r| r.to_string()).ok());
-    let identifier = get_string_attribute(element, ax::attr::title())
-        .or_else(|| get_string_attribute(element, ax::attr::desc()))
-        .or_else(|| get_string_attribute(element, ax::attr::help()));
-    let value = ar_pool(|| {
-        element
-            .attr_value(ax::attr::value())
-            .ok()
-            .and_then(|cf_val| cf_value_to_json(&*cf_val))
-    });
-    let position = get_element_position(element);
-    let size = get_element_size(element);
+fn app_info(app: &ns::RunningApp) -> ApplicationInfo {
+    let url = app.bundle_url().or_else(|| app.executable_url());
+    ApplicationInfo {
+        name: app.localized_name().map(|s| s.to_string()),
+        pid: Some(app.pid()),
+        bundle_id: app.bundle_id().map(|s| s.to_string()),
+        path: url.and_then(|u| u.path()).map(|s| s.to_string()),
+    }
+}
 
-    let element_details = ElementDetails {
-        role,
-        identifier,
-        value,
-        position,
-        size,
-    };
+// Call `create` until it succeeds, doubling the delay between attempts, and hand the result
+// to `done`: the value, or the last error and the number of attempts made. Retries run from
+// run loop timers, so the loop keeps delivering events while an app is not ready yet.
+fn with_retry<T: 'static, E: std::fmt::Debug + 'static>(
+    pid: i32,
+    create: impl FnMut() -> std::result::Result<T, E> + 'static,
+    done: impl FnOnce(std::result::Result<T, (E, u32)>) + 'static,
+) {
+    retry_attempt(pid, 1, OBSERVER_RETRY_INITIAL_DELAY, create, done);
+}
 
-    Ok((app_info, window_info, Some(element_details))) // Final Result constructed outside ar_pool
+fn retry_attempt<T: 'static, E: std::fmt::Debug + 'static>(
+    pid: i32,
+    attempt: u32,
+    delay: Duration,
+    mut create: impl FnMut() -> std::result::Result<T, E> + 'static,
+    done: impl FnOnce(std::result::Result<T, (E, u32)>) + 'static,
+) {
+    match create() {
+        Ok(value) => done(Ok(value)),
+        Err(e) if attempt >= OBSERVER_CREATE_ATTEMPTS => done(Err((e, attempt))),
+        Err(e) => {
+            warn!(pid, attempt, error = ?e, ?delay, "failed to create axobserver, retrying");
+            run_later(delay, move || {
+                retry_attempt(pid, attempt + 1, delay * 2, create, done)
+            });
+        }
+    }
 }
 
-// Function called by NSWorkspace notification observer when an app activates
-// Changed signature to accept &ns::running_application::RunningApp
-fn handle_activation(app: &ns::running_application::RunningApp, sender: &mpsc::Sender<UiEvent>) {
+// Running apps with a UI, each probed the way the listener attaches to apps
+pub fn list_apps() -> Vec<AppObservability> {
     ar_pool(|| {
-        let pid = app.pid();
-        let app_name = app.localized_name().map(|s| s.to_string());
-        info!(app_name = ?app_name, pid, "activated app");
+        ns::Workspace::shared()
+            .running_apps()
+            .iter()
+            .filter(|app| app.activation_policy() != ns::AppActivationPolicy::Prohibited)
+            .map(|app| {
+                let error = probe_observer(app.pid()).err();
+                AppObservability {
+                    application: app_info(app),
+                    observable: error.is_none(),
+                    error,
+                }
+            })
+            .collect()
+    })
+}
 
-        // --- Send ApplicationActivated Event ---
-        let event = UiEvent {
-            event_type: EventType::ApplicationActivated,
-            timestamp: Utc::now(),
-            application: Some(ApplicationInfo {
-                name: app_name.clone(),
-                pid: Some(pid),
-            }),
-            window: None,
-            element: None,
-            event_specific_data: None,
-        };
-        if let Err(e) = sender.try_send(event) {
-            error!(error = %e, "failed to send activation event");
-        }
-
-        CURRENT_AX_OBSERVER.with(|cell| {
-            if cell.borrow().is_some() {
-                info!(pid = pid, "dropping old axobserver");
-                *cell.borrow_mut() = None;
-            }
-
-            // Get app element using pid
-            let app_element = ax::UiElement::with_app_pid(pid);
-
-            // Retry Observer::new_for_app
-            match ax::Observer::with_cb(pid, observer_callback) {
-                Ok(mut observer) => { // observer should be Retained<ax::Observer>
-                    info!(pid, "created new axobserver");
-
-                    let notifications_to_add = [
-                        ax::notification::focused_window_changed(),
-                        ax::notification::focused_ui_element_changed(),
-                        ax::notification::value_changed(),
-                        // Added notifications:
-                        ax::notification::window_created(),
-                        ax::notification::window_moved(),
-                        ax::notification::window_resized(),
-                        ax::notification::ui_element_destroyed(),
-                        ax::notification::menu_opened(),
-                        ax::notification::menu_closed(),
-                        ax::notification::menu_item_selected(),
-                        ax::notification::selected_text_changed(),
-                        ax::notification::title_changed(),
-                    ];
-
-                    for notif_name in notifications_to_add {
-                        // Observer expects &cf::String for notification name
-                        // Call add_notification on the observer instance
-                        match observer.add_notification(&app_element, notif_name, std::ptr::null_mut()) {
-                            Ok(_) => info!(pid, notification = %notif_name.to_string(), "added notification"),
-                            Err(e) => error!(pid, notification = %notif_name.to_string(), error = ?e, "failed to add notification"),
-                        }
-                    }
+// Create an observer for the app and register a notification with it, without adding it to a
+// run loop, so nothing is ever delivered
+fn probe_observer(pid: i32) -> std::result::Result<(), String> {
+    let mut observer =
+        ax::Observer::with_cb(pid, observer_callback2).map_err(|e| format!("{:?}", e))?;
+    let app_element = ax::UiElement::with_app_pid(pid);
+    observer
+        .add_notification(
+            &app_element,
+            ax::notification::focused_ui_element_changed(),
+            std::ptr::null_mut(),
+        )
+        .map_err(|e| format!("{:?}", e))?;
+    Ok(())
+}
 
-                    // Call run_loop_source on the observer instance
-                    let source = observer.run_loop_src(); // Should be Retained<cf::RunLoopSource>
-                    source.invalidate();
-                    // Use add_source with as_ref()
-                    cf::RunLoop::current().add_src(source, cf::RunLoopMode::default());
-                    info!(pid, "added run loop source for observer");
+// Private SkyLight calls, re-exported by CoreGraphics.
+// There is no public API exposing Space (virtual desktop) identifiers.
+#[link(name = "CoreGraphics", kind = "framework")]
+unsafe extern "C" {
+    fn CGSMainConnectionID() -> i32;
+    fn CGSGetActiveSpace(cid: i32) -> u64;
+}
 
-                    // Store the observer
-                    *cell.borrow_mut() = Some((observer, app_element));
-                }
-                Err(e) => {
-                    error!(pid, error = ?e, "failed to create axobserver for pid");
-                }
-            }
-        });
-    });
+// Identifier of the active Space on the main display, if the private API answers
+fn active_space_id() -> Option<u64> {
+    let id = unsafe { CGSGetActiveSpace(CGSMainConnectionID()) };
+    (id != 0).then_some(id)
 }
 
-pub struct MacosListener {
-    tx: mpsc::Sender<UiEvent>,
-    ax_observer: Mutex<Option<arc::R<ax::Observer>>>,
-    ws_observer_token: Mutex<Option<arc::R<ns::Id>>>,
-    // self reference pointer
-    ptr: *mut std::ffi::c_void,
-    _pin: PhantomPinned,
+// Display geometry (CoreGraphics), in the same global top-left coordinates as AX
+#[link(name = "CoreGraphics", kind = "framework")]
+unsafe extern "C" {
+    fn CGGetDisplaysWithPoint(
+        point: CGPoint,
+        max_displays: u32,
+        displays: *mut u32,
+        display_count: *mut u32,
+    ) -> i32;
+    fn CGDisplayBounds(display: u32) -> CGRect;
 }
 
-// all processing on main thread
-unsafe impl Send for MacosListener {}
-unsafe impl Sync for MacosListener {}
+// The display containing `point` (AX coordinates, points)
+fn display_at(point: &Position) -> Option<DisplayInfo> {
+    let mut display = 0u32;
+    let mut count = 0u32;
+    let point_cg = CGPoint {
+        x: point.x,
+        y: point.y,
+    };
+    let err = unsafe { CGGetDisplaysWithPoint(point_cg, 1, &mut display, &mut count) };
+    if err != 0 || count == 0 {
+        return None;
+    }
+    let bounds = unsafe { CGDisplayBounds(display) };
+    Some(DisplayInfo {
+        id: display,
+        position: Position {
+            x: bounds.origin.x,
+            y: bounds.origin.y,
+        },
+        size: Size {
+            width: bounds.size.width,
+            height: bounds.size.height,
+        },
+        scale_factor: backing_scale_at(point).unwrap_or(1.0),
+    })
+}
 
-impl MacosListener {
-    fn handle_ui_event(&self, element: &mut ax::UiElement, n: &ax::Notification) {
-        // Map AX notifications (cf::String constants) to our event types
-        use ax::notification as axn;
-        let event_type = match () {
-            _ if n == axn::focused_window_changed() => EventType::WindowFocused,
-            _ if n == axn::focused_ui_element_changed() => EventType::ElementFocused,
-            _ if n == axn::value_changed() => EventType::ValueChanged,
-            _ if n == axn::window_created() => EventType::WindowCreated,
-            _ if n == axn::window_moved() => EventType::WindowMoved,
-            _ if n == axn::window_resized() => EventType::WindowResized,
-            _ if n == axn::ui_element_destroyed() => EventType::ElementDestroyed,
-            _ if n == axn::menu_opened() => EventType::MenuOpened,
-            _ if n == axn::menu_closed() => EventType::MenuClosed,
-            _ if n == axn::menu_item_selected() => EventType::MenuItemSelected,
-            _ if n == axn::selected_text_changed() => EventType::SelectedTextChanged,
-            _ if n == axn::title_changed() => EventType::TitleChanged,
-            _ => return,
-        };
+// Display of an event from its element's position, else its window's. Call before
+// positions are scaled to pixels.
+fn display_of(
+    window: &Option<WindowInfo>,
+    element: &Option<ElementDetails>,
+) -> Option<DisplayInfo> {
+    element
+        .as_ref()
+        .and_then(|e| e.position.as_ref())
+        .or_else(|| window.as_ref().and_then(|w| w.position.as_ref()))
+        .and_then(display_at)
+}
 
-        // Extract contextual data from the element
-        match extract_event_data(&element) {
-            Ok((app_info, window_info, element_details)) => {
-                let event = UiEvent {
-                    event_type,
-                    timestamp: Utc::now(),
-                    application: app_info,
-                    window: window_info,
-                    element: element_details,
-                    event_specific_data: None, // Populate if needed
-                };
+// Text Input Source Services (Carbon), not covered by cidre
+#[link(name = "Carbon", kind = "framework")]
+unsafe extern "C" {
+    static kTISPropertyInputSourceID: *const c_void;
+    fn TISCopyCurrentKeyboardInputSource() -> *const c_void;
+    fn TISGetInputSourceProperty(source: *const c_void, key: *const c_void) -> *const c_void;
+}
 
-                println!("{event:?}");
+#[link(name = "CoreFoundation", kind = "framework")]
+unsafe extern "C" {
+    fn CFRelease(cf: *const c_void);
+    fn CFNotificationCenterGetDistributedCenter() -> *const c_void;
+    fn CFNotificationCenterAddObserver(
+        center: *const c_void,
+        observer: *const c_void,
+        callback: extern "C" fn(
+            *const c_void,
+            *const c_void,
+            *const c_void,
+            *const c_void,
+            *const c_void,
+        ),
+        name: *const c_void,
+        object: *const c_void,
+        suspension_behavior: isize,
+    );
+    fn CFNotificationCenterRemoveEveryObserver(center: *const c_void, observer: *const c_void);
+}
 
-                // Send the event (non-blocking)
-                if let Err(e) = self.tx.try_send(event) {
-                    error!(error = %e, "failed to send event from callback");
-                }
-            }
-            Err(e) => {
-                error!(error = %e, "failed to extract event data in callback");
-            }
+// kTISNotifySelectedKeyboardInputSourceChanged, posted on the distributed center
+const INPUT_SOURCE_CHANGED_NOTIFICATION: &str =
+    "com.apple.Carbon.TISNotifySelectedKeyboardInputSourceChanged";
+// CFNotificationSuspensionBehaviorDeliverImmediately
+const DELIVER_IMMEDIATELY: isize = 4;
+
+// Id of the selected keyboard input source, e.g. `com.apple.keylayout.US`
+fn current_input_source_id() -> Option<String> {
+    unsafe {
+        let source = TISCopyCurrentKeyboardInputSource();
+        if source.is_null() {
+            return None;
         }
+        // Get rule: owned by `source`
+        let id = TISGetInputSourceProperty(source, kTISPropertyInputSourceID);
+        let id = (!id.is_null()).then(|| (*(id as *const cf::String)).to_string());
+        CFRelease(source);
+        id
     }
+}
 
-    #[inline]
-    fn handle_app_activation(self: &Pin<Arc<Self>>, n: &ns::Notification) {
-        let Some(user_info) = n.user_info() else {
-            return println!("no user info");
-        };
-        let Some(app) = user_info.get(ns::workspace::notification::app_key()) else {
-            return println!("no active app");
-        };
+extern "C" fn input_source_callback(
+    _center: *const c_void,
+    observer: *const c_void,
+    _name: *const c_void,
+    _object: *const c_void,
+    _user_info: *const c_void,
+) {
+    let listener: &MacosListener = unsafe { &*(observer as *const MacosListener) };
+    listener.refresh_input_source();
+}
 
-        let Some(app) = app.try_cast(ns::RunningApp::cls()) else {
-            return println!("wrong app class");
-        };
+// Input event taps (CoreGraphics), not covered by cidre
+#[link(name = "CoreGraphics", kind = "framework")]
+unsafe extern "C" {
+    fn CGPreflightListenEventAccess() -> bool;
+    fn CGRequestListenEventAccess() -> bool;
+    fn CGEventTapCreate(
+        tap: u32,
+        place: u32,
+        options: u32,
+        events_of_interest: u64,
+        callback: extern "C" fn(*const c_void, u32, *const c_void, *mut c_void) -> *const c_void,
+        user_info: *mut c_void,
+    ) -> *mut c_void;
+    fn CGEventTapEnable(tap: *mut c_void, enable: bool);
+    fn CGEventGetIntegerValueField(event: *const c_void, field: u32) -> i64;
+    fn CGEventGetFlags(event: *const c_void) -> u64;
+    fn CGEventGetLocation(event: *const c_void) -> CGPoint;
+    fn CGEventKeyboardGetUnicodeString(
+        event: *const c_void,
+        max_len: usize,
+        actual_len: *mut usize,
+        chars: *mut u16,
+    );
+}
 
-        let pid = app.pid();
+#[link(name = "CoreFoundation", kind = "framework")]
+unsafe extern "C" {
+    static kCFRunLoopCommonModes: *const c_void;
+    fn CFMachPortCreateRunLoopSource(
+        allocator: *const c_void,
+        port: *mut c_void,
+        order: isize,
+    ) -> *mut c_void;
+    fn CFRunLoopGetMain() -> *mut c_void;
+    fn CFRunLoopAddSource(run_loop: *mut c_void, source: *mut c_void, mode: *const c_void);
+    fn CFRunLoopStop(run_loop: *mut c_void);
+    fn CFAbsoluteTimeGetCurrent() -> f64;
+    fn CFRunLoopTimerCreate(
+        allocator: *const c_void,
+        fire_date: f64,
+        interval: f64,
+        flags: usize,
+        order: isize,
+        callout: extern "C" fn(*mut c_void, *mut c_void),
+        context: *mut CFRunLoopTimerContext,
+    ) -> *mut c_void;
+    fn CFRunLoopAddTimer(run_loop: *mut c_void, timer: *mut c_void, mode: *const c_void);
+}
 
-        let app_name = app.localized_name().map(|s| s.to_string());
-        info!(app_name = ?app_name, pid, "activated app");
+#[repr(C)]
+struct CFRunLoopTimerContext {
+    version: isize,
+    info: *mut c_void,
+    retain: Option<extern "C" fn(*const c_void) -> *const c_void>,
+    release: Option<extern "C" fn(*const c_void)>,
+    copy_description: Option<extern "C" fn(*const c_void) -> *const c_void>,
+}
 
-        // --- Send ApplicationActivated Event ---
-        let event = UiEvent {
-            event_type: EventType::ApplicationActivated,
-            timestamp: Utc::now(),
-            application: Some(ApplicationInfo {
-                name: app_name,
-                pid: Some(pid),
-            }),
-            window: None,
-            element: None,
-            event_specific_data: None,
-        };
-        if let Err(e) = self.tx.try_send(event) {
-            error!(error = %e, "failed to send activation event");
-        }
+// Callback of a run_later timer; taken when it fires
+type TimerCallback = Option<Box<dyn FnOnce()>>;
 
-        {
-            // release current observer
-            self.ax_observer.lock().unwrap().take();
-        }
+extern "C" fn timer_fired(_timer: *mut c_void, info: *mut c_void) {
+    debug_assert!(ns::Thread::is_main(), "run_later timer off the main thread");
+    let callback = unsafe { &mut *(info as *mut TimerCallback) };
+    if let Some(callback) = callback.take() {
+        callback();
+    }
+}
 
-        let app_element = ax::UiElement::with_app_pid(pid);
+extern "C" fn release_timer_callback(info: *const c_void) {
+    drop(unsafe { Box::from_raw(info as *mut TimerCallback) });
+}
 
-        // Retry Observer::new_for_app
-        match ax::Observer::with_cb(pid, observer_callback2) {
-            Ok(mut observer) => {
-                // observer should be Retained<ax::Observer>
+// Run `callback` on the main run loop once `delay` has passed, without blocking the loop in
+// the meantime. The main loop is the one that runs however the listener was started, and the
+// one its observers are attached to. The one-shot timer is released by the run loop after it
+// fires, which frees the callback.
+fn run_later(delay: Duration, callback: impl FnOnce() + 'static) {
+    let info = Box::into_raw(Box::new(Some(Box::new(callback) as Box<dyn FnOnce()>)));
+    let mut context = CFRunLoopTimerContext {
+        version: 0,
+        info: info.cast(),
+        retain: None,
+        release: Some(release_timer_callback),
+        copy_description: None,
+    };
+    unsafe {
+        let timer = CFRunLoopTimerCreate(
+            std::ptr::null(),
+            CFAbsoluteTimeGetCurrent() + delay.as_secs_f64(),
+            0.0,
+            0,
+            0,
+            timer_fired,
+            &mut context,
+        );
+        CFRunLoopAddTimer(CFRunLoopGetMain(), timer, kCFRunLoopCommonModes);
+        // The run loop holds the timer until it fires
+        CFRelease(timer);
+    }
+}
+
+// libdispatch (part of libSystem)
+unsafe extern "C" {
+    // The main queue; dispatch_get_main_queue() is a macro returning its address
+    static _dispatch_main_q: u8;
+    fn dispatch_async_f(
+        queue: *const c_void,
+        context: *mut c_void,
+        work: extern "C" fn(*mut c_void),
+    );
+    fn dispatch_sync_f(
+        queue: *const c_void,
+        context: *mut c_void,
+        work: extern "C" fn(*mut c_void),
+    );
+}
+
+type MainThreadWork = Box<dyn FnOnce() + Send>;
+
+extern "C" fn main_thread_work(context: *mut c_void) {
+    let work = unsafe { Box::from_raw(context as *mut MainThreadWork) };
+    work();
+}
+
+/// Runs `work` on the main thread: right away when called there, else queued on the main
+/// dispatch queue, which the main run loop drains. With `wait`, returns once it has run, so
+/// the main thread must not be waiting on the caller.
+pub fn run_on_main_thread(wait: bool, work: impl FnOnce() + Send + 'static) {
+    if ns::Thread::is_main() {
+        return work();
+    }
+    let context = Box::into_raw(Box::new(Box::new(work) as MainThreadWork)).cast();
+    unsafe {
+        let queue = &raw const _dispatch_main_q as *const c_void;
+        if wait {
+            dispatch_sync_f(queue, context, main_thread_work);
+        } else {
+            dispatch_async_f(queue, context, main_thread_work);
+        }
+    }
+}
+
+/// Accessibility permission status, without prompting.
+pub fn accessibility_granted() -> bool {
+    ax::is_process_trusted_with_prompt(false)
+}
+
+/// Accessibility permission status, prompting the user when it is missing.
+pub fn request_accessibility() -> bool {
+    ax::is_process_trusted_with_prompt(true)
+}
+
+/// Makes `ns::App::run` return on the main thread. Callable from any thread.
+pub fn stop_main_run_loop() {
+    ns::App::shared().stop(None);
+    // `stop` is only noticed once the run loop wakes up
+    unsafe { CFRunLoopStop(CFRunLoopGetMain()) };
+}
+
+#[repr(C)]
+struct CGPoint {
+    x: f64,
+    y: f64,
+}
+
+#[repr(C)]
+struct CGSize {
+    width: f64,
+    height: f64,
+}
+
+#[repr(C)]
+struct CGRect {
+    origin: CGPoint,
+    size: CGSize,
+}
+
+// kCGSessionEventTap, kCGHeadInsertEventTap, kCGEventTapOptionListenOnly
+const SESSION_EVENT_TAP: u32 = 1;
+const HEAD_INSERT_EVENT_TAP: u32 = 0;
+const TAP_LISTEN_ONLY: u32 = 1;
+// CGEventType values the tap listens to
+const LEFT_MOUSE_DOWN_EVENT: u32 = 1;
+const RIGHT_MOUSE_DOWN_EVENT: u32 = 3;
+const MOUSE_MOVED_EVENT: u32 = 5;
+const LEFT_MOUSE_DRAGGED_EVENT: u32 = 6;
+const RIGHT_MOUSE_DRAGGED_EVENT: u32 = 7;
+const KEY_DOWN_EVENT: u32 = 10;
+const OTHER_MOUSE_DOWN_EVENT: u32 = 25;
+const OTHER_MOUSE_DRAGGED_EVENT: u32 = 27;
+const INPUT_TAP_EVENTS: [u32; 8] = [
+    LEFT_MOUSE_DOWN_EVENT,
+    RIGHT_MOUSE_DOWN_EVENT,
+    MOUSE_MOVED_EVENT,
+    LEFT_MOUSE_DRAGGED_EVENT,
+    RIGHT_MOUSE_DRAGGED_EVENT,
+    KEY_DOWN_EVENT,
+    OTHER_MOUSE_DOWN_EVENT,
+    OTHER_MOUSE_DRAGGED_EVENT,
+];
+// Sent to the callback when the system disabled the tap (slow callback, user input)
+const TAP_DISABLED_BY_TIMEOUT: u32 = 0xFFFF_FFFE;
+const TAP_DISABLED_BY_USER_INPUT: u32 = 0xFFFF_FFFF;
+// kCGKeyboardEventAutorepeat, kCGKeyboardEventKeycode
+const KEYBOARD_AUTOREPEAT_FIELD: u32 = 8;
+const KEYBOARD_KEYCODE_FIELD: u32 = 9;
+// kCGMouseEventClickState, kCGMouseEventButtonNumber
+const MOUSE_CLICK_STATE_FIELD: u32 = 1;
+const MOUSE_BUTTON_NUMBER_FIELD: u32 = 3;
+// CGEventFlags masks, in the order modifiers are listed
+const KEY_MODIFIER_FLAGS: [(u64, &str); 6] = [
+    (0x0001_0000, "caps_lock"),
+    (0x0002_0000, "shift"),
+    (0x0004_0000, "control"),
+    (0x0008_0000, "option"),
+    (0x0010_0000, "command"),
+    (0x0080_0000, "fn"),
+];
+
+// Keycode, typed characters and modifiers of a key event. The characters are what the user
+// typed, so they are only read with `capture_values`.
+fn key_event_data(event: *const c_void, capture_values: bool) -> serde_json::Value {
+    let (keycode, repeat, flags) = unsafe {
+        (
+            CGEventGetIntegerValueField(event, KEYBOARD_KEYCODE_FIELD),
+            CGEventGetIntegerValueField(event, KEYBOARD_AUTOREPEAT_FIELD) != 0,
+            CGEventGetFlags(event),
+        )
+    };
+    let modifiers: Vec<&str> = KEY_MODIFIER_FLAGS
+        .iter()
+        .filter(|(mask, _)| flags & mask != 0)
+        .map(|(_, name)| *name)
+        .collect();
+    let mut data = serde_json::json!({
+        "keycode": keycode,
+        "modifiers": modifiers,
+        "repeat": repeat,
+    });
+    if capture_values {
+        let mut chars = [0u16; 8];
+        let mut len = 0;
+        unsafe {
+            CGEventKeyboardGetUnicodeString(event, chars.len(), &mut len, chars.as_mut_ptr())
+        };
+        data["characters"] = String::from_utf16_lossy(&chars[..len.min(chars.len())]).into();
+    }
+    data
+}
+
+// Button and click count (1 single, 2 double, ...) of a mouse down
+fn mouse_click_data(event: *const c_void) -> serde_json::Value {
+    let (button, click_count) = unsafe {
+        (
+            CGEventGetIntegerValueField(event, MOUSE_BUTTON_NUMBER_FIELD),
+            CGEventGetIntegerValueField(event, MOUSE_CLICK_STATE_FIELD),
+        )
+    };
+    let button = match button {
+        0 => "left".to_string(),
+        1 => "right".to_string(),
+        2 => "middle".to_string(),
+        n => format!("button{}", n),
+    };
+    serde_json::json!({ "button": button, "click_count": click_count })
+}
+
+extern "C" fn input_tap_callback(
+    _proxy: *const c_void,
+    event_type: u32,
+    event: *const c_void,
+    user_info: *mut c_void,
+) -> *const c_void {
+    let listener: &MacosListener = unsafe { &*(user_info as *const MacosListener) };
+    match event_type {
+        KEY_DOWN_EVENT => listener.handle_key_down(event),
+        LEFT_MOUSE_DOWN_EVENT | RIGHT_MOUSE_DOWN_EVENT | OTHER_MOUSE_DOWN_EVENT => {
+            listener.handle_mouse(event, EventType::MouseClicked)
+        }
+        MOUSE_MOVED_EVENT
+        | LEFT_MOUSE_DRAGGED_EVENT
+        | RIGHT_MOUSE_DRAGGED_EVENT
+        | OTHER_MOUSE_DRAGGED_EVENT => listener.handle_mouse(event, EventType::MouseMoved),
+        TAP_DISABLED_BY_TIMEOUT | TAP_DISABLED_BY_USER_INPUT => {
+            warn!("input event tap was disabled by the system, re-enabling");
+            let tap = listener.input_tap.load(Ordering::Relaxed);
+            if !tap.is_null() {
+                unsafe { CGEventTapEnable(tap, true) };
+            }
+        }
+        _ => {}
+    }
+    // Listen-only tap: the event is passed on unchanged
+    event
+}
+
+// Helper to get the direct children of an element
+fn get_children(element: &ax::UiElement) -> Vec<arc::R<ax::UiElement>> {
+    get_element_array(element, &attr_named("AXChildren"))
+}
+
+// Helper to get an attribute holding an array of elements (AXChildren, AXSelectedRows, ...)
+fn get_element_array(element: &ax::UiElement, attribute: &ax::Attr) -> Vec<arc::R<ax::UiElement>> {
+    ar_pool(|| {
+        read_attr(element, attribute)
+            .filter(|val| val.get_type_id() == cf::Array::type_id())
+            .map(|val| {
+                let arr_ptr = &*val as *const cf::Type as *const cf::ArrayOf<ax::UiElement>;
+                unsafe { &*arr_ptr }
+                    .iter()
+                    .map(|child| child.retained())
+                    .collect()
+            })
+            .unwrap_or_default()
+    })
+}
+
+fn is_sheet(element: &ax::UiElement) -> bool {
+    ar_pool(|| {
+        let role = element.role().ok().map(|r| r.to_string());
+        let subrole = get_string_attribute(element, &attr_named("AXSubrole"));
+        role.as_deref() == Some(SHEET_ROLE) || subrole.as_deref() == Some(SHEET_ROLE)
+    })
+}
+
+// Static texts and button titles inside a sheet, so consumers can tell what it asks
+fn sheet_summary(sheet: &ax::UiElement, budget: Duration) -> serde_json::Value {
+    let deadline = Instant::now() + budget;
+    let mut truncated = false;
+    let mut texts = Vec::new();
+    let mut buttons = Vec::new();
+    let mut queue = vec![(sheet.retained(), 0)];
+    while let Some((el, depth)) = queue.pop() {
+        if texts.len() >= SHEET_SUMMARY_ITEMS && buttons.len() >= SHEET_SUMMARY_ITEMS {
+            break;
+        }
+        if Instant::now() >= deadline {
+            truncated = true;
+            break;
+        }
+        let role = ar_pool(|| el.role().ok().map(|r| r.to_string()));
+        match role.as_deref() {
+            Some("AXStaticText") if texts.len() < SHEET_SUMMARY_ITEMS => {
+                let text = ar_pool(|| {
+                    el.attr_value(ax::attr::value())
+                        .ok()
+                        .and_then(|val| cf_value_to_json(&*val))
+                });
+                if let Some(serde_json::Value::String(text)) = text {
+                    texts.push(text);
+                }
+            }
+            Some("AXButton") if buttons.len() < SHEET_SUMMARY_ITEMS => {
+                if let Some(title) = get_string_attribute(&el, ax::attr::title()) {
+                    buttons.push(title);
+                }
+            }
+            _ => {}
+        }
+        if depth < SHEET_SUMMARY_DEPTH {
+            // Reverse so the traversal follows on-screen order
+            queue.extend(get_children(&el).into_iter().rev().map(|c| (c, depth + 1)));
+        }
+    }
+    serde_json::json!({
+        "texts": texts,
+        "buttons": buttons,
+        "truncated": truncated,
+    })
+}
+
+// Build a parameterized attribute name that `cidre` does not expose as a constant
+fn param_attr_named(name: &str) -> arc::R<ax::ParamAttr> {
+    // ax::ParamAttr is a thin wrapper around cf::String
+    unsafe { std::mem::transmute(cf::String::from_str(name)) }
+}
+
+// Helper to read a parameterized attribute (e.g. AXBoundsForRange) as an AXValue
+fn get_parameterized_ax_value(
+    element: &ax::UiElement,
+    attribute: &ax::ParamAttr,
+    param: &cf::Type,
+) -> Option<arc::R<ax::Value>> {
+    element
+        .param_attr_value(attribute, param)
+        .ok()
+        .and_then(|val| {
+            if val.get_type_id() == ax::Value::type_id() {
+                let value_ptr = &*val as *const cf::Type as *const ax::Value;
+                Some(unsafe { &*value_ptr }.retained())
+            } else {
+                None
+            }
+        })
+}
+
+// Whether a core notification can produce an event type the config captures. Focus changes
+// and element destruction are always observed: focus tracking, value commits and sheet
+// closing rely on them.
+fn notification_wanted(config: &ListenerConfig, n: &ax::Notification) -> bool {
+    use ax::notification as axn;
+    let produces = match () {
+        _ if n == axn::focused_window_changed() => vec![EventType::WindowFocused],
+        _ if n == axn::value_changed() => vec![EventType::ValueChanged],
+        _ if n == axn::window_created() => vec![EventType::WindowCreated, EventType::SheetOpened],
+        _ if n == axn::window_moved() => vec![EventType::WindowMoved],
+        _ if n == axn::window_resized() => vec![EventType::WindowResized],
+        // Menu state decides whether a selected item was picked via its shortcut
+        _ if n == axn::menu_opened() || n == axn::menu_closed() => vec![
+            EventType::MenuOpened,
+            EventType::MenuClosed,
+            EventType::MenuItemSelected,
+        ],
+        _ if n == axn::menu_item_selected() => vec![EventType::MenuItemSelected],
+        _ if n == axn::selected_text_changed() => {
+            vec![EventType::SelectedTextChanged, EventType::CaretMoved]
+        }
+        _ if n == axn::title_changed() => vec![EventType::TitleChanged],
+        _ if n == axn::selected_children_changed() => vec![EventType::SelectionChanged],
+        _ if n.equal(&notification_named(LOAD_COMPLETE_NOTIFICATION)) => {
+            vec![EventType::PageLoaded]
+        }
+        _ if n.equal(&notification_named(SHEET_CREATED_NOTIFICATION)) => {
+            vec![EventType::SheetOpened]
+        }
+        _ if n.equal(&notification_named(SELECTED_ROWS_CHANGED_NOTIFICATION)) => {
+            vec![EventType::SelectionChanged, EventType::ItemReordered]
+        }
+        _ => return true,
+    };
+    produces
+        .iter()
+        .any(|event_type| config.captures_event_type(event_type))
+}
+
+// AXSelectedTextRange of a text element; None for elements without text
+fn selected_range(element: &ax::UiElement) -> Option<cf::Range> {
+    element
+        .attr_value(&attr_named("AXSelectedTextRange"))
+        .ok()
+        .and_then(|val| {
+            if val.get_type_id() == ax::Value::type_id() {
+                let value_ptr = &*val as *const cf::Type as *const ax::Value;
+                unsafe { &*value_ptr }.cf_range()
+            } else {
+                None
+            }
+        })
+}
+
+// Selection of a text element for SelectedTextChanged: range, selected text, and the line the
+// selection starts on (number and text). Text is only read with `capture_text`; None for
+// elements without a selection range.
+fn selection_data(element: &ax::UiElement, capture_text: bool) -> Option<serde_json::Value> {
+    ar_pool(|| {
+        let range = selected_range(element)?;
+        let mut data = serde_json::json!({
+            "range": { "location": range.loc, "length": range.len },
+        });
+
+        let index = cf::Number::from_i64(range.loc as i64);
+        let line = element
+            .param_attr_value(&param_attr_named("AXLineForIndex"), &index)
+            .ok()
+            .filter(|val| val.get_type_id() == cf::Number::type_id())
+            .and_then(|val| {
+                let n_ptr = &*val as *const cf::Type as *const cf::Number;
+                unsafe { &*n_ptr }.to_i64()
+            });
+        if let Some(line) = line {
+            data["line"] = serde_json::json!({ "number": line });
+        }
+        if !capture_text {
+            return Some(data);
+        }
+
+        data["selected_text"] = string_attr_quiet(element, &attr_named("AXSelectedText")).into();
+        let line_text = line
+            .and_then(|line| {
+                let line = cf::Number::from_i64(line);
+                get_parameterized_ax_value(element, &param_attr_named("AXRangeForLine"), &line)?
+                    .cf_range()
+            })
+            .and_then(|line_range| {
+                let param = ax::Value::with_cf_range(&line_range);
+                let val = element
+                    .param_attr_value(&param_attr_named("AXStringForRange"), &param)
+                    .ok()?;
+                if val.get_type_id() != cf::String::type_id() {
+                    return None;
+                }
+                let s_ptr = &*val as *const cf::Type as *const cf::String;
+                Some(unsafe { &*s_ptr }.to_string())
+            });
+        if let Some(text) = line_text {
+            data["line"]["text"] = text.trim_end_matches('\n').into();
+        }
+        Some(data)
+    })
+}
+
+// Caret range and screen rect of a text element, from AXSelectedTextRange + AXBoundsForRange
+// `scale` converts the rect from points (1.0) to pixels (backing scale factor)
+fn caret_data(element: &ax::UiElement, scale: f64) -> Option<serde_json::Value> {
+    ar_pool(|| {
+        let range = selected_range(element)?;
+
+        // Bounds of an empty range is the caret itself
+        let param = ax::Value::with_cf_range(&cf::Range {
+            loc: range.loc,
+            len: 0,
+        });
+        let rect =
+            get_parameterized_ax_value(element, &param_attr_named("AXBoundsForRange"), &param)?
+                .cg_rect()?;
+
+        Some(serde_json::json!({
+            "location": range.loc,
+            "length": range.len,
+            "position": Position {
+                x: rect.origin.x,
+                y: rect.origin.y,
+            }
+            .scaled(scale),
+            "size": Size {
+                width: rect.size.width,
+                height: rect.size.height,
+            }
+            .scaled(scale),
+        }))
+    })
+}
+
+// Backing scale factor of the screen containing `point` (AX coordinates, top-left origin)
+fn backing_scale_at(point: &Position) -> Option<f64> {
+    ar_pool(|| {
+        let screens = ns::Screen::screens();
+        // AX uses a flipped coordinate space anchored at the primary screen's top-left
+        let primary_height = screens.iter().next()?.frame().size.height;
+        let (x, y) = (point.x, primary_height - point.y);
+        screens
+            .iter()
+            .find(|screen| {
+                let frame = screen.frame();
+                x >= frame.origin.x
+                    && x < frame.origin.x + frame.size.width
+                    && y > frame.origin.y
+                    && y <= frame.origin.y + frame.size.height
+            })
+            .map(|screen| screen.backing_scale_factor())
+    })
+}
+
+// Actions the element supports (AXPress, AXIncrement, AXShowMenu, ...), None if there are none
+fn get_action_names(element: &ax::UiElement) -> Option<Vec<String>> {
+    ar_pool(|| {
+        let names: Vec<String> = element
+            .action_names()
+            .ok()?
+            .iter()
+            .map(|name| name.to_string())
+            .collect();
+        (!names.is_empty()).then_some(names)
+    })
+}
+
+// Build an attribute name that `cidre` does not expose as a constant
+fn attr_named(name: &str) -> arc::R<ax::Attr> {
+    // ax::Attr is a thin wrapper around cf::String
+    unsafe { std::mem::transmute(cf::String::from_str(name)) }
+}
+
+// Build a notification name that `cidre` does not expose as a constant
+fn notification_named(name: &str) -> arc::R<ax::Notification> {
+    // ax::Notification is a thin wrapper around cf::String
+    unsafe { std::mem::transmute(cf::String::from_str(name)) }
+}
+
+// Titles of the items owning `menu` and the menus above it, outermost first, e.g.
+// `["File", "Open Recent"]` for the Open Recent submenu. Empty for context menus.
+fn menu_path(menu: &ax::UiElement) -> Vec<String> {
+    ar_pool(|| {
+        let mut path = Vec::new();
+        let mut current = menu.parent().ok();
+        for _ in 0..MENU_PATH_MAX_DEPTH {
+            let Some(element) = current else {
+                break;
+            };
+            let role = element.role().ok().map(|r| r.to_string());
+            match role.as_deref() {
+                Some("AXMenu") => {}
+                Some(role) if MENU_OWNER_ROLES.contains(&role) => path
+                    .push(string_attr_quiet(&element, &attr_named("AXTitle")).unwrap_or_default()),
+                _ => break,
+            }
+            current = element.parent().ok();
+        }
+        path.reverse();
+        path
+    })
+}
+
+// Menu details for MenuOpened / MenuClosed (`menu` is the AXMenu)
+fn menu_data(menu: &ax::UiElement) -> serde_json::Value {
+    let path = menu_path(menu);
+    serde_json::json!({
+        "menu_title": path.last(),
+        "menu_path": path,
+    })
+}
+
+// Menu details for MenuItemSelected: the item's title and position in its menu (separators
+// count), and the menu it is in
+fn menu_item_data(item: &ax::UiElement) -> serde_json::Value {
+    ar_pool(|| {
+        let menu = item.parent().ok();
+        let path = menu.as_deref().map(menu_path).unwrap_or_default();
+        let index = menu
+            .as_deref()
+            .and_then(|menu| get_children(menu).iter().position(|c| c.equal(item)));
+        serde_json::json!({
+            "item_title": string_attr_quiet(item, &attr_named("AXTitle")),
+            "item_index": index,
+            "menu_title": path.last(),
+            "menu_path": path,
+        })
+    })
+}
+
+// Key equivalent of a menu item in menu notation, e.g. "⇧⌘S". Items whose shortcut is only
+// a glyph (arrows, function keys) or that have none give None.
+fn menu_item_shortcut(item: &ax::UiElement) -> Option<String> {
+    let key = get_string_attribute(item, &attr_named("AXMenuItemCmdChar"))
+        .filter(|key| !key.is_empty())?;
+    let modifiers =
+        get_number_attribute(item, &attr_named("AXMenuItemCmdModifiers")).map_or(0, |m| m as u32);
+
+    let mut shortcut = String::new();
+    if modifiers & MENU_MODIFIER_CONTROL != 0 {
+        shortcut.push('⌃');
+    }
+    if modifiers & MENU_MODIFIER_OPTION != 0 {
+        shortcut.push('⌥');
+    }
+    if modifiers & MENU_MODIFIER_SHIFT != 0 {
+        shortcut.push('⇧');
+    }
+    if modifiers & MENU_MODIFIER_NO_COMMAND == 0 {
+        shortcut.push('⌘');
+    }
+    shortcut.push_str(&key);
+    Some(shortcut)
+}
+
+// Helper to get a numeric attribute (e.g. AXMinValue) as f64
+fn get_number_attribute(element: &ax::UiElement, attribute: &ax::Attr) -> Option<f64> {
+    ar_pool(|| {
+        read_attr(element, attribute)
+            .and_then(|val| cf_value_to_json(&*val))
+            .and_then(|json| json.as_f64())
+    })
+}
+
+// Last known value per element, keyed by the element's CFHash.
+// Used to diff consecutive ValueChanged notifications.
+#[derive(Default)]
+struct ValueCache {
+    values: HashMap<cf::HashCode, serde_json::Value>,
+}
+
+impl ValueCache {
+    // Store the new value and return the previous one, if any
+    fn replace(
+        &mut self,
+        element: &ax::UiElement,
+        value: Option<serde_json::Value>,
+    ) -> Option<serde_json::Value> {
+        let key = element.hash();
+        match value {
+            Some(value) => {
+                if self.values.len() >= VALUE_CACHE_CAPACITY && !self.values.contains_key(&key) {
+                    self.values.clear();
+                }
+                self.values.insert(key, value)
+            }
+            None => self.values.remove(&key),
+        }
+    }
+
+    fn remove(&mut self, element: &ax::UiElement) {
+        self.values.remove(&element.hash());
+    }
+
+    fn clear(&mut self) {
+        self.values.clear();
+    }
+}
+
+// Min/max bounds of a ranged control (slider, stepper, progress bar)
+fn get_value_bounds(element: &ax::UiElement) -> (Option<f64>, Option<f64>) {
+    (
+        get_number_attribute(element, &attr_named("AXMinValue")),
+        get_number_attribute(element, &attr_named("AXMaxValue")),
+    )
+}
+
+// Position of `value` within [min, max] as 0..1, None if the range is unknown or empty
+fn normalize_value(value: f64, min: Option<f64>, max: Option<f64>) -> Option<f64> {
+    let (min, max) = (min?, max?);
+    if max <= min {
+        return None;
+    }
+    Some(((value - min) / (max - min)).clamp(0.0, 1.0))
+}
+
+// Work out whether a stepper went up or down.
+// A jump from max to min (or min to max) is treated as a wrap-around in the opposite direction.
+// Returns None for non-numeric or unchanged values.
+fn stepper_direction(
+    previous: &serde_json::Value,
+    current: &serde_json::Value,
+    min: Option<f64>,
+    max: Option<f64>,
+) -> Option<&'static str> {
+    let as_number = |v: &serde_json::Value| {
+        v.as_f64()
+            .or_else(|| v.as_str().and_then(|s| s.trim().parse::<f64>().ok()))
+    };
+    let (old, new) = (as_number(previous)?, as_number(current)?);
+    if old == new {
+        return None;
+    }
+    if let (Some(min), Some(max)) = (min, max) {
+        if old == max && new == min {
+            return Some("incremented");
+        }
+        if old == min && new == max {
+            return Some("decremented");
+        }
+    }
+    Some(if new > old {
+        "incremented"
+    } else {
+        "decremented"
+    })
+}
+
+// Key clients can use to refer back to an element: "<pid>:<cf hash>"
+fn element_key(element: &ax::UiElement) -> String {
+    let pid = element.pid().unwrap_or_default();
+    format!("{}:{:x}", pid, element.hash())
+}
+
+// Recently seen elements by element_key, oldest evicted first
+#[derive(Default)]
+struct ElementRegistry {
+    elements: HashMap<String, arc::R<ax::UiElement>>,
+    order: VecDeque<String>,
+}
+
+impl ElementRegistry {
+    fn remember(&mut self, key: String, element: &ax::UiElement) {
+        if self
+            .elements
+            .insert(key.clone(), element.retained())
+            .is_some()
+        {
+            return;
+        }
+        self.order.push_back(key);
+        if self.order.len() > DESCRIBE_REGISTRY_CAPACITY {
+            if let Some(oldest) = self.order.pop_front() {
+                self.elements.remove(&oldest);
+            }
+        }
+    }
+
+    fn get(&self, key: &str) -> Option<arc::R<ax::UiElement>> {
+        self.elements.get(key).map(|el| el.retained())
+    }
+}
+
+// Every attribute and action of an element, for the describe query
+fn describe_element(element: &ax::UiElement) -> Result<serde_json::Value, String> {
+    ar_pool(|| {
+        let names = element
+            .attr_names()
+            .map_err(|e| format!("element no longer available: {:?}", e))?;
+        let role = element.role().ok().map(|r| r.to_string());
+        let secure = is_secure_text_field(element, role.as_deref());
+        let mut attributes = serde_json::Map::new();
+        for name in names.iter() {
+            if secure && name.to_string() == "AXValue" {
+                attributes.insert(name.to_string(), serde_json::Value::Null);
+                continue;
+            }
+            let value = match element.attr_value(name) {
+                Ok(val) => cf_value_to_json(&*val)
+                    .unwrap_or_else(|| serde_json::Value::String(format!("{:?}", val.desc()))),
+                Err(_) => serde_json::Value::Null,
+            };
+            attributes.insert(name.to_string(), value);
+        }
+        Ok(serde_json::json!({
+            "attributes": attributes,
+            "actions": get_action_names(element).unwrap_or_default(),
+        }))
+    })
+}
+
+// The whole text of a text field or area. Some apps report a shortened or non-string
+// AXValue; when AXNumberOfCharacters says there is more, the text is read by range instead.
+fn full_text(
+    element: &ax::UiElement,
+    value: Option<serde_json::Value>,
+) -> Option<serde_json::Value> {
+    let Some(length) = get_number_attribute(element, &attr_named("AXNumberOfCharacters")) else {
+        return value;
+    };
+    let length = length as isize;
+    // AX lengths count UTF-16 code units
+    let complete = value
+        .as_ref()
+        .and_then(|v| v.as_str())
+        .is_some_and(|text| text.encode_utf16().count() as isize >= length);
+    if complete || length <= 0 {
+        return value;
+    }
+    let text = ar_pool(|| {
+        let param = ax::Value::with_cf_range(&cf::Range {
+            loc: 0,
+            len: length,
+        });
+        let val = element
+            .param_attr_value(&param_attr_named("AXStringForRange"), &param)
+            .ok()?;
+        if val.get_type_id() != cf::String::type_id() {
+            return None;
+        }
+        let s_ptr = &*val as *const cf::Type as *const cf::String;
+        Some(unsafe { &*s_ptr }.to_string())
+    });
+    text.map(serde_json::Value::String).or(value)
+}
+
+// Cut a string value to `max` characters; whether it was cut
+fn truncate_value(details: &mut Option<ElementDetails>, max: usize) -> bool {
+    let Some(serde_json::Value::String(text)) = details.as_mut().and_then(|d| d.value.as_mut())
+    else {
+        return false;
+    };
+    match text.char_indices().nth(max) {
+        Some((end, _)) => {
+            text.truncate(end);
+            true
+        }
+        None => false,
+    }
+}
+
+// Enhanced helper - NOT wrapped entirely in ar_pool anymore
+// Values are only read with `capture_values`, and never from password fields
+fn extract_event_data(
+    element: &ax::UiElement,
+    capture_values: bool,
+    attributes: AttributeMask,
+) -> Result<(
+    Option<ApplicationInfo>,
+    Option<WindowInfo>,
+    Option<ElementDetails>,
+)> {
+    let pid = element.pid().ok(); // Use ok() to handle potential error
+
+    // --- Application Info ---
+    let app_info: Option<ApplicationInfo> = pid.and_then(|p| {
+        ar_pool(|| {
+            // Pool for NS object access
+            let app = ns::running_application::RunningApp::with_pid(p);
+            Some(app.map(|a| app_info(&a)).unwrap_or(ApplicationInfo {
+                pid: Some(p),
+                ..Default::default()
+            }))
+        })
+    });
+
+    // --- Window Info ---
+    // ar_pool might be needed here due to element access & retention
+    let window_info: Option<WindowInfo> = ar_pool(|| {
+        // 1. Check if the element itself is the window
+        let mut window_element = element
+            .role()
+            .ok()
+            .filter(|r| r.equal(ax::role::window()))
+            .map(|_| element.retained()); // Retain if it's a window
+
+        // 2. If not, traverse parents
+        if window_element.is_none() {
+            window_element = std::iter::successors(Some(element.retained()), |el| el.parent().ok())
+                .find(|el| {
+                    el.role()
+                        .map(|r| r.equal(ax::role::window()))
+                        .unwrap_or(false) // Default to false if role fetch fails
+                });
+        }
+
+        // 3. If still no window, try getting the app's focused window
+        if window_element.is_none() {
+            if let Some(p) = pid {
+                // Requires access to AX API inside pool
+                let app_element = ax::UiElement::with_app_pid(p);
+                window_element = app_element
+                    .attr_value(ax::attr::focused_window())
+                    .ok()
+                    .and_then(|val| {
+                        // The attribute value should be an AXUIElement
+                        if val.get_type_id() == ax::UiElement::type_id() {
+                            let win_ptr = &*val as *const cf::Type as *const ax::UiElement;
+                            Some(unsafe { &*win_ptr }.retained()) // Retain the window element
+                        } else {
+                            None
+                        }
+                    });
+            }
+        }
+
+        // Extract title and frame if we found a window element
+        window_element.map(|win| window_info_of(&win))
+        // Note: win (Retained<UiElement>) goes out of scope here, pool handles release
+    });
+
+    // --- Element Details ---
+    // These helpers use ar_pool internally
+    // Also needed to recognize secure text fields before reading the value
+    let capture_values = capture_values && attributes.contains(AttributeMask::VALUE);
+    let role = (attributes.contains(AttributeMask::ROLE) || capture_values)
+        .then(|| {
+            ar_pool(|| {
+                element
+                    .role()
+                    .map(|r| r.to_string())
+                    .map_err(|e| note_ax_failure("AXRole", &e))
+                    .ok()
+            })
+        })
+        .flatten();
+    let identifier = attributes
+        .contains(AttributeMask::IDENTIFIER)
+        .then(|| {
+            get_string_attribute(element, ax::attr::title())
+                .or_else(|| get_string_attribute(element, ax::attr::desc()))
+                .or_else(|| get_string_attribute(element, ax::attr::help()))
+        })
+        .flatten();
+    let value = if capture_values && !is_secure_text_field(element, role.as_deref()) {
+        let value = ar_pool(|| {
+            read_attr(element, ax::attr::value()).and_then(|cf_val| cf_value_to_json(&*cf_val))
+        });
+        if role
+            .as_deref()
+            .is_some_and(|role| FULL_TEXT_ROLES.contains(&role))
+        {
+            full_text(element, value)
+        } else {
+            value
+        }
+    } else {
+        None
+    };
+    let position = attributes
+        .contains(AttributeMask::POSITION)
+        .then(|| get_element_position(element))
+        .flatten();
+    let size = attributes
+        .contains(AttributeMask::SIZE)
+        .then(|| get_element_size(element))
+        .flatten();
+    // Read for the secure field check only
+    let role = role.filter(|_| attributes.contains(AttributeMask::ROLE));
+
+    let element_details = ElementDetails {
+        element_key: Some(element_key(element)),
+        normalized_role: role.as_deref().map(NormalizedRole::from_ax_role),
+        role,
+        identifier,
+        value,
+        position,
+        size,
+        path: attributes
+            .contains(AttributeMask::PATH)
+            .then(|| element_path(element)),
+        enabled: attributes
+            .contains(AttributeMask::ENABLED)
+            .then(|| get_bool_attribute(element, &attr_named("AXEnabled")))
+            .flatten(),
+        focused: attributes
+            .contains(AttributeMask::FOCUSED)
+            .then(|| get_bool_attribute(element, &attr_named("AXFocused")))
+            .flatten(),
+        children_count: attributes
+            .contains(AttributeMask::CHILDREN_COUNT)
+            .then(|| get_children(element).len()),
+        ..Default::default()
+    };
+
+    Ok((app_info, window_info, Some(element_details))) // Final Result constructed outside ar_pool
+}
+
+// Open menus, to tell clicked menu items from keyboard shortcuts
+#[derive(Default)]
+struct MenuState {
+    open: usize,
+    last_activity: Option<Instant>,
+}
+
+impl MenuState {
+    fn opened(&mut self) {
+        self.open += 1;
+        self.last_activity = Some(Instant::now());
+    }
+
+    fn closed(&mut self) {
+        self.open = self.open.saturating_sub(1);
+        self.last_activity = Some(Instant::now());
+    }
+
+    // Heuristic: shortcuts (Cmd-S) make AppKit select the item in a menu that is never
+    // shown, so no menu is open and none was opened or closed just before. Apps that flash
+    // the menu bar title or post menu notifications for shortcuts are reported as clicks.
+    fn selection_via_shortcut(&self) -> bool {
+        self.open == 0
+            && self
+                .last_activity
+                .is_none_or(|t| t.elapsed() > MENU_SHORTCUT_QUIET_PERIOD)
+    }
+}
+
+// Current interaction session, see ListenerConfig::interaction_gap
+#[derive(Default)]
+struct InteractionState {
+    id: u64,
+    // Element or window that had focus when the session started
+    focus: Option<cf::HashCode>,
+    last_event: Option<Instant>,
+}
+
+impl InteractionState {
+    // Session id for an element event. `focus` is the element's key for focus events
+    fn observe(&mut self, focus: Option<cf::HashCode>, gap: Duration) -> u64 {
+        let focus_moved = focus.is_some() && focus != self.focus;
+        let idle = self.last_event.is_none_or(|t| t.elapsed() > gap);
+        if focus_moved || idle {
+            self.id += 1;
+        }
+        if focus.is_some() {
+            self.focus = focus;
+        }
+        self.last_event = Some(Instant::now());
+        self.id
+    }
+}
+
+pub struct MacosListener {
+    config: ListenerConfig,
+    // Filters, holds back and sends the events
+    delivery: Arc<Delivery<cf::HashCode>>,
+    // Observer of the frontmost app, with its pid
+    ax_observer: Mutex<Option<(i32, arc::R<ax::Observer>)>>,
+    // Counts app activations, so an observer still retrying for an app that lost focus is
+    // dropped
+    activations: AtomicU64,
+    ws_observer_tokens: Mutex<Vec<arc::R<ns::Id>>>,
+    // Observer per running app (watch_all_apps), by pid
+    app_observers: Mutex<HashMap<i32, arc::R<ax::Observer>>>,
+    value_cache: Mutex<ValueCache>,
+    // When each progress indicator last produced a ValueChanged
+    progress_last_sent: Mutex<HashMap<cf::HashCode, Instant>>,
+    last_caret_event: Mutex<Option<Instant>>,
+    // Latest uncommitted ValueChanged per text element (value-on-commit mode)
+    pending_commits: Mutex<HashMap<cf::HashCode, UiEvent>>,
+    // Last known frame per window, for the deltas of WindowMoved/WindowResized
+    window_frames: Mutex<HashMap<cf::HashCode, (Position, Size)>>,
+    // Sheets currently open, with their parent window, to report SheetClosed
+    open_sheets: Mutex<HashMap<cf::HashCode, Option<WindowInfo>>>,
+    // Selected row and its index per table/outline, for reorder detection
+    row_positions: Mutex<HashMap<cf::HashCode, (cf::HashCode, i64)>>,
+    menu_state: Mutex<MenuState>,
+    // Elements that can be described over the control channel
+    registry: Mutex<ElementRegistry>,
+    // Selected keyboard input source, refreshed when it changes (include_input_source)
+    input_source: Mutex<Option<String>>,
+    interaction: Mutex<InteractionState>,
+    // Last focused element, a fallback trigger for menus
+    last_focus: Mutex<Option<(Instant, ElementDetails)>>,
+    // Input event tap (capture_input), to re-enable it when the system turns it off
+    input_tap: AtomicPtr<c_void>,
+    last_mouse_move: Mutex<Option<Instant>>,
+    // Set by shutdown(); ends the helper threads
+    stopped: AtomicBool,
+    // self reference pointer
+    ptr: *mut std::ffi::c_void,
+    _pin: PhantomPinned,
+}
+
+// all processing on main thread
+unsafe impl Send for MacosListener {}
+unsafe impl Sync for MacosListener {}
+
+impl MacosListener {
+    fn send(&self, event: UiEvent) {
+        self.send_keyed(event, None);
+    }
+
+    // Send an event produced for a specific element (`key` is its CFHash)
+    fn send_keyed(&self, event: UiEvent, key: Option<cf::HashCode>) {
+        self.delivery.send(event, key);
+    }
+
+    fn handle_ui_event(&self, element: &mut ax::UiElement, n: &ax::Notification) {
+        // Map AX notifications (cf::String constants) to our event types
+        use ax::notification as axn;
+        let event_type = match () {
+            _ if n == axn::focused_window_changed() => EventType::WindowFocused,
+            _ if n == axn::focused_ui_element_changed() => EventType::ElementFocused,
+            _ if n == axn::value_changed() => EventType::ValueChanged,
+            _ if n == axn::window_created() => EventType::WindowCreated,
+            _ if n == axn::window_moved() => EventType::WindowMoved,
+            _ if n == axn::window_resized() => EventType::WindowResized,
+            _ if n == axn::ui_element_destroyed() => EventType::ElementDestroyed,
+            _ if n == axn::menu_opened() => EventType::MenuOpened,
+            _ if n == axn::menu_closed() => EventType::MenuClosed,
+            _ if n == axn::menu_item_selected() => EventType::MenuItemSelected,
+            _ if n == axn::selected_text_changed() => EventType::SelectedTextChanged,
+            _ if n == axn::title_changed() => EventType::TitleChanged,
+            _ if n == axn::selected_children_changed() => EventType::SelectionChanged,
+            _ if n.equal(&notification_named(LOAD_COMPLETE_NOTIFICATION)) => EventType::PageLoaded,
+            _ if n.equal(&notification_named(SHEET_CREATED_NOTIFICATION)) => EventType::SheetOpened,
+            _ if n.equal(&notification_named(SELECTED_ROWS_CHANGED_NOTIFICATION)) => {
+                EventType::ItemReordered
+            }
+            // Only registered notifications arrive here, so anything unmapped was asked for
+            // (e.g. ListenerConfig::extra_notifications) and is passed on by name
+            _ => EventType::Custom {
+                name: n.to_string(),
+            },
+        };
+
+        // Row selection changes that reveal a move are reported as such, the others as
+        // plain selection changes
+        let (event_type, mut reorder_data) = match event_type {
+            EventType::ItemReordered => match self.row_move(element) {
+                Some(data) => (EventType::ItemReordered, Some(data)),
+                None => (EventType::SelectionChanged, None),
+            },
+            event_type => (event_type, None),
+        };
+
+        // Sheets arrive as AXSheetCreated or as a window creation with the sheet role
+        let event_type = match event_type {
+            EventType::WindowCreated if is_sheet(element) => EventType::SheetOpened,
+            EventType::ElementDestroyed
+                if self
+                    .open_sheets
+                    .lock()
+                    .unwrap()
+                    .contains_key(&element.hash()) =>
+            {
+                EventType::SheetClosed
+            }
+            event_type => event_type,
+        };
+
+        if matches!(event_type, EventType::ElementDestroyed) {
+            self.value_cache.lock().unwrap().remove(element);
+            self.progress_last_sent
+                .lock()
+                .unwrap()
+                .remove(&element.hash());
+            self.window_frames.lock().unwrap().remove(&element.hash());
+        }
+        if matches!(event_type, EventType::ValueChanged) && self.throttle_progress(element) {
+            return;
+        }
+
+        let via_shortcut = {
+            // lock scope
+            let mut menus = self.menu_state.lock().unwrap();
+            match event_type {
+                EventType::MenuOpened => menus.opened(),
+                EventType::MenuClosed => menus.closed(),
+                _ => {}
+            }
+            menus.selection_via_shortcut()
+        };
+
+        if self.config.diagnostics {
+            collect_ax_failures();
+        }
+
+        // Extract contextual data from the element
+        let extracted =
+            extract_event_data(&element, self.config.capture_values, self.config.attributes);
+        let diagnostics = self.config.diagnostics.then(|| {
+            let failures = take_ax_failures();
+            for failure in &failures {
+                debug!(
+                    %event_type,
+                    attribute = %failure.attribute,
+                    error = %failure.error,
+                    "ax attribute read failed"
+                );
+            }
+            failures
+        });
+
+        match extracted {
+            Ok((app_info, mut window_info, mut element_details)) => {
+                let truncated = self
+                    .config
+                    .max_value_length
+                    .is_some_and(|max| truncate_value(&mut element_details, max));
+
+                // The notification's element is the window. Don't rely on the generic lookup,
+                // which can fall back to the focused window while a new one is still coming
+                // up (role not readable yet, or created in the background), or while a
+                // background window is moved.
+                if matches!(
+                    event_type,
+                    EventType::WindowCreated | EventType::WindowMoved | EventType::WindowResized
+                ) {
+                    window_info = Some(window_info_of(element));
+                }
+
+                // Windows being focused/created/moved/resized are on the active Space
+                if matches!(
+                    event_type,
+                    EventType::WindowFocused
+                        | EventType::WindowCreated
+                        | EventType::WindowMoved
+                        | EventType::WindowResized
+                ) {
+                    if let Some(window) = window_info.as_mut() {
+                        window.space_id = active_space_id();
+                    }
+                }
+
+                let display = display_of(&window_info, &element_details);
+                self.apply_backing_scale(&mut window_info, &mut element_details);
+                if self.config.enable_describe {
+                    if let Some(key) = element_details.as_ref().and_then(|d| d.element_key.clone())
+                    {
+                        self.registry.lock().unwrap().remember(key, element);
+                    }
+                }
+                if self.config.include_actions {
+                    if let Some(details) = element_details.as_mut() {
+                        details.actions = get_action_names(element);
+                    }
+                }
+                if self.config.include_placeholder
+                    && matches!(event_type, EventType::ElementFocused)
+                {
+                    if let Some(details) = element_details.as_mut().filter(|d| {
+                        d.role
+                            .as_deref()
+                            .is_some_and(|role| TEXT_INPUT_ROLES.contains(&role))
+                    }) {
+                        details.placeholder =
+                            get_string_attribute(element, &attr_named("AXPlaceholderValue"));
+                    }
+                }
+
+                if let (EventType::ElementFocused, Some(details)) = (&event_type, &element_details)
+                {
+                    *self.last_focus.lock().unwrap() = Some((Instant::now(), details.clone()));
+                }
+
+                let mut event_specific_data = match (&event_type, &element_details) {
+                    (EventType::ValueChanged, Some(details)) => {
+                        self.value_change_data(element, details)
+                    }
+                    (EventType::PageLoaded, _) => Some(page_loaded_data(element, &window_info)),
+                    (EventType::SelectedTextChanged, details) => {
+                        let role = details.as_ref().and_then(|d| d.role.as_deref());
+                        let capture_text =
+                            self.config.capture_values && !is_secure_text_field(element, role);
+                        selection_data(element, capture_text)
+                    }
+                    (EventType::ItemReordered, _) => reorder_data.take(),
+                    (EventType::SelectionChanged, _) => Some(self.selection_change_data(element)),
+                    (EventType::WindowMoved | EventType::WindowResized, _) => window_info
+                        .as_ref()
+                        .and_then(|window| self.window_frame_change(element, window, &event_type)),
+                    (EventType::WindowCreated | EventType::WindowFocused, _) => {
+                        // Known frame for the delta of the first move/resize
+                        if let Some(window) = &window_info {
+                            self.window_frame_change(element, window, &event_type);
+                        }
+                        None
+                    }
+                    (EventType::MenuOpened, _) => {
+                        let mut data = menu_data(element);
+                        if let (
+                            serde_json::Value::Object(map),
+                            Some(serde_json::Value::Object(trigger)),
+                        ) = (&mut data, self.menu_trigger(element))
+                        {
+                            map.extend(trigger);
+                        }
+                        Some(data)
+                    }
+                    (EventType::MenuClosed, _) => Some(menu_data(element)),
+                    (EventType::MenuItemSelected, _) => {
+                        let mut data = menu_item_data(element);
+                        data["via_shortcut"] = via_shortcut.into();
+                        if let Some(shortcut) = menu_item_shortcut(element) {
+                            data["shortcut"] = shortcut.into();
+                        }
+                        Some(data)
+                    }
+                    (EventType::SheetOpened, _) => {
+                        if self
+                            .open_sheets
+                            .lock()
+                            .unwrap()
+                            .insert(element.hash(), window_info.clone())
+                            .is_some()
+                        {
+                            // Already reported through the other notification
+                            return;
+                        }
+                        Some(sheet_summary(element, self.config.traversal_budget))
+                    }
+                    (EventType::SheetClosed, _) => {
+                        // The destroyed sheet can no longer be queried for its parent
+                        if let Some(parent) =
+                            self.open_sheets.lock().unwrap().remove(&element.hash())
+                        {
+                            window_info = parent;
+                        }
+                        None
+                    }
+                    _ => None,
+                };
+
+                let interaction_id = self.config.interaction_gap.map(|gap| {
+                    let focus = matches!(
+                        event_type,
+                        EventType::ElementFocused | EventType::WindowFocused
+                    )
+                    .then(|| element.hash());
+                    self.interaction.lock().unwrap().observe(focus, gap)
+                });
+
+                if self.config.include_input_source
+                    && matches!(
+                        event_type,
+                        EventType::ElementFocused
+                            | EventType::ValueChanged
+                            | EventType::SelectedTextChanged
+                    )
+                {
+                    if let Some(source) = self.input_source.lock().unwrap().clone() {
+                        if let serde_json::Value::Object(map) =
+                            event_specific_data.get_or_insert_with(|| serde_json::json!({}))
+                        {
+                            map.insert("input_source".into(), source.into());
+                        }
+                    }
+                }
+                if truncated {
+                    if let serde_json::Value::Object(map) =
+                        event_specific_data.get_or_insert_with(|| serde_json::json!({}))
+                    {
+                        map.insert("truncated".into(), true.into());
+                    }
+                }
+
+                let event = UiEvent {
+                    application: app_info,
+                    window: window_info,
+                    element: element_details,
+                    event_specific_data,
+                    diagnostics,
+                    interaction_id,
+                    display,
+                    ..UiEvent::new(event_type)
+                };
+
+                if self.config.value_on_commit {
+                    let key = element.hash();
+                    match event.event_type {
+                        EventType::ValueChanged if is_text_input(&event) => {
+                            // Hold the value until the element loses focus
+                            self.pending_commits.lock().unwrap().insert(key, event);
+                            return;
+                        }
+                        EventType::ElementFocused => self.commit_pending(|k| k != key),
+                        EventType::ElementDestroyed => self.commit_pending(|k| k == key),
+                        _ => {}
+                    }
+                }
+
+                let caret_event = match event.event_type {
+                    EventType::SelectedTextChanged if self.config.track_caret => {
+                        self.caret_event(element, &event)
+                    }
+                    _ => None,
+                };
+
+                self.send_keyed(event, Some(element.hash()));
+                if let Some(caret_event) = caret_event {
+                    self.send(caret_event);
+                }
+            }
+            Err(e) => {
+                error!(error = %e, "failed to extract event data in callback");
+            }
+        }
+    }
+
+    // Emit the held ValueChanged events whose element key matches `filter`
+    fn commit_pending(&self, filter: impl Fn(cf::HashCode) -> bool) {
+        let committed: Vec<UiEvent> = {
+            // lock scope
+            let mut pending = self.pending_commits.lock().unwrap();
+            let keys: Vec<_> = pending.keys().copied().filter(|k| filter(*k)).collect();
+            keys.into_iter()
+                .filter_map(|k| pending.remove(&k))
+                .collect()
+        };
+
+        for mut event in committed {
+            event.restamp();
+            if let serde_json::Value::Object(map) = event
+                .event_specific_data
+                .get_or_insert_with(|| serde_json::json!({}))
+            {
+                map.insert("committed".into(), true.into());
+            }
+            self.send(event);
+        }
+    }
+
+    // What opened a menu: the element owning it (menu bar item, pop-up button, parent menu
+    // item) or else the element focused just before, within `menu_trigger_window`
+    fn menu_trigger(&self, menu: &ax::UiElement) -> Option<serde_json::Value> {
+        let owner = ar_pool(|| menu.parent().ok()).filter(|parent| {
+            let role = ar_pool(|| parent.role().ok().map(|r| r.to_string()));
+            role.is_some_and(|role| MENU_OWNER_ROLES.contains(&role.as_str()))
+        });
+        if let Some((_, _, Some(details))) = owner.and_then(|o| {
+            extract_event_data(&o, self.config.capture_values, self.config.attributes).ok()
+        }) {
+            return Some(serde_json::json!({
+                "trigger": details,
+                "trigger_source": "owner",
+            }));
+        }
+
+        let last_focus = self.last_focus.lock().unwrap().clone();
+        match last_focus {
+            Some((at, details)) if at.elapsed() <= self.config.menu_trigger_window => {
+                Some(serde_json::json!({
+                    "trigger": details,
+                    "trigger_source": "recent_focus",
+                }))
+            }
+            _ => None,
+        }
+    }
+
+    // Heuristic row move detection for tables, outlines and lists.
+    //
+    // Remembers the single selected row (by element identity) and its AXIndex per container.
+    // When the same row is still selected but its index changed, the row was moved, which is
+    // what drag-to-reorder looks like through AX. Apps that recreate row elements on every
+    // change, or that don't keep the dragged row selected, produce nothing.
+    fn row_move(&self, container: &ax::UiElement) -> Option<serde_json::Value> {
+        let rows = get_element_array(container, &attr_named("AXSelectedRows"));
+        let [row] = rows.as_slice() else {
+            return None;
+        };
+        let index = get_number_attribute(row, &attr_named("AXIndex"))? as i64;
+        let row_key = row.hash();
+
+        let previous = self
+            .row_positions
+            .lock()
+            .unwrap()
+            .insert(container.hash(), (row_key, index));
+        match previous {
+            Some((previous_row, old_index)) if previous_row == row_key && old_index != index => {
+                Some(serde_json::json!({
+                    "old_index": old_index,
+                    "new_index": index,
+                    "heuristic": true,
+                }))
+            }
+            _ => None,
+        }
+    }
+
+    // Selected items of a list, table, outline or other container, by AXSelectedChildren or
+    // AXSelectedRows. Items are listed up to SELECTION_MAX_ITEMS, their titles separately so
+    // they are hashed with the other free text.
+    fn selection_change_data(&self, container: &ax::UiElement) -> serde_json::Value {
+        let mut selected = get_element_array(container, &attr_named("AXSelectedChildren"));
+        if selected.is_empty() {
+            selected = get_element_array(container, &attr_named("AXSelectedRows"));
+        }
+        let mut items = Vec::new();
+        let mut titles = Vec::new();
+        for item in selected.iter().take(SELECTION_MAX_ITEMS) {
+            let key = element_key(item);
+            if self.config.enable_describe {
+                self.registry.lock().unwrap().remember(key.clone(), item);
+            }
+            items.push(serde_json::json!({
+                "element_key": key,
+                "role": string_attr_quiet(item, &attr_named("AXRole")),
+                "index": get_number_attribute(item, &attr_named("AXIndex")).map(|i| i as i64),
+            }));
+            titles.push(
+                string_attr_quiet(item, &attr_named("AXTitle"))
+                    .or_else(|| string_attr_quiet(item, &attr_named("AXDescription"))),
+            );
+        }
+        serde_json::json!({
+            "selected_count": selected.len(),
+            "selected": items,
+            "selected_titles": titles,
+        })
+    }
+
+    /// Answers a client query. Called from the control thread; AX calls are thread-safe.
+    pub fn handle_control(&self, request: ControlRequest) {
+        let result = match &request.query {
+            ClientQuery::Describe { element_key } => {
+                let element = self.registry.lock().unwrap().get(element_key);
+                match element {
+                    Some(element) => describe_element(&element),
+                    None => Err(format!("unknown or expired element_key {}", element_key)),
+                }
+            }
+        };
+        // The client may have gone away in the meantime
+        let _ = request.reply.send(result);
+    }
+
+    fn refresh_input_source(&self) {
+        let source = current_input_source_id();
+        debug!(?source, "keyboard input source changed");
+        *self.input_source.lock().unwrap() = source;
+    }
+
+    // Attach a listen-only input tap to the main run loop, so key and mouse events are
+    // handled on the same thread as AX notifications
+    fn install_input_tap(&self) -> Result<(), UiEventsError> {
+        if !unsafe { CGPreflightListenEventAccess() } {
+            // Shows the system prompt once; the user has to restart the listener after granting
+            unsafe { CGRequestListenEventAccess() };
+            error!("input monitoring permission not granted (required for capture_input)");
+            return Err(UiEventsError::InputMonitoringDenied);
+        }
+        unsafe {
+            let tap = CGEventTapCreate(
+                SESSION_EVENT_TAP,
+                HEAD_INSERT_EVENT_TAP,
+                TAP_LISTEN_ONLY,
+                INPUT_TAP_EVENTS.iter().fold(0, |mask, t| mask | 1 << t),
+                input_tap_callback,
+                self.ptr,
+            );
+            if tap.is_null() {
+                return Err(anyhow!("failed to create input event tap").into());
+            }
+            // Tap and source live as long as the listener, which is never dropped while running
+            let source = CFMachPortCreateRunLoopSource(std::ptr::null(), tap, 0);
+            CFRunLoopAddSource(CFRunLoopGetMain(), source, kCFRunLoopCommonModes);
+            CGEventTapEnable(tap, true);
+            self.input_tap.store(tap, Ordering::Relaxed);
+        }
+        info!("input event tap installed");
+        Ok(())
+    }
+
+    fn handle_key_down(&self, event: *const c_void) {
+        let event = UiEvent {
+            application: frontmost_app().map(|app| app_info(&app)),
+            event_specific_data: Some(key_event_data(event, self.config.capture_values)),
+            ..UiEvent::new(EventType::KeyPressed)
+        };
+        self.send(event);
+    }
+
+    // Pointer location (screen coordinates, like element positions) as the element position
+    fn handle_mouse(&self, event: *const c_void, event_type: EventType) {
+        let location = unsafe { CGEventGetLocation(event) };
+        let point = Position {
+            x: location.x,
+            y: location.y,
+        };
+        if matches!(event_type, EventType::MouseClicked) {
+            let click = ar_pool(|| self.click_event(event, point));
+            self.send(click);
+            return;
+        }
+
+        {
+            // lock scope
+            let mut last = self.last_mouse_move.lock().unwrap();
+            if last.is_some_and(|t| t.elapsed() < self.config.mouse_move_interval) {
+                return;
+            }
+            *last = Some(Instant::now());
+        }
+        let display = display_at(&point);
+        let mut element = Some(ElementDetails {
+            position: Some(point),
+            ..Default::default()
+        });
+        self.apply_backing_scale(&mut None, &mut element);
+        let event = UiEvent {
+            application: frontmost_app().map(|app| app_info(&app)),
+            element,
+            display,
+            ..UiEvent::new(event_type)
+        };
+        self.send(event);
+    }
+
+    // Hit-test a click: the element under the pointer with its app and window when it can
+    // be read, otherwise the bare location like a move. The click location is always in the
+    // event data, and `frontmost` tells whether the click landed in the frontmost app
+    // (clicks on background windows belong to another process).
+    fn click_event(&self, event: *const c_void, point: Position) -> UiEvent {
+        let mut data = mouse_click_data(event);
+        let scale = if self.config.coordinates_in_pixels {
+            backing_scale_at(&point).unwrap_or(1.0)
+        } else {
+            1.0
+        };
+        data["location"] = serde_json::json!(point.clone().scaled(scale));
+
+        let frontmost_pid = frontmost_app().map(|app| app.pid());
+        let hit = ax::UiElement::sys_wide()
+            .element_at_pos(point.x as f32, point.y as f32)
+            .map_err(|e| debug!(?e, "hit-test failed"))
+            .ok();
+        let extracted = hit.as_ref().and_then(|element| {
+            extract_event_data(element, self.config.capture_values, self.config.attributes)
+                .map_err(|e| debug!(error = %e, "failed to read clicked element"))
+                .ok()
+        });
+
+        let (application, mut window, mut element) = match extracted {
+            Some((application, window, element)) => {
+                let hit_pid = application.as_ref().and_then(|a| a.pid);
+                data["frontmost"] = (hit_pid.is_some() && hit_pid == frontmost_pid).into();
+                (application, window, element)
+            }
+            None => (
+                frontmost_app().map(|app| app_info(&app)),
+                None,
+                Some(ElementDetails {
+                    position: Some(point),
+                    ..Default::default()
+                }),
+            ),
+        };
+        let display = display_of(&window, &element);
+        self.apply_backing_scale(&mut window, &mut element);
+        if self.config.enable_describe {
+            if let (Some(hit), Some(key)) = (
+                hit.as_ref(),
+                element.as_ref().and_then(|d| d.element_key.clone()),
+            ) {
+                self.registry.lock().unwrap().remember(key, hit);
+            }
+        }
+
+        UiEvent {
+            application,
+            window,
+            element,
+            event_specific_data: Some(data),
+            display,
+            ..UiEvent::new(EventType::MouseClicked)
+        }
+    }
+
+    // Record the backing scale of the element's screen and convert to pixels if configured
+    fn apply_backing_scale(
+        &self,
+        window_info: &mut Option<WindowInfo>,
+        element_details: &mut Option<ElementDetails>,
+    ) {
+        let Some(details) = element_details.as_mut() else {
+            return;
+        };
+        let Some(scale) = details.position.as_ref().and_then(backing_scale_at) else {
+            return;
+        };
+        details.scale_factor = Some(scale);
+        if let Some(window) = window_info.as_mut() {
+            window.scale_factor = Some(scale);
+        }
+        if self.config.coordinates_in_pixels {
+            details.position = details.position.take().map(|p| p.scaled(scale));
+            details.size = details.size.take().map(|s| s.scaled(scale));
+            if let Some(window) = window_info.as_mut() {
+                window.position = window.position.take().map(|p| p.scaled(scale));
+                window.size = window.size.take().map(|s| s.scaled(scale));
+            }
+        }
+    }
+
+    // Record the window's frame and describe it for a move/resize: the new frame, plus the
+    // change in position (WindowMoved) or size (WindowResized) since the last known frame
+    fn window_frame_change(
+        &self,
+        window: &ax::UiElement,
+        info: &WindowInfo,
+        event_type: &EventType,
+    ) -> Option<serde_json::Value> {
+        let (Some(position), Some(size)) = (info.position.clone(), info.size.clone()) else {
+            return None;
+        };
+        let mut data = serde_json::json!({
+            "frame": {
+                "x": position.x,
+                "y": position.y,
+                "width": size.width,
+                "height": size.height,
+            }
+        });
+        let previous = self
+            .window_frames
+            .lock()
+            .unwrap()
+            .insert(window.hash(), (position.clone(), size.clone()));
+        match (event_type, previous) {
+            (EventType::WindowMoved, Some((previous, _))) => {
+                data["delta"] = serde_json::json!({
+                    "x": position.x - previous.x,
+                    "y": position.y - previous.y,
+                });
+            }
+            (EventType::WindowResized, Some((_, previous))) => {
+                data["delta"] = serde_json::json!({
+                    "width": size.width - previous.width,
+                    "height": size.height - previous.height,
+                });
+            }
+            _ => {}
+        }
+        Some(data)
+    }
+
+    // Build a throttled CaretMoved event sharing the context of the selection change
+    fn caret_event(&self, element: &ax::UiElement, source: &UiEvent) -> Option<UiEvent> {
+        {
+            // lock scope
+            let mut last = self.last_caret_event.lock().unwrap();
+            if last.is_some_and(|t| t.elapsed() < self.config.caret_throttle) {
+                return None;
+            }
+            *last = Some(Instant::now());
+        }
+
+        let scale = if self.config.coordinates_in_pixels {
+            source
+                .element
+                .as_ref()
+                .and_then(|e| e.scale_factor)
+                .unwrap_or(1.0)
+        } else {
+            1.0
+        };
+        let data = caret_data(element, scale)?;
+        Some(UiEvent {
+            application: source.application.clone(),
+            window: source.window.clone(),
+            display: source.display.clone(),
+            element: source.element.clone(),
+            event_specific_data: Some(data),
+            ..UiEvent::new(EventType::CaretMoved)
+        })
+    }
+
+    // Update the value cache and describe the change relative to the previous value
+    fn value_change_data(
+        &self,
+        element: &ax::UiElement,
+        details: &ElementDetails,
+    ) -> Option<serde_json::Value> {
+        let previous = self
+            .value_cache
+            .lock()
+            .unwrap()
+            .replace(element, details.value.clone());
+
+        match details.role.as_deref() {
+            Some(STEPPER_ROLE) => {
+                let (previous, current) = (previous?, details.value.as_ref()?);
+                let (min, max) = get_value_bounds(element);
+                let direction = stepper_direction(&previous, current, min, max)?;
+                Some(serde_json::json!({
+                    "direction": direction,
+                    "previous_value": previous,
+                }))
+            }
+            Some(PROGRESS_ROLE) => {
+                let value = details.value.as_ref()?.as_f64()?;
+                let (min, max) = get_value_bounds(element);
+                let progress = normalize_value(value, min, max)?;
+                Some(serde_json::json!({ "progress": progress }))
+            }
+            _ => None,
+        }
+    }
+
+    // Progress bars update constantly; let one ValueChanged per bar through per throttle
+    // window, plus the final one at 100%. Returns true if the event should be dropped.
+    fn throttle_progress(&self, element: &ax::UiElement) -> bool {
+        let is_progress = ar_pool(|| element.role().is_ok_and(|r| r.to_string() == PROGRESS_ROLE));
+        if !is_progress {
+            return false;
+        }
+
+        let complete = get_number_attribute(element, ax::attr::value())
+            .and_then(|value| {
+                let (min, max) = get_value_bounds(element);
+                normalize_value(value, min, max)
+            })
+            .is_some_and(|progress| progress >= 1.0);
+
+        let mut last_sent = self.progress_last_sent.lock().unwrap();
+        let key = element.hash();
+        let throttled = last_sent
+            .get(&key)
+            .is_some_and(|t| t.elapsed() < self.config.progress_throttle);
+        if throttled && !complete {
+            return true;
+        }
+        last_sent.insert(key, Instant::now());
+        false
+    }
+
+    // Periodic snapshot of what is in front, independent of UI changes
+    fn sample_context(&self) {
+        let (application, window) = frontmost_context();
+        let event = UiEvent {
+            application,
+            window,
+            ..UiEvent::new(EventType::ContextSample)
+        };
+        self.send(event);
+    }
+
+    // Hang watchdog step. Probes the app currently reported as hung, or else the frontmost
+    // one, and reports transitions between responsive and unresponsive.
+    fn check_responsiveness(&self, hung: &mut Option<(i32, Instant)>, timeout: Duration) {
+        let pid = match *hung {
+            Some((pid, _)) => pid,
+            None => match ar_pool(frontmost_app) {
+                Some(app) => app.pid(),
+                None => return,
+            },
+        };
+        let responds = app_responds(pid, timeout);
+        let application = ar_pool(|| {
+            ns::running_application::RunningApp::with_pid(pid).map(|app| app_info(&app))
+        });
+
+        match (*hung, responds) {
+            (None, false) => {
+                warn!(pid, "app stopped responding to accessibility requests");
+                *hung = Some((pid, Instant::now()));
+                let event = UiEvent {
+                    application,
+                    event_specific_data: Some(serde_json::json!({
+                        "timeout_ms": timeout.as_millis() as u64,
+                    })),
+                    ..UiEvent::new(EventType::ApplicationUnresponsive)
+                };
+                self.send(event);
+            }
+            (Some((_, since)), true) => {
+                info!(pid, "app responds again");
+                *hung = None;
+                let event = UiEvent {
+                    application,
+                    event_specific_data: Some(serde_json::json!({
+                        "unresponsive_ms": since.elapsed().as_millis() as u64,
+                    })),
+                    ..UiEvent::new(EventType::ApplicationResponsive)
+                };
+                self.send(event);
+            }
+            _ => {}
+        }
+    }
+
+    fn handle_space_change(&self) {
+        let space_id = active_space_id();
+        info!(?space_id, "active space changed");
+
+        // The frontmost app after the switch gives the new Space some context
+        let application = ar_pool(|| frontmost_app().map(|app| app_info(&app)));
+
+        let event = UiEvent {
+            application,
+            event_specific_data: Some(serde_json::json!({ "space_id": space_id })),
+            ..UiEvent::new(EventType::SpaceChanged)
+        };
+        self.send(event);
+    }
+
+    // Emit ApplicationHidden/ApplicationUnhidden for NSWorkspace hide notifications
+    fn handle_app_visibility(&self, n: &ns::Notification, event_type: EventType) {
+        let Some(app) = notification_app(n) else {
+            return;
+        };
+        let application = app_info(&app);
+        info!(app_name = ?application.name, pid = app.pid(), %event_type, "app visibility changed");
+
+        let event = UiEvent {
+            application: Some(application),
+            ..UiEvent::new(event_type)
+        };
+        self.send(event);
+    }
+
+    #[inline]
+    fn handle_app_activation(self: &Pin<Arc<Self>>, n: &ns::Notification) {
+        let Some(app) = notification_app(n) else {
+            return;
+        };
+
+        let pid = app.pid();
+
+        let application = app_info(&app);
+        info!(app_name = ?application.name, pid, "activated app");
+
+        // --- Send ApplicationActivated Event ---
+        // (dropped by send for filtered apps)
+        let event = UiEvent {
+            application: Some(application.clone()),
+            ..UiEvent::new(EventType::ApplicationActivated)
+        };
+        self.send(event);
+
+        let activation = self.activations.fetch_add(1, Ordering::SeqCst) + 1;
+        if !self.config.watch_all_apps {
+            // release current observer
+            if let Some((_, old)) = self.ax_observer.lock().unwrap().take() {
+                detach_observer(&old);
+            }
+        }
+        // Focus left the previous app, so its text edits are final
+        self.commit_pending(|_| true);
+        self.open_sheets.lock().unwrap().clear();
+        self.row_positions.lock().unwrap().clear();
+        *self.menu_state.lock().unwrap() = MenuState::default();
+        self.value_cache.lock().unwrap().clear();
+        self.progress_last_sent.lock().unwrap().clear();
+
+        if !self.config.captures_app(&application) {
+            debug!(pid, bundle_id = ?application.bundle_id, "app filtered out, not observing");
+            return;
+        }
+
+        if self.config.watch_all_apps {
+            self.watch_app(&app);
+        } else {
+            self.attach_observer(pid, application, move |this, observer| {
+                // Another app was activated while this one was not ready yet
+                if this.activations.load(Ordering::SeqCst) != activation {
+                    detach_observer(&observer);
+                    return;
+                }
+                if let Some((_, old)) = this.ax_observer.lock().unwrap().replace((pid, observer)) {
+                    detach_observer(&old);
+                }
+            });
+        }
+    }
+
+    // Create an observer for the app's AX notifications on the main run loop and hand it to
+    // `done`, possibly after a few retries. Sends ObservationFailed when the app cannot be
+    // observed; nothing is handed over once the listener stopped.
+    fn attach_observer(
+        self: &Pin<Arc<Self>>,
+        pid: i32,
+        application: ApplicationInfo,
+        done: impl FnOnce(&Pin<Arc<Self>>, arc::R<ax::Observer>) + 'static,
+    ) {
+        let this = self.clone();
+        with_retry(
+            pid,
+            move || ax::Observer::with_cb(pid, observer_callback2),
+            move |result| this.observer_created(pid, application, result, done),
+        );
+    }
+
+    fn observer_created<E: std::fmt::Debug>(
+        self: &Pin<Arc<Self>>,
+        pid: i32,
+        application: ApplicationInfo,
+        result: std::result::Result<arc::R<ax::Observer>, (E, u32)>,
+        done: impl FnOnce(&Pin<Arc<Self>>, arc::R<ax::Observer>),
+    ) {
+        if self.stopped.load(Ordering::SeqCst) {
+            return;
+        }
+        let app_element = ax::UiElement::with_app_pid(pid);
+
+        match result {
+            Ok(mut observer) => {
+                // observer should be Retained<ax::Observer>
                 info!(pid, "created new axobserver");
+                metrics::observer_attached();
                 use ax::notification as axn;
                 let notifications_to_add = [
                     axn::focused_window_changed(),
@@ -548,9 +2687,14 @@ impl MacosListener {
                     axn::menu_item_selected(),
                     axn::selected_text_changed(),
                     axn::title_changed(),
+                    axn::selected_children_changed(),
                 ];
 
                 for notif_name in notifications_to_add {
+                    if !notification_wanted(&self.config, notif_name) {
+                        debug!(pid, notification = %notif_name.to_string(), "event types not captured, skipping notification");
+                        continue;
+                    }
                     // Observer expects &cf::String for notification name
                     // Call add_notification on the observer instance
                     match observer.add_notification(&app_element, notif_name, self.ptr) {
@@ -563,37 +2707,190 @@ impl MacosListener {
                     }
                 }
 
+                for notif_name in OPTIONAL_NOTIFICATIONS {
+                    let notif = notification_named(notif_name);
+                    if !notification_wanted(&self.config, &notif) {
+                        continue;
+                    }
+                    match observer.add_notification(&app_element, &notif, self.ptr) {
+                        Ok(_) => info!(pid, notification = notif_name, "added notification"),
+                        Err(e) => {
+                            debug!(pid, notification = notif_name, error = ?e, "notification not supported by app")
+                        }
+                    }
+                }
+
+                for notif_name in &self.config.extra_notifications {
+                    let notif = notification_named(notif_name);
+                    match observer.add_notification(&app_element, &notif, self.ptr) {
+                        Ok(_) => info!(pid, notification = %notif_name, "added extra notification"),
+                        Err(e) => {
+                            warn!(pid, notification = %notif_name, error = ?e, "failed to add extra notification")
+                        }
+                    }
+                }
+
                 // Call run_loop_source on the observer instance
                 let source = observer.run_loop_src(); // Should be Retained<cf::RunLoopSource>
                 // Reply to comment above: No, it is get rule there
                 cf::RunLoop::main().add_src(source, cf::RunLoopMode::default());
                 info!(pid, "added run loop source for observer");
 
-                // Store the observer
-                {
-                    // lock scope
-                    let _ = self.ax_observer.lock().unwrap().insert(observer);
+                done(self, observer);
+            }
+            Err((e, attempts)) => {
+                error!(pid, attempts, error = ?e, "failed to create axobserver for pid");
+                // Let consumers know this app's UI events will be missing
+                let event = UiEvent {
+                    application: Some(application),
+                    event_specific_data: Some(serde_json::json!({
+                        "attempts": attempts,
+                        "error": format!("{:?}", e),
+                    })),
+                    ..UiEvent::new(EventType::ObservationFailed)
+                };
+                self.send(event);
+            }
+        }
+    }
+
+    // Observe a running app for watch_all_apps, unless it already is or is filtered out
+    fn watch_app(self: &Pin<Arc<Self>>, app: &ns::RunningApp) {
+        let pid = app.pid();
+        if self.app_observers.lock().unwrap().contains_key(&pid) {
+            return;
+        }
+        let application = app_info(app);
+        if !self.config.captures_app(&application) {
+            return;
+        }
+        self.attach_observer(pid, application, move |this, observer| {
+            // Attached meanwhile by a second launch or activation notification
+            let mut observers = this.app_observers.lock().unwrap();
+            if observers.contains_key(&pid) {
+                detach_observer(&observer);
+                return;
+            }
+            observers.insert(pid, observer);
+        });
+    }
+
+    fn handle_app_launch(self: &Pin<Arc<Self>>, n: &ns::Notification) {
+        if let Some(app) = notification_app(n) {
+            self.watch_app(&app);
+        }
+    }
+
+    // Drop the observer of an app that quit and end its session with a final
+    // ApplicationDeactivated. Detaching only touches our run loop, never the dead pid.
+    fn handle_app_termination(&self, n: &ns::Notification) {
+        let Some(app) = notification_app(n) else {
+            return;
+        };
+        let pid = app.pid();
+        let observer = {
+            // lock scope
+            let mut current = self.ax_observer.lock().unwrap();
+            match current.take() {
+                Some((observed, observer)) if observed == pid => Some(observer),
+                other => {
+                    *current = other;
+                    self.app_observers.lock().unwrap().remove(&pid)
                 }
             }
-            Err(e) => {
-                error!(pid, error = ?e, "failed to create axobserver for pid");
+        };
+        let Some(observer) = observer else {
+            return;
+        };
+        detach_observer(&observer);
+        info!(pid, "observed app terminated, removed its observer");
+
+        self.commit_pending(|_| true);
+        let event = UiEvent {
+            application: Some(app_info(&app)),
+            event_specific_data: Some(serde_json::json!({ "terminated": true })),
+            ..UiEvent::new(EventType::ApplicationDeactivated)
+        };
+        self.send(event);
+    }
+
+    /// Removes every observer and event tap and sends held-back events, after which the
+    /// listener produces no more events. Must be called on the main thread, where the observers
+    /// deliver (see [`run_on_main_thread`]); later calls do nothing.
+    pub fn shutdown(&self) {
+        debug_assert!(ns::Thread::is_main());
+        if self.stopped.swap(true, Ordering::SeqCst) {
+            return;
+        }
+        if let Some((_, observer)) = self.ax_observer.lock().unwrap().take() {
+            detach_observer(&observer);
+        }
+        for (_, observer) in self.app_observers.lock().unwrap().drain() {
+            detach_observer(&observer);
+        }
+        let tokens = std::mem::take(&mut *self.ws_observer_tokens.lock().unwrap());
+        ar_pool(|| {
+            let mut nc = ns::Workspace::shared().notification_center();
+            for token in &tokens {
+                nc.remove_observer(token);
+            }
+        });
+        unsafe {
+            CFNotificationCenterRemoveEveryObserver(
+                CFNotificationCenterGetDistributedCenter(),
+                self.ptr,
+            );
+            let tap = self.input_tap.swap(std::ptr::null_mut(), Ordering::Relaxed);
+            if !tap.is_null() {
+                CGEventTapEnable(tap, false);
             }
         }
+
+        // Nothing will push these out anymore
+        self.commit_pending(|_| true);
+        self.delivery.shutdown();
+        info!("macos listener stopped");
     }
 
-    pub fn new_on_main_thread(tx: mpsc::Sender<UiEvent>) -> Result<Pin<Arc<Self>>> {
-        // debug_assert!(ns::Thread::is_main());
-        // TODO: assert on main thread
+    pub fn new_on_main_thread(
+        tx: mpsc::Sender<UiEvent>,
+        config: ListenerConfig,
+    ) -> Result<Pin<Arc<Self>>, UiEventsError> {
+        // Everything the listener sets up runs on the main thread: AX observers and retries
+        // on its run loop, workspace notifications (observed without a queue) where posted
+        debug_assert!(ns::Thread::is_main());
         info!("checking accessibility permissions...");
-        if !ax::is_process_trusted_with_prompt(true) {
+        let granted =
+            accessibility_granted() || (config.prompt_for_accessibility && request_accessibility());
+        if !granted {
             error!("accessibility permissions not granted");
-            return Err(anyhow!("accessibility permissions not granted by user"));
+            return Err(UiEventsError::AccessibilityDenied);
         }
 
+        let delivery = Delivery::new(tx, &config);
+
         let data = Self {
-            tx,
+            config,
+            delivery,
             ax_observer: Default::default(),
-            ws_observer_token: Default::default(),
+            activations: Default::default(),
+            ws_observer_tokens: Default::default(),
+            app_observers: Default::default(),
+            value_cache: Default::default(),
+            progress_last_sent: Default::default(),
+            window_frames: Default::default(),
+            last_caret_event: Default::default(),
+            pending_commits: Default::default(),
+            open_sheets: Default::default(),
+            row_positions: Default::default(),
+            menu_state: Default::default(),
+            registry: Default::default(),
+            input_source: Default::default(),
+            interaction: Default::default(),
+            last_focus: Default::default(),
+            input_tap: Default::default(),
+            last_mouse_move: Default::default(),
+            stopped: Default::default(),
             ptr: std::ptr::null_mut(),
             _pin: PhantomPinned,
         };
@@ -617,98 +2914,161 @@ impl MacosListener {
             },
         );
 
+        let block_pin = pin.clone();
+        let space_token = nc.add_observer(
+            &ns::NotificationName::with_str("NSWorkspaceActiveSpaceDidChangeNotification"),
+            None,
+            None,
+            move |_n: &ns::Notification| {
+                block_pin.handle_space_change();
+            },
+        );
+
+        let block_pin = pin.clone();
+        let hide_token = nc.add_observer(
+            &ns::NotificationName::with_str("NSWorkspaceDidHideApplicationNotification"),
+            None,
+            None,
+            move |n: &ns::Notification| {
+                block_pin.handle_app_visibility(n, EventType::ApplicationHidden);
+            },
+        );
+
+        let block_pin = pin.clone();
+        let unhide_token = nc.add_observer(
+            &ns::NotificationName::with_str("NSWorkspaceDidUnhideApplicationNotification"),
+            None,
+            None,
+            move |n: &ns::Notification| {
+                block_pin.handle_app_visibility(n, EventType::ApplicationUnhidden);
+            },
+        );
+
+        let block_pin = pin.clone();
+        let terminate_token = nc.add_observer(
+            &ns::NotificationName::with_str("NSWorkspaceDidTerminateApplicationNotification"),
+            None,
+            None,
+            move |n: &ns::Notification| {
+                block_pin.handle_app_termination(n);
+            },
+        );
+
         {
             // lock scope
-            let _ = pin.ws_observer_token.lock().unwrap().insert(token);
+            let mut tokens = pin.ws_observer_tokens.lock().unwrap();
+            tokens.extend([
+                token,
+                space_token,
+                hide_token,
+                unhide_token,
+                terminate_token,
+            ]);
         }
 
-        Ok(pin)
-    }
-}
-
-impl PlatformListener for MacosListener {
-    fn run(&self, sender: mpsc::Sender<UiEvent>) -> Result<()> {
-        if !ax::is_process_trusted_with_prompt(true) {
-            error!("accessibility permissions not granted");
-            return Err(anyhow!("accessibility permissions not granted by user"));
+        if pin.config.watch_all_apps {
+            let block_pin = pin.clone();
+            let launch_token = nc.add_observer(
+                &ns::NotificationName::with_str("NSWorkspaceDidLaunchApplicationNotification"),
+                None,
+                None,
+                move |n: &ns::Notification| {
+                    block_pin.handle_app_launch(n);
+                },
+            );
+            pin.ws_observer_tokens.lock().unwrap().push(launch_token);
         }
-        info!(
-            "macos listener starting run() on thread {:?}...",
-            std::thread::current().id()
-        );
 
-        // Store the sender for the callbacks
-        SENDER.with(|cell| {
-            *cell.borrow_mut() = Some(sender.clone());
-        });
-        info!("sender stored in thread_local");
+        if pin.config.include_input_source {
+            pin.refresh_input_source();
+            // Observer lives as long as the listener, which is never dropped while running
+            let name = cf::String::from_str(INPUT_SOURCE_CHANGED_NOTIFICATION);
+            unsafe {
+                CFNotificationCenterAddObserver(
+                    CFNotificationCenterGetDistributedCenter(),
+                    pin.ptr,
+                    input_source_callback,
+                    &*name as *const cf::String as *const c_void,
+                    std::ptr::null(),
+                    DELIVER_IMMEDIATELY,
+                );
+            }
+        }
 
-        // --- Setup NSWorkspace Observer for App Activation ---
-        let mut center = ns::Workspace::shared().notification_center();
-        let sender_callback = sender.clone();
+        if pin.config.capture_input {
+            pin.install_input_tap()?;
+        }
 
-        // Define the callback closure for NSWorkspace notifications
-        let workspace_callback = move |notification: &ns::Notification| {
-            ar_pool(|| {
-                info!(notification_name = ?notification.name(), "received workspace notification");
+        if let Some(interval) = pin.config.context_sample_interval {
+            // Sampling only reads state, so it can run off the main thread
+            let sampler_pin = pin.clone();
+            std::thread::spawn(move || {
+                while !sampler_pin.stopped.load(Ordering::Relaxed) {
+                    std::thread::sleep(interval);
+                    sampler_pin.sample_context();
+                }
+            });
+        }
 
-                let apps = ns::Workspace::shared().running_apps();
-                let active_app = apps.iter().find(|app| app.is_active()).unwrap();
+        if let Some(interval) = pin.config.heartbeat_interval {
+            // Only looks at the time of the last event, so it can run off the main thread
+            let heartbeat_pin = pin.clone();
+            std::thread::spawn(move || {
+                while !heartbeat_pin.stopped.load(Ordering::Relaxed) {
+                    let idle = heartbeat_pin.delivery.idle();
+                    if idle < interval {
+                        std::thread::sleep(interval - idle);
+                        continue;
+                    }
+                    // Through the filters like any event (app and type filters let it pass)
+                    heartbeat_pin
+                        .delivery
+                        .send(UiEvent::new(EventType::Heartbeat), None);
+                }
+            });
+        }
 
-                handle_activation(active_app, &sender_callback);
+        if let Some(timeout) = pin.config.hang_timeout {
+            // Off the main thread, which a hung app must not block
+            let watchdog_pin = pin.clone();
+            std::thread::spawn(move || {
+                let mut hung = None;
+                while !watchdog_pin.stopped.load(Ordering::Relaxed) {
+                    std::thread::sleep(HANG_PROBE_INTERVAL);
+                    watchdog_pin.check_responsiveness(&mut hung, timeout);
+                }
             });
-        }; // Copy the block to the heap
-
-        // Add the observer to the notification center
-        // TODO this does not work rn - so only recording the active app at startup
-        let token = center.add_observer(
-            // Use actual static method name for notification
-            &ns::NotificationName::with_str("NSWorkspaceDidActivateApplicationNotification"),
-            None, // Observe notifications from any object
-            None, // Pass None for OperationQueue
-            workspace_callback,
-        );
-        let retained_token = token.retained(); // Retain the token
+        }
 
-        // Store the token for cleanup
-        WORKSPACE_OBSERVER_TOKEN.with(|cell| {
-            *cell.borrow_mut() = Some(retained_token);
-        });
-        info!("added ns workspace observer for app activation");
-
-        // --- Initial Activation Handling ---
-        // Handle the currently active application immediately
-        let apps = ns::Workspace::shared().running_apps();
-        let active_app = apps.iter().find(|app| app.is_active()).unwrap();
-
-        handle_activation(&active_app, &sender);
-
-        // --- Start Run Loop ---
-        info!("starting cf run loop (blocking current thread)... Awaiting UI events.");
-        cf::RunLoop::run(); // This blocks the thread
-
-        warn!("cf run loop finished! Performing cleanup (this is unexpected).");
-        // Cleanup for NSWorkspace observer
-        WORKSPACE_OBSERVER_TOKEN.with(|cell| {
-            if let Some(token) = cell.borrow_mut().take() {
-                ar_pool(|| {
-                    ns::Workspace::shared()
-                        .notification_center()
-                        .remove_observer(&token);
-                });
-                info!("removed ns workspace observer");
-            }
-        });
-        // Cleanup for AXObserver (managed by handle_activation, but clear it finally)
-        CURRENT_AX_OBSERVER.with(|cell| {
-            if cell.borrow().is_some() {
-                info!("dropping final axobserver during cleanup");
-                *cell.borrow_mut() = None;
-            }
-        });
-        // Cleanup sender
-        SENDER.with(|cell| *cell.borrow_mut() = None);
+        // First event of the stream: queued ahead of everything the run loop will produce and
+        // of the ObservationFailed events attaching to running apps may cause
+        let ready = UiEvent {
+            event_specific_data: Some(serde_json::json!({
+                "platform": std::env::consts::OS,
+                "arch": std::env::consts::ARCH,
+                "version": env!("CARGO_PKG_VERSION"),
+                "input_capture": pin.config.capture_input,
+            })),
+            ..UiEvent::new(EventType::ListenerReady)
+        };
+        pin.send(ready);
+        info!("macos listener ready");
+
+        if pin.config.watch_all_apps {
+            ar_pool(|| {
+                for app in ns::Workspace::shared().running_apps().iter() {
+                    // Background-only agents have no UI to observe
+                    if app.activation_policy() != ns::AppActivationPolicy::Prohibited {
+                        pin.watch_app(app);
+                    }
+                }
+            });
+            info!(
+                apps = pin.app_observers.lock().unwrap().len(),
+                "observing all running apps"
+            );
+        }
 
-        Ok(()) // Should technically not be reached if RunLoop runs forever
+        Ok(pin)
     }
 }
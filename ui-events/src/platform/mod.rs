@@ -1,8 +1,19 @@
-use crate::event::UiEvent;
+use crate::config::ListenerConfig;
+use crate::config::event_type_matches;
+use crate::control::ControlRequest;
+use crate::error::UiEventsError;
+use crate::event::{ApplicationInfo, EventType, UiEvent};
 use anyhow::Result;
+use futures_util::Stream;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
 use tracing::info;
 
+mod delivery;
+
 // Modules for each platform
 #[cfg(target_os = "linux")]
 pub mod linux;
@@ -11,6 +22,166 @@ pub mod macos;
 #[cfg(target_os = "windows")]
 pub mod windows;
 
+// Events the listener could not queue, for the whole process
+static DROPPED_EVENTS: AtomicU64 = AtomicU64::new(0);
+
+/// Events the listener dropped so far because its channel was full (the consumer is too
+/// slow) or closed. Counts for the whole process, across listener restarts.
+pub fn dropped_events() -> u64 {
+    DROPPED_EVENTS.load(Ordering::Relaxed)
+}
+
+// Record a dropped event, returning the new total
+pub(crate) fn count_dropped_event() -> u64 {
+    DROPPED_EVENTS.fetch_add(1, Ordering::Relaxed) + 1
+}
+
+// Events dropped by the rate limit, for the whole process
+static RATE_LIMITED_EVENTS: AtomicU64 = AtomicU64::new(0);
+
+/// Events dropped so far by `ListenerConfig::max_events_per_sec` and `event_type_rates`.
+/// Counts for the whole process, separately from [`dropped_events`].
+pub fn rate_limited_events() -> u64 {
+    RATE_LIMITED_EVENTS.load(Ordering::Relaxed)
+}
+
+// Token bucket holding up to one second's worth of events, refilled continuously
+#[derive(Debug)]
+struct TokenBucket {
+    rate: f64,
+    tokens: f64,
+    refilled: Instant,
+}
+
+impl TokenBucket {
+    fn new(rate: u32, now: Instant) -> Self {
+        Self {
+            rate: rate as f64,
+            tokens: rate as f64,
+            refilled: now,
+        }
+    }
+
+    // Refill for the time since the last call; whether a token is available
+    fn ready(&mut self, now: Instant) -> bool {
+        let elapsed = now.saturating_duration_since(self.refilled).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.rate);
+        self.refilled = now;
+        self.tokens >= 1.0
+    }
+
+    fn take(&mut self) {
+        self.tokens -= 1.0;
+    }
+}
+
+#[derive(Debug)]
+struct RateBuckets {
+    total: Option<TokenBucket>,
+    per_type: Vec<(String, TokenBucket)>,
+}
+
+/// `ListenerConfig::max_events_per_sec` and `event_type_rates`, shared by every path that
+/// sends events.
+#[derive(Debug)]
+pub(crate) struct RateLimiter {
+    buckets: Mutex<RateBuckets>,
+}
+
+impl RateLimiter {
+    /// `None` when the config sets no limit.
+    pub(crate) fn new(config: &ListenerConfig) -> Option<Self> {
+        if config.max_events_per_sec.is_none() && config.event_type_rates.is_empty() {
+            return None;
+        }
+        let now = Instant::now();
+        let buckets = RateBuckets {
+            total: config
+                .max_events_per_sec
+                .map(|rate| TokenBucket::new(rate, now)),
+            per_type: config
+                .event_type_rates
+                .iter()
+                .map(|(name, rate)| (name.clone(), TokenBucket::new(*rate, now)))
+                .collect(),
+        };
+        Some(Self {
+            buckets: Mutex::new(buckets),
+        })
+    }
+
+    /// Whether an event of `event_type` may be sent now; counts it as rate limited if not.
+    pub(crate) fn allow(&self, event_type: &EventType) -> bool {
+        self.allow_at(event_type, Instant::now())
+    }
+
+    fn allow_at(&self, event_type: &EventType, now: Instant) -> bool {
+        if matches!(
+            event_type,
+            EventType::ListenerReady | EventType::EventsDropped | EventType::Heartbeat
+        ) {
+            return true;
+        }
+        let mut buckets = self.buckets.lock().unwrap();
+        let RateBuckets { total, per_type } = &mut *buckets;
+        let mut type_bucket = per_type
+            .iter_mut()
+            .find(|(name, _)| event_type_matches(name, event_type))
+            .map(|(_, bucket)| bucket);
+        // Take from both buckets or neither, so an event the total limit drops does not use
+        // up its type's allowance
+        let allowed = type_bucket.as_mut().is_none_or(|bucket| bucket.ready(now))
+            && total.as_mut().is_none_or(|bucket| bucket.ready(now));
+        if allowed {
+            type_bucket
+                .into_iter()
+                .chain(total.as_mut())
+                .for_each(TokenBucket::take);
+        } else {
+            RATE_LIMITED_EVENTS.fetch_add(1, Ordering::Relaxed);
+        }
+        allowed
+    }
+}
+
+/// Whether this process may use the accessibility APIs the listener needs. Never shows a
+/// dialog, so it can be polled, e.g. while the user is in System Settings.
+pub fn accessibility_granted() -> bool {
+    #[cfg(target_os = "macos")]
+    return macos::accessibility_granted();
+    #[cfg(not(target_os = "macos"))]
+    true
+}
+
+/// Like [`accessibility_granted`], but shows the system's permission dialog when the
+/// permission is missing. Returns the status at the time of the call, so still `false` right
+/// after prompting.
+pub fn request_accessibility() -> bool {
+    #[cfg(target_os = "macos")]
+    return macos::request_accessibility();
+    #[cfg(not(target_os = "macos"))]
+    true
+}
+
+/// A running application and whether the listener can observe it, see [`list_apps`].
+#[derive(Debug, Clone)]
+pub struct AppObservability {
+    pub application: ApplicationInfo,
+    /// An accessibility observer could be attached, so the app's events would be seen.
+    pub observable: bool,
+    /// Why not, e.g. `kAXErrorAPIDisabled` without the accessibility permission.
+    pub error: Option<String>,
+}
+
+/// Running applications with a user interface and whether each can be observed, for
+/// diagnosing missing events. Does not start the listener or a run loop.
+pub fn list_apps() -> Result<Vec<AppObservability>, UiEventsError> {
+    #[cfg(target_os = "macos")]
+    return Ok(macos::list_apps());
+    #[cfg(not(target_os = "macos"))]
+    Err(UiEventsError::UnsupportedPlatform)
+}
+
 /// Common trait for platform-specific listeners.
 /// Must be Send to allow spawning in a separate thread/task.
 pub trait PlatformListener: Send {
@@ -19,24 +190,278 @@ pub trait PlatformListener: Send {
     fn run(&self, sender: mpsc::Sender<UiEvent>) -> Result<()>;
 }
 
-pub fn listener_run(tx: mpsc::Sender<UiEvent>) {
+pub fn listener_run(tx: mpsc::Sender<UiEvent>) -> Result<(), UiEventsError> {
+    listener_run_with_config(tx, ListenerConfig::default(), None)
+}
+
+/// Runs the listener on the current (main) thread. Client queries arriving on `control`
+/// are answered from a helper thread. Fails right away when the listener cannot start, e.g.
+/// off the main thread or on an unsupported platform.
+pub fn listener_run_with_config(
+    tx: mpsc::Sender<UiEvent>,
+    config: ListenerConfig,
+    control: Option<mpsc::Receiver<ControlRequest>>,
+) -> Result<(), UiEventsError> {
+    listener_run_stoppable(tx, config, control, &ListenerHandle::new())
+}
+
+/// Stops a running listener from any thread, see [`listener_run_stoppable`] and
+/// [`spawn_listener`]. Clones control the same listener.
+#[derive(Debug, Clone, Default)]
+pub struct ListenerHandle {
+    inner: Arc<HandleState>,
+}
+
+#[derive(Default)]
+struct HandleState {
+    stopped: AtomicBool,
+    // Set for spawn_listener: the listener lives on the host's main loop instead of its own
+    spawned: AtomicBool,
+    // The spawn_listener listener, once set up on the main thread
+    #[cfg(target_os = "macos")]
+    listener: Mutex<Option<std::pin::Pin<Arc<macos::MacosListener>>>>,
+}
+
+impl std::fmt::Debug for HandleState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("HandleState")
+            .field("stopped", &self.stopped)
+            .field("spawned", &self.spawned)
+            .finish_non_exhaustive()
+    }
+}
+
+impl ListenerHandle {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Stops capturing: the listener removes its observers and its run function returns.
+    /// For [`spawn_listener`], the observers are removed on the main thread and this returns
+    /// once they are gone. Safe to call from any thread and more than once; only the first
+    /// call has an effect.
+    pub fn stop(&self) {
+        if self.inner.stopped.swap(true, Ordering::SeqCst) {
+            return;
+        }
+        if !self.inner.spawned.load(Ordering::SeqCst) {
+            #[cfg(target_os = "macos")]
+            macos::stop_main_run_loop();
+            return;
+        }
+        // A listener not set up yet sees `stopped` and shuts itself down
+        #[cfg(target_os = "macos")]
+        {
+            let listener = self.inner.listener.lock().unwrap().take();
+            if let Some(listener) = listener {
+                macos::run_on_main_thread(true, move || listener.shutdown());
+            }
+        }
+    }
+
+    pub fn is_stopped(&self) -> bool {
+        self.inner.stopped.load(Ordering::SeqCst)
+    }
+}
+
+/// Like [`listener_run_with_config`], but returns once `handle` is stopped, after removing
+/// the listener's observers.
+pub fn listener_run_stoppable(
+    tx: mpsc::Sender<UiEvent>,
+    config: ListenerConfig,
+    control: Option<mpsc::Receiver<ControlRequest>>,
+    handle: &ListenerHandle,
+) -> Result<(), UiEventsError> {
+    #[cfg(target_os = "macos")]
+    {
+        use cidre::ns;
+        let listener = macos::MacosListener::new_on_main_thread(tx, config)?;
+        if let Some(mut control) = control {
+            let listener = listener.clone();
+            std::thread::spawn(move || {
+                while let Some(request) = control.blocking_recv() {
+                    listener.handle_control(request);
+                }
+            });
+        }
+        if !handle.is_stopped() {
+            ns::App::shared().run();
+        }
+        listener.shutdown();
+        Ok(())
+    }
+    #[cfg(not(target_os = "macos"))]
+    {
+        let _ = (tx, config, control, handle);
+        Err(UiEventsError::UnsupportedPlatform)
+    }
+}
+
+/// Starts the listener without blocking and returns a handle to stop it.
+///
+/// For hosts that already run the AppKit main event loop, such as GUI apps: the listener is
+/// set up on the main thread (right away when called there, else once the host's loop gets
+/// to it) and its observers are attached to the main run loop, so events flow while the host
+/// runs it. A process without one (a CLI) should use [`listener_run_stoppable`] on the main
+/// thread. Setup failures are logged.
+pub fn spawn_listener(tx: mpsc::Sender<UiEvent>, config: ListenerConfig) -> ListenerHandle {
+    let handle = ListenerHandle::new();
+    handle.inner.spawned.store(true, Ordering::SeqCst);
+    #[cfg(target_os = "macos")]
+    {
+        let state = handle.inner.clone();
+        macos::run_on_main_thread(false, move || {
+            let listener = match macos::MacosListener::new_on_main_thread(tx, config) {
+                Ok(listener) => listener,
+                Err(e) => {
+                    tracing::error!(error = %e, "failed to start listener");
+                    return;
+                }
+            };
+            let mut slot = state.listener.lock().unwrap();
+            if state.stopped.load(Ordering::SeqCst) {
+                drop(slot);
+                listener.shutdown();
+            } else {
+                *slot = Some(listener);
+            }
+        });
+    }
+    #[cfg(not(target_os = "macos"))]
+    let _ = (tx, config);
+    handle
+}
+
+/// Runs the listener on the current (main) thread, calling `callback` with every event.
+///
+/// The callback runs on a dedicated thread, one event at a time and in order. Keep it quick:
+/// while it blocks, events queue up (up to 100) and further ones are dropped by the listener.
+pub fn listener_run_with_callback(
+    config: ListenerConfig,
+    mut callback: impl FnMut(UiEvent) + Send + 'static,
+) -> Result<(), UiEventsError> {
+    let (tx, mut rx) = mpsc::channel(100);
+    std::thread::spawn(move || {
+        while let Some(event) = rx.blocking_recv() {
+            callback(event);
+        }
+    });
+    listener_run_with_config(tx, config, None)
+}
+
+/// Captures for `duration` and returns the events in order, e.g. for tests and scripted
+/// recordings.
+///
+/// Blocks the calling thread for the whole recording. It must be the main thread, and no
+/// AppKit run loop may be running yet: the listener runs it (like
+/// [`listener_run_with_config`]) and stops it when the time is up.
+pub fn record_for(duration: Duration) -> Result<Vec<UiEvent>, UiEventsError> {
+    record_for_with_config(duration, ListenerConfig::default())
+}
+
+/// Like [`record_for`], with listener options.
+pub fn record_for_with_config(
+    duration: Duration,
+    config: ListenerConfig,
+) -> Result<Vec<UiEvent>, UiEventsError> {
     #[cfg(target_os = "macos")]
     {
         use cidre::ns;
-        macos::MacosListener::new_on_main_thread(tx).unwrap();
-        ns::App::shared().run()
+        use tokio::sync::oneshot;
+
+        let (tx, mut rx) = mpsc::channel(100);
+        let listener = macos::MacosListener::new_on_main_thread(tx, config)?;
+
+        // Drain the channel while the main thread runs the loop, so it never fills up
+        let (done_tx, mut done_rx) = oneshot::channel::<()>();
+        let collector = std::thread::spawn(move || {
+            let rt = tokio::runtime::Builder::new_current_thread()
+                .build()
+                .expect("failed to build recorder runtime");
+            rt.block_on(async move {
+                let mut events = Vec::new();
+                loop {
+                    tokio::select! {
+                        Some(event) = rx.recv() => events.push(event),
+                        _ = &mut done_rx => break,
+                    }
+                }
+                // Whatever the listener flushed while shutting down
+                while let Ok(event) = rx.try_recv() {
+                    events.push(event);
+                }
+                events
+            })
+        });
+
+        let handle = ListenerHandle::new();
+        let timer = handle.clone();
+        std::thread::spawn(move || {
+            std::thread::sleep(duration);
+            timer.stop();
+        });
+        if !handle.is_stopped() {
+            ns::App::shared().run();
+        }
+        listener.shutdown();
+
+        let _ = done_tx.send(());
+        Ok(collector.join().unwrap_or_default())
+    }
+    #[cfg(not(target_os = "macos"))]
+    {
+        let _ = (duration, config);
+        Err(UiEventsError::UnsupportedPlatform)
+    }
+}
+
+/// The listener half of [`event_stream`], to be run on the main thread.
+pub struct StreamListener {
+    tx: mpsc::Sender<UiEvent>,
+    config: ListenerConfig,
+}
+
+impl StreamListener {
+    /// Runs the listener on the current (main) thread, feeding the stream.
+    pub fn run(self) -> Result<(), UiEventsError> {
+        listener_run_with_config(self.tx, self.config, None)
+    }
+}
+
+/// Events as a [`Stream`], for use with stream combinators (`filter`, `take`, ...).
+///
+/// The listener needs the main thread on macOS (AppKit run loop), so it is not started in
+/// the background: consume the stream on another thread and call [`StreamListener::run`] on
+/// the main thread. The stream ends when the listener stops. See
+/// `examples/focus_stream.rs`.
+pub fn event_stream(config: ListenerConfig) -> (impl Stream<Item = UiEvent>, StreamListener) {
+    let (tx, rx) = mpsc::channel(100);
+    (ReceiverStream::new(rx), StreamListener { tx, config })
+}
+
+// The macOS listener behind PlatformListener: `run` blocks on the main run loop, like
+// listener_run_with_config
+#[cfg(target_os = "macos")]
+struct MainThreadListener {
+    config: ListenerConfig,
+}
+
+#[cfg(target_os = "macos")]
+impl PlatformListener for MainThreadListener {
+    fn run(&self, sender: mpsc::Sender<UiEvent>) -> Result<()> {
+        listener_run_with_config(sender, self.config.clone(), None)?;
+        Ok(())
     }
 }
 
 /// Creates the appropriate platform listener.
-pub fn create_listener() -> Result<Box<dyn PlatformListener>> {
+pub fn create_listener() -> Result<Box<dyn PlatformListener>, UiEventsError> {
     #[cfg(target_os = "macos")]
     {
-        // use macos::MacosListener;
         info!("creating macos listener");
-        todo!();
-        // let listener = MacosListener::new()?;
-        // Ok(Box::new(listener))
+        Ok(Box::new(MainThreadListener {
+            config: ListenerConfig::default(),
+        }))
     }
     #[cfg(target_os = "windows")]
     {
@@ -54,6 +479,56 @@ pub fn create_listener() -> Result<Box<dyn PlatformListener>> {
     }
     #[cfg(not(any(target_os = "macos", target_os = "windows", target_os = "linux")))]
     {
-        anyhow::bail!("unsupported platform")
+        Err(UiEventsError::UnsupportedPlatform)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limiter(max_events_per_sec: Option<u32>, event_type_rates: &[(&str, u32)]) -> RateLimiter {
+        let config = ListenerConfig {
+            max_events_per_sec,
+            event_type_rates: event_type_rates
+                .iter()
+                .map(|(name, rate)| (name.to_string(), *rate))
+                .collect(),
+            ..Default::default()
+        };
+        RateLimiter::new(&config).unwrap()
+    }
+
+    #[test]
+    fn rate_limit_lets_through_about_the_cap() {
+        let limiter = limiter(Some(100), &[]);
+        let start = Instant::now();
+        // 1000 events/s for two seconds; the first second uses up the initial burst
+        let allowed = (0..2000)
+            .filter(|i| {
+                let now = start + Duration::from_millis(*i);
+                limiter.allow_at(&EventType::ValueChanged, now) && *i >= 1000
+            })
+            .count();
+        assert!((99..=101).contains(&allowed), "{} allowed", allowed);
+    }
+
+    #[test]
+    fn events_dropped_by_the_total_cap_keep_their_type_allowance() {
+        let limiter = limiter(Some(10), &[("ValueChanged", 2)]);
+        let start = Instant::now();
+        for _ in 0..10 {
+            assert!(limiter.allow_at(&EventType::ElementFocused, start));
+        }
+        for _ in 0..2 {
+            assert!(!limiter.allow_at(&EventType::ValueChanged, start));
+        }
+
+        // Half a second refills 5 total tokens; ValueChanged still has both of its own
+        let later = start + Duration::from_millis(500);
+        for _ in 0..2 {
+            assert!(limiter.allow_at(&EventType::ValueChanged, later));
+        }
+        assert!(!limiter.allow_at(&EventType::ValueChanged, later));
     }
 }
@@ -1,6 +1,7 @@
 #![cfg(target_os = "windows")]
 
 use super::PlatformListener;
+use crate::error::UiEventsError;
 use crate::event::UiEvent;
 use anyhow::Result;
 use tokio::sync::mpsc;
@@ -8,8 +9,9 @@ use tokio::sync::mpsc;
 pub struct WindowsListener {}
 
 impl WindowsListener {
-    pub fn new() -> Result<Self> {
-        anyhow::bail!("windows listener not implemented")
+    pub fn new() -> Result<Self, UiEventsError> {
+        // TODO: Implement using UI Automation
+        Err(UiEventsError::UnsupportedPlatform)
     }
 }
 
@@ -0,0 +1,133 @@
+// Tauri plugin: captures UI events inside a Tauri app and emits them to its webviews, no
+// websocket needed
+
+use crate::config::ListenerConfig;
+use crate::error::UiEventsError;
+use crate::platform::{
+    ListenerHandle, accessibility_granted, request_accessibility, spawn_listener,
+};
+use serde::Serialize;
+use std::sync::Mutex;
+use tauri::plugin::{Builder, TauriPlugin};
+use tauri::{AppHandle, Emitter, Manager, Runtime, State};
+use tokio::sync::mpsc;
+use tracing::{error, info};
+
+/// Name of the Tauri event carrying each `UiEvent`, e.g. `listen("ui-event", ...)` in the
+/// frontend.
+pub const EVENT_NAME: &str = "ui-event";
+
+// Listener options and the running capture, if any
+struct Capture {
+    config: ListenerConfig,
+    handle: Mutex<Option<ListenerHandle>>,
+}
+
+impl Capture {
+    fn start<R: Runtime>(&self, app: &AppHandle<R>) -> Result<bool, UiEventsError> {
+        let mut handle = self.handle.lock().unwrap();
+        if handle.is_some() {
+            return Ok(false);
+        }
+        if !accessibility_granted() {
+            return Err(UiEventsError::AccessibilityDenied);
+        }
+        let (tx, mut rx) = mpsc::channel(100);
+        // Tauri runs the AppKit main event loop, which the spawned listener's observers use
+        *handle = Some(spawn_listener(tx, self.config.clone()));
+        let app = app.clone();
+        std::thread::spawn(move || {
+            while let Some(event) = rx.blocking_recv() {
+                if let Err(e) = app.emit(EVENT_NAME, &event) {
+                    error!(error = %e, "failed to emit ui event to the webview");
+                }
+            }
+        });
+        info!("ui event capture started");
+        Ok(true)
+    }
+
+    fn stop(&self) -> bool {
+        let Some(handle) = self.handle.lock().unwrap().take() else {
+            return false;
+        };
+        handle.stop();
+        info!("ui event capture stopped");
+        true
+    }
+}
+
+/// Reply of the `permission_status` command.
+#[derive(Debug, Clone, Serialize)]
+pub struct PermissionStatus {
+    pub accessibility: bool,
+}
+
+// Start capturing; false when already capturing
+#[tauri::command]
+fn start_capture<R: Runtime>(
+    app: AppHandle<R>,
+    capture: State<'_, Capture>,
+) -> Result<bool, String> {
+    capture.start(&app).map_err(|e| e.to_string())
+}
+
+// Stop capturing; false when not capturing
+#[tauri::command]
+fn stop_capture(capture: State<'_, Capture>) -> bool {
+    capture.stop()
+}
+
+#[tauri::command]
+fn permission_status() -> PermissionStatus {
+    PermissionStatus {
+        accessibility: accessibility_granted(),
+    }
+}
+
+// Show the system's permission dialog if needed; returns the status at the time of the call
+#[tauri::command]
+fn request_permission() -> PermissionStatus {
+    PermissionStatus {
+        accessibility: request_accessibility(),
+    }
+}
+
+/// The plugin with the default [`ListenerConfig`], see [`tauri_plugin_with_config`].
+pub fn tauri_plugin<R: Runtime>() -> TauriPlugin<R> {
+    tauri_plugin_with_config(ListenerConfig::default())
+}
+
+/// Captures UI events in a Tauri app and emits each `UiEvent` to the webviews as
+/// [`EVENT_NAME`]: `tauri::Builder::default().plugin(ui_events::tauri_plugin())`.
+///
+/// Capture starts with the app when the accessibility permission is granted. The frontend
+/// controls it with the commands `plugin:ui-events|start_capture`, `stop_capture`,
+/// `permission_status` and `request_permission`; allow them in the app's capabilities with
+/// `ui-events:default`.
+pub fn tauri_plugin_with_config<R: Runtime>(config: ListenerConfig) -> TauriPlugin<R> {
+    Builder::new("ui-events")
+        .invoke_handler(tauri::generate_handler![
+            start_capture,
+            stop_capture,
+            permission_status,
+            request_permission
+        ])
+        .setup(move |app, _api| {
+            let capture = Capture {
+                config,
+                handle: Mutex::new(None),
+            };
+            if let Err(e) = capture.start(app) {
+                info!(error = %e, "ui event capture not started");
+            }
+            app.manage(capture);
+            Ok(())
+        })
+        .on_drop(|app| {
+            if let Some(capture) = app.try_state::<Capture>() {
+                capture.stop();
+            }
+        })
+        .build()
+}
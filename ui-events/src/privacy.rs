@@ -0,0 +1,100 @@
+// Privacy transforms applied to events before they leave the listener
+
+use crate::event::UiEvent;
+use rand::RngCore;
+use sha2::{Digest, Sha256};
+
+/// Keys of `event_specific_data` that carry user content and are hashed too.
+pub const TEXT_DATA_KEYS: [&str; 11] = [
+    "url",
+    "title",
+    "selected_text",
+    "line",
+    "item_title",
+    "menu_title",
+    "menu_path",
+    "selected_titles",
+    "characters",
+    "texts",
+    "buttons",
+];
+
+/// Replaces captured text with salted SHA-256 digests.
+///
+/// Hashing is one-way: identical inputs map to identical digests within the same salt, so
+/// consumers can still correlate values (e.g. the same search term typed twice) without
+/// seeing the plaintext. Without an explicit salt a random one is generated, which makes
+/// digests comparable only within a single session.
+#[derive(Debug, Clone)]
+pub struct ValueHasher {
+    salt: Vec<u8>,
+}
+
+impl ValueHasher {
+    pub fn new(salt: Option<&str>) -> Self {
+        let salt = match salt {
+            Some(salt) => salt.as_bytes().to_vec(),
+            None => {
+                let mut salt = vec![0u8; 16];
+                rand::thread_rng().fill_bytes(&mut salt);
+                salt
+            }
+        };
+        Self { salt }
+    }
+
+    /// Digest of `value`, formatted as `sha256:<hex>`.
+    pub fn hash_str(&self, value: &str) -> String {
+        let digest = Sha256::new()
+            .chain_update(&self.salt)
+            .chain_update(value.as_bytes())
+            .finalize();
+        let hex: String = digest.iter().map(|b| format!("{:02x}", b)).collect();
+        format!("sha256:{}", hex)
+    }
+
+    /// Hashes element values, identifiers, placeholders and path labels, the window title,
+    /// and free-text fields of `event_specific_data` (see [`TEXT_DATA_KEYS`]) in place.
+    pub fn apply(&self, event: &mut UiEvent) {
+        if let Some(window) = event.window.as_mut() {
+            if let Some(title) = window.title.as_mut() {
+                *title = self.hash_str(title);
+            }
+        }
+        if let Some(element) = event.element.as_mut() {
+            if let Some(identifier) = element.identifier.as_mut() {
+                *identifier = self.hash_str(identifier);
+            }
+            if let Some(value) = element.value.as_mut() {
+                self.hash_json(value);
+            }
+            if let Some(placeholder) = element.placeholder.as_mut() {
+                *placeholder = self.hash_str(placeholder);
+            }
+            // Only the label of `role[label]` is user content
+            for segment in element.path.iter_mut().flatten() {
+                if let Some((role, label)) = segment.split_once('[') {
+                    let label = label.strip_suffix(']').unwrap_or(label);
+                    *segment = format!("{}[{}]", role, self.hash_str(label));
+                }
+            }
+        }
+        if let Some(serde_json::Value::Object(data)) = event.event_specific_data.as_mut() {
+            for key in TEXT_DATA_KEYS {
+                if let Some(value) = data.get_mut(key) {
+                    self.hash_json(value);
+                }
+            }
+        }
+    }
+
+    // Hash every string inside a JSON value, leaving numbers/booleans untouched
+    fn hash_json(&self, value: &mut serde_json::Value) {
+        match value {
+            serde_json::Value::String(s) => *s = self.hash_str(s),
+            serde_json::Value::Array(items) => items.iter_mut().for_each(|v| self.hash_json(v)),
+            serde_json::Value::Object(map) => map.values_mut().for_each(|v| self.hash_json(v)),
+            _ => {}
+        }
+    }
+}
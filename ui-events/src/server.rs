@@ -1,59 +1,835 @@
 // Placeholder for websocket server implementation
+//
+// Ordering: events are numbered (`UiEvent::seq`) and serialized by a single broadcaster
+// task, in the order the listener produced them. Each connection is served by one task that
+// forwards from its own broadcast receiver and never sends concurrently, so every client sees
+// strictly increasing `seq`. Keep it that way: any parallelism added to a connection's send
+// path must preserve this order.
+//
+// Server-Sent Events: with `ServerConfig::sse_port`, the same stream is also served over
+// plain HTTP (see `sse`), fed by the same broadcaster.
+//
+// Encodings: events go out as JSON (built by the broadcaster, also used for SSE, reliable
+// sinks and the replay backlog), as msgpack for clients asking for it, or in the encoding of
+// `ServerConfig::serializer`, which the broadcaster runs once per event.
+//
+// Compression: permessage-deflate (RFC 7692) is negotiated with clients offering it, unless
+// `ServerConfig::compression` is off. tungstenite (0.21) does not implement the extension, so
+// `deflate` does: event frames are compressed there, and compressed client messages are
+// inflated before tungstenite reads them. Replies, notices and control frames are sent
+// uncompressed, which the extension allows per message.
 
-use crate::event::UiEvent;
+use crate::control::{
+    Authenticate, ClientMessage, ClientQuery, ControlRequest, ServerCommand, SetFormat, Subscribe,
+    WireFormat,
+};
+use crate::error::UiEventsError;
+use crate::event::{EventType, SCHEMA_VERSION, UiEvent};
+use crate::metrics;
+use crate::sink::EventEncoder;
 use anyhow::{Context, Result};
+use deflate::{Deflater, InflateFrames};
 use futures_util::{SinkExt, StreamExt};
-use std::net::SocketAddr;
+use std::collections::VecDeque;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, BufReader};
 use tokio::net::{TcpListener, TcpStream};
-use tokio::sync::{broadcast, mpsc};
-use tokio_tungstenite::{accept_async, tungstenite::protocol::Message};
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::{broadcast, mpsc, oneshot, watch};
+use tokio::task::JoinSet;
+use tokio::time::{Instant, interval_at, timeout};
+use tokio_tungstenite::accept_hdr_async;
+use tokio_tungstenite::tungstenite::handshake::server::{Request, Response};
+use tokio_tungstenite::tungstenite::http::HeaderValue;
+use tokio_tungstenite::tungstenite::protocol::frame::coding::CloseCode;
+use tokio_tungstenite::tungstenite::protocol::{CloseFrame, Message};
 use tracing::{debug, error, info, warn};
 
+mod deflate;
+mod sse;
+
+/// Why the server ended a connection, sent to the client in the Close frame.
+///
+/// - `1000 normal`: client asked to close (echoed by the closing handshake)
+/// - `1001 going away`: server shutting down or event source stopped
+/// - `1008 policy violation`: client not allowed (e.g. failed authentication)
+/// - `1011 internal error`: server-side error
+/// - `4000 slow consumer`: client fell too far behind the event stream
+/// - `4001 rate limited`: client exceeded a request rate limit
+///
+/// 1000-1011 are standard codes, 4000-4999 are reserved for applications. Clients should
+/// reconnect after 1001, 1011 and 4000, and give up after 1008.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CloseReason {
+    Normal,
+    GoingAway,
+    PolicyViolation,
+    InternalError,
+    SlowConsumer,
+    RateLimited,
+}
+
+impl CloseReason {
+    pub fn code(self) -> u16 {
+        match self {
+            CloseReason::Normal => 1000,
+            CloseReason::GoingAway => 1001,
+            CloseReason::PolicyViolation => 1008,
+            CloseReason::InternalError => 1011,
+            CloseReason::SlowConsumer => 4000,
+            CloseReason::RateLimited => 4001,
+        }
+    }
+
+    pub fn reason(self) -> &'static str {
+        match self {
+            CloseReason::Normal => "normal",
+            CloseReason::GoingAway => "going away",
+            CloseReason::PolicyViolation => "policy violation",
+            CloseReason::InternalError => "internal error",
+            CloseReason::SlowConsumer => "slow consumer",
+            CloseReason::RateLimited => "rate limited",
+        }
+    }
+
+    fn frame(self) -> CloseFrame<'static> {
+        CloseFrame {
+            code: CloseCode::from(self.code()),
+            reason: self.reason().into(),
+        }
+    }
+}
+
+/// What the server does once the listener stops sending events (its mpsc sender dropped).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SourceClosedBehavior {
+    /// Stop accepting connections and return from `run_server`. Connected clients are closed
+    /// with code 1001 (going away).
+    #[default]
+    Shutdown,
+    /// Keep the server up, broadcast a `SourceStopped` event to connected clients and send it
+    /// to every client connecting afterwards, so nobody waits on a dead stream unknowingly.
+    Notify,
+}
+
+/// What happens to a client that falls more than the broadcast capacity behind, i.e. events
+/// were dropped for it before it could read them.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum LagBehavior {
+    /// Continue with the oldest event still buffered; the client sees a gap in `seq`.
+    #[default]
+    Skip,
+    /// Like `Skip`, but first tell the client with a text message
+    /// `{"warning":"lagged","skipped":<n>}`.
+    Notify,
+    /// Close the connection with 4000 (slow consumer).
+    Disconnect,
+}
+
+/// How an [`EventSerializer`]'s output is framed on the websocket.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ContentType {
+    /// Text frames; the output must be UTF-8.
+    Text,
+    /// Binary frames.
+    Binary,
+}
+
+/// Encoding of the events sent to websocket clients, see `ServerConfig::serializer`.
+pub trait EventSerializer: std::fmt::Debug + Send + Sync {
+    fn serialize(&self, event: &UiEvent) -> Result<Vec<u8>>;
+    fn content_type(&self) -> ContentType;
+}
+
+/// Plain `serde_json` encoding, the same as the server's default except that it ignores
+/// `ServerConfig::omit_null_fields`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct JsonSerializer;
+
+impl EventSerializer for JsonSerializer {
+    fn serialize(&self, event: &UiEvent) -> Result<Vec<u8>> {
+        serde_json::to_vec(event).context("failed to serialize uievent to json")
+    }
+
+    fn content_type(&self) -> ContentType {
+        ContentType::Text
+    }
+}
+
+/// Certificate and key for serving `wss://`, both PEM files. The certificate file may hold
+/// a chain (leaf first); the key may be PKCS#8, PKCS#1 or SEC1.
+#[cfg(feature = "tls")]
+#[derive(Debug, Clone)]
+pub struct TlsConfig {
+    pub cert_path: std::path::PathBuf,
+    pub key_path: std::path::PathBuf,
+}
+
+#[cfg(feature = "tls")]
+fn tls_acceptor(tls: &TlsConfig) -> Result<tokio_rustls::TlsAcceptor> {
+    use std::fs::File;
+    use std::io::BufReader;
+    use tokio_rustls::rustls;
+
+    let open = |path: &std::path::Path| {
+        File::open(path)
+            .map(BufReader::new)
+            .with_context(|| format!("failed to open {}", path.display()))
+    };
+    let certs = rustls_pemfile::certs(&mut open(&tls.cert_path)?)
+        .collect::<Result<Vec<_>, _>>()
+        .with_context(|| {
+            format!(
+                "failed to read certificates from {}",
+                tls.cert_path.display()
+            )
+        })?;
+    let key = rustls_pemfile::private_key(&mut open(&tls.key_path)?)
+        .with_context(|| format!("failed to read private key from {}", tls.key_path.display()))?
+        .with_context(|| format!("no private key in {}", tls.key_path.display()))?;
+    let config = rustls::ServerConfig::builder()
+        .with_no_client_auth()
+        .with_single_cert(certs, key)
+        .context("invalid tls certificate or key")?;
+    Ok(tokio_rustls::TlsAcceptor::from(Arc::new(config)))
+}
+
+/// Options for [`run_server_with_config`].
+#[derive(Debug, Clone)]
+pub struct ServerConfig {
+    /// Address to listen on; `None` (default) is loopback (127.0.0.1) only. Use
+    /// `0.0.0.0` to accept clients from other machines, which then see every event.
+    pub host: Option<IpAddr>,
+    /// Where to forward client queries (see [`ClientQuery`]). Without it, queries are
+    /// answered with an error.
+    pub control: Option<mpsc::Sender<ControlRequest>>,
+    pub on_source_closed: SourceClosedBehavior,
+    /// Leave out `null` fields of the event, application, window and element objects instead
+    /// of sending them as `null`. Sparse events (most focus changes) get about a third
+    /// smaller. Off by default since it changes the wire shape; clients must treat a missing
+    /// key like `null` (serde-based clients such as `UiEvent` itself already do).
+    /// `event_specific_data` is sent as is.
+    pub omit_null_fields: bool,
+    /// Sinks that must see every event (e.g. a file or audit log), fed the same serialized
+    /// events as websocket clients, in order. Unlike websocket clients, which skip events when
+    /// they lag, the server waits for room in a reliable sink before moving on.
+    ///
+    /// That wait is backpressure: a slow or stuck reliable sink stalls delivery to every
+    /// client, and once the listener's channel is full the listener itself drops events (with
+    /// an error log). Give these channels enough capacity and drain them promptly. A sink
+    /// whose receiver is dropped is removed.
+    pub reliable_sinks: Vec<mpsc::Sender<String>>,
+    /// Liveness: ping each client at this interval and drop it once `max_missed_pongs` pings
+    /// in a row went unanswered, so half-open connections do not hold on to a subscription.
+    /// `None` uses [`DEFAULT_PING_INTERVAL`]; `Some(Duration::ZERO)` sends no pings. SSE
+    /// clients get a keepalive comment at the same interval instead.
+    pub ping_interval: Option<Duration>,
+    /// Unanswered pings after which a client is dropped. `None` uses
+    /// [`DEFAULT_MAX_MISSED_PONGS`].
+    pub max_missed_pongs: Option<u32>,
+    /// Throughput: close a client with 4000 (slow consumer) when sending it one message
+    /// takes longer than this, i.e. it stopped reading and its TCP buffers are full. Catches
+    /// clients that still answer pings but do not drain events. `None` (default) waits
+    /// forever.
+    pub send_timeout: Option<Duration>,
+    /// Set to `true` to stop the server: it stops accepting, closes every client with 1001
+    /// (going away) and `run_server_with_config` returns `Ok(())`.
+    pub shutdown: Option<watch::Receiver<bool>>,
+    /// How many recent events a newly connected client receives before live events, so it
+    /// does not start blind mid-session. `None` uses [`DEFAULT_REPLAY_BACKLOG`]; `Some(0)`
+    /// disables replay. Replayed events are sent as is (before any subscription or format
+    /// message from the client could arrive, but in the format of a `?format=` query) and
+    /// keep their original `seq`.
+    pub replay_backlog: Option<usize>,
+    pub on_lag: LagBehavior,
+    /// Serve `wss://` with this certificate instead of plain `ws://`. Clients that fail the
+    /// TLS handshake are dropped before the websocket handshake.
+    #[cfg(feature = "tls")]
+    pub tls: Option<TlsConfig>,
+    /// Shared secret clients must present before receiving anything, either as
+    /// `Authorization: Bearer <token>` in the handshake or as an `Authenticate` first
+    /// message. Clients that fail are closed with 1008 (policy violation). `None` (default)
+    /// lets every client in.
+    pub auth_token: Option<String>,
+    /// Also serve events as Server-Sent Events at `http://<host>:<sse_port>/events`, e.g.
+    /// for `EventSource` or `curl -N`. Each event is sent as `id: <seq>` and `data: <json>`;
+    /// clients reconnecting with `Last-Event-ID` resume after that `seq` from the replay
+    /// backlog. Always plain HTTP, also with `tls`; `auth_token` is checked against the
+    /// `Authorization: Bearer` header. No CORS headers are sent, so pages on other origins
+    /// cannot read it. `None` (default) serves websocket only.
+    pub sse_port: Option<u16>,
+    /// Encode events for websocket clients with this instead of the built-in JSON, e.g. to
+    /// try CBOR or protobuf without forking the server. Applies to clients using the default
+    /// `WireFormat::Json` (clients asking for msgpack still get msgpack), including replayed
+    /// events. Replies, notices and the `SourceStopped` event stay JSON, as do SSE and
+    /// `reliable_sinks`. An event the serializer fails on is sent as JSON. `None` (default)
+    /// uses the built-in JSON.
+    pub serializer: Option<Arc<dyn EventSerializer>>,
+    /// Negotiate permessage-deflate with websocket clients that offer it (browsers do), so
+    /// events reach them compressed; the repetitive JSON shrinks several times over.
+    /// Compressing costs some CPU time per event and client, so turn it off when latency
+    /// matters more than bandwidth. Replies and notices are sent uncompressed. On by default.
+    pub compression: bool,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            host: None,
+            control: None,
+            on_source_closed: SourceClosedBehavior::default(),
+            omit_null_fields: false,
+            reliable_sinks: Vec::new(),
+            ping_interval: None,
+            max_missed_pongs: None,
+            send_timeout: None,
+            shutdown: None,
+            replay_backlog: None,
+            on_lag: LagBehavior::default(),
+            #[cfg(feature = "tls")]
+            tls: None,
+            auth_token: None,
+            sse_port: None,
+            serializer: None,
+            compression: true,
+        }
+    }
+}
+
+/// Default for `ServerConfig::replay_backlog`.
+pub const DEFAULT_REPLAY_BACKLOG: usize = 100;
+
+/// Default for `ServerConfig::ping_interval`.
+pub const DEFAULT_PING_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Default for `ServerConfig::max_missed_pongs`.
+pub const DEFAULT_MAX_MISSED_PONGS: u32 = 2;
+
+// How long a client may take to send its token
+const AUTH_TIMEOUT: Duration = Duration::from_secs(10);
+
+// How long a client may take to finish the TLS handshake, after which it is dropped
+#[cfg(feature = "tls")]
+const TLS_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+// Compare without an early exit, so timing does not reveal how much of a token matched
+fn tokens_match(given: &str, expected: &str) -> bool {
+    given.len() == expected.len()
+        && given
+            .bytes()
+            .zip(expected.bytes())
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+// The token of the client's first message, if it is an `Authenticate` sent in time
+async fn read_token<S>(ws_receiver: &mut S) -> Option<String>
+where
+    S: futures_util::Stream<Item = Result<Message, tokio_tungstenite::tungstenite::Error>> + Unpin,
+{
+    let first = timeout(AUTH_TIMEOUT, ws_receiver.next())
+        .await
+        .ok()??
+        .ok()?;
+    let Message::Text(text) = first else {
+        return None;
+    };
+    serde_json::from_str::<Authenticate>(&text)
+        .ok()
+        .map(|auth| auth.token)
+}
+
+// Upper bound on sending the final Close frame, which a stuck client would block forever
+const CLOSE_SEND_TIMEOUT: Duration = Duration::from_secs(1);
+
+// How long shutdown waits for connections to close before aborting them
+const SHUTDOWN_GRACE_PERIOD: Duration = Duration::from_secs(5);
+
+// Resolves once shutdown is requested. Never resolves without a shutdown receiver or once
+// its sender is gone.
+async fn shutdown_requested(shutdown: &mut Option<watch::Receiver<bool>>) {
+    if let Some(shutdown) = shutdown {
+        if shutdown.wait_for(|stop| *stop).await.is_ok() {
+            return;
+        }
+    }
+    std::future::pending().await
+}
+
+// A serialized event with its type (`EventType` display form), so connections can filter
+// without parsing the JSON. The event itself is kept for clients using another `WireFormat`,
+// `encoded` is the frame from `ServerConfig::serializer`.
+#[derive(Debug, Clone)]
+struct Broadcast {
+    event_type: String,
+    json: String,
+    event: Arc<UiEvent>,
+    encoded: Option<Message>,
+}
+
+// Run the configured serializer, falling back to the JSON frame when it fails
+fn serializer_message(serializer: &dyn EventSerializer, event: &UiEvent) -> Option<Message> {
+    let encoded = serializer
+        .serialize(event)
+        .and_then(|bytes| match serializer.content_type() {
+            ContentType::Text => String::from_utf8(bytes)
+                .map(Message::Text)
+                .context("serializer produced text that is not utf-8"),
+            ContentType::Binary => Ok(Message::Binary(bytes)),
+        });
+    match encoded {
+        Ok(message) => Some(message),
+        Err(e) => {
+            error!(error = %e, "failed to serialize uievent, sending json");
+            None
+        }
+    }
+}
+
+// The frame carrying a broadcast event in the client's format
+fn event_message(format: WireFormat, broadcast: Broadcast) -> Result<Message> {
+    match format {
+        WireFormat::Json => Ok(broadcast.encoded.unwrap_or(Message::Text(broadcast.json))),
+        WireFormat::Msgpack => msgpack_message(&broadcast.event),
+    }
+}
+
+// An event frame as sent to the client, compressed when permessage-deflate was agreed on
+fn compressed(deflater: &mut Option<Deflater>, message: Message) -> Result<Message> {
+    match deflater {
+        Some(deflater) => deflater.compress(message),
+        None => Ok(message),
+    }
+}
+
+// Struct fields are encoded as map keys (like the JSON) so clients can decode by name
+#[cfg(feature = "msgpack")]
+fn msgpack_message(event: &UiEvent) -> Result<Message> {
+    let bytes = rmp_serde::to_vec_named(event).context("failed to encode event as msgpack")?;
+    Ok(Message::Binary(bytes))
+}
+
+// Unreachable in practice: clients cannot select msgpack without the feature
+#[cfg(not(feature = "msgpack"))]
+fn msgpack_message(_event: &UiEvent) -> Result<Message> {
+    anyhow::bail!("built without msgpack support")
+}
+
+// Whether a client's subscription selects an event type. `Custom` selects every
+// `Custom:<notification>`.
+fn subscribed(subscription: Option<&[String]>, event_type: &str) -> bool {
+    let Some(types) = subscription else {
+        return true;
+    };
+    let variant = event_type.split(':').next().unwrap_or(event_type);
+    types.iter().any(|t| t == event_type || t == variant)
+}
+
+// State shared by the accept loop and every connection
+#[derive(Debug)]
+struct ServerState {
+    config: ServerConfig,
+    // Serialized SourceStopped event, once the listener has gone away (Notify mode)
+    source_stopped: Mutex<Option<String>>,
+    // Most recent broadcasts, oldest first, replayed to new connections
+    backlog: Mutex<VecDeque<Broadcast>>,
+    // The listener's latest ListenerReady, sent to every new connection first
+    ready: Mutex<Option<Broadcast>>,
+    // Open connections, for `ServerCommand::Stats`
+    clients: AtomicUsize,
+    started: Instant,
+}
+
+impl ServerState {
+    fn new(config: ServerConfig) -> Self {
+        Self {
+            config,
+            source_stopped: Mutex::new(None),
+            backlog: Mutex::new(VecDeque::new()),
+            ready: Mutex::new(None),
+            clients: AtomicUsize::new(0),
+            started: Instant::now(),
+        }
+    }
+
+    fn stats(&self) -> serde_json::Value {
+        serde_json::json!({
+            "cmd": "stats",
+            "clients": self.clients.load(Ordering::Relaxed),
+            "uptime_secs": self.started.elapsed().as_secs(),
+            "source_running": self.source_stopped.lock().unwrap().is_none(),
+        })
+    }
+
+    // Interval between pings to each client, None when pings are off
+    fn ping_interval(&self) -> Option<Duration> {
+        match self.config.ping_interval {
+            None => Some(DEFAULT_PING_INTERVAL),
+            Some(interval) if interval.is_zero() => None,
+            interval => interval,
+        }
+    }
+
+    // Keep a broadcast for replay, evicting the oldest beyond the configured size
+    fn remember(&self, broadcast: &Broadcast) {
+        if matches!(broadcast.event.event_type, EventType::ListenerReady) {
+            *self.ready.lock().unwrap() = Some(broadcast.clone());
+        }
+        let capacity = self.config.replay_backlog.unwrap_or(DEFAULT_REPLAY_BACKLOG);
+        if capacity == 0 {
+            return;
+        }
+        let mut backlog = self.backlog.lock().unwrap();
+        if backlog.len() >= capacity {
+            backlog.pop_front();
+        }
+        backlog.push_back(broadcast.clone());
+    }
+
+    // What a new client is sent before live events, in order: the latest ListenerReady (even
+    // once it left the backlog, so every client learns whether capture is live), then the
+    // backlog without it
+    fn catch_up(&self) -> Vec<Broadcast> {
+        let ready = self.ready.lock().unwrap().clone();
+        let ready_seq = ready.as_ref().map(|ready| ready.event.seq);
+        let backlog = self.backlog.lock().unwrap();
+        ready
+            .into_iter()
+            .chain(
+                backlog
+                    .iter()
+                    .filter(|broadcast| Some(broadcast.event.seq) != ready_seq)
+                    .cloned(),
+            )
+            .collect()
+    }
+}
+
+// Hand an event to every reliable sink, waiting for room; drops sinks that went away
+async fn feed_reliable_sinks(sinks: &mut Vec<mpsc::Sender<String>>, json_str: &str) {
+    let mut open = Vec::with_capacity(sinks.len());
+    for sink in sinks.drain(..) {
+        match sink.send(json_str.to_owned()).await {
+            Ok(()) => open.push(sink),
+            Err(_) => warn!("reliable sink closed, removing it"),
+        }
+    }
+    *sinks = open;
+}
+
+// Answer a client query, returning the JSON reply to send back
+async fn answer_query(
+    query: ClientQuery,
+    control: Option<&mpsc::Sender<ControlRequest>>,
+) -> serde_json::Value {
+    let ClientQuery::Describe { element_key } = &query;
+    let mut reply = serde_json::json!({
+        "query": "describe",
+        "element_key": element_key,
+    });
+
+    let result = match control {
+        None => Err("queries are not enabled on this server".to_string()),
+        Some(control) => {
+            let (reply_tx, reply_rx) = oneshot::channel();
+            let request = ControlRequest {
+                query,
+                reply: reply_tx,
+            };
+            match control.send(request).await {
+                Ok(()) => reply_rx
+                    .await
+                    .unwrap_or_else(|_| Err("listener dropped the query".to_string())),
+                Err(_) => Err("listener is not running".to_string()),
+            }
+        }
+    };
+
+    match result {
+        Ok(value) => reply["result"] = value,
+        Err(e) => reply["error"] = e.into(),
+    }
+    reply
+}
+
 async fn handle_connection(
-    peer: SocketAddr,
-    stream: TcpStream,
-    mut broadcast_rx: broadcast::Receiver<String>, // Receiver for serialized events
+    peer: String,
+    stream: impl AsyncRead + AsyncWrite + Unpin,
+    mut broadcast_rx: broadcast::Receiver<Broadcast>, // Receiver for serialized events
+    state: Arc<ServerState>,
 ) -> Result<()> {
-    let ws_stream = accept_async(stream)
-        .await
-        .context("error during websocket handshake")?;
-    info!(%peer, "new websocket connection established");
+    // Event encoding for this client, from `?format=` or a later `SetFormat` message
+    let mut format = WireFormat::Json;
+    let mut agreement = None;
+    let mut header_token = None;
+    let mut ws_stream = accept_hdr_async(
+        InflateFrames::new(stream),
+        // The error type is tungstenite's
+        #[allow(clippy::result_large_err)]
+        |request: &Request, mut response: Response| {
+            if let Some(requested) = request.uri().query().and_then(WireFormat::from_query) {
+                format = requested;
+            }
+            if state.config.compression {
+                let offers = request.headers().get_all("sec-websocket-extensions");
+                agreement = deflate::negotiate(offers.iter().filter_map(|v| v.to_str().ok()));
+            }
+            if let Some(agreement) = agreement {
+                response.headers_mut().insert(
+                    "sec-websocket-extensions",
+                    HeaderValue::from_static(agreement.header()),
+                );
+            }
+            header_token = request
+                .headers()
+                .get("authorization")
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| value.strip_prefix("Bearer "))
+                .map(str::to_owned);
+            Ok(response)
+        },
+    )
+    .await
+    .context("error during websocket handshake")?;
+    if format == WireFormat::Msgpack && !cfg!(feature = "msgpack") {
+        warn!(%peer, "client asked for msgpack, but it is not compiled in; using json");
+        format = WireFormat::Json;
+    }
+    let mut deflater = agreement.map(|agreement| {
+        ws_stream.get_mut().enable();
+        Deflater::new(agreement)
+    });
+    info!(
+        %peer,
+        format = format.as_str(),
+        compress = deflater.is_some(),
+        "new websocket connection established"
+    );
 
     let (mut ws_sender, mut ws_receiver) = ws_stream.split();
 
-    loop {
+    if let Some(expected) = state.config.auth_token.as_deref() {
+        let token = match header_token {
+            Some(token) => Some(token),
+            None => read_token(&mut ws_receiver).await,
+        };
+        if !token.is_some_and(|token| tokens_match(&token, expected)) {
+            warn!(%peer, "client failed to authenticate, closing connection");
+            let close = Message::Close(Some(CloseReason::PolicyViolation.frame()));
+            let _ = timeout(CLOSE_SEND_TIMEOUT, ws_sender.send(close)).await;
+            let _ = timeout(CLOSE_SEND_TIMEOUT, ws_sender.close()).await;
+            return Ok(());
+        }
+        let ack = serde_json::json!({ "authenticated": true });
+        if ws_sender
+            .send(Message::Text(ack.to_string()))
+            .await
+            .is_err()
+        {
+            return Ok(());
+        }
+        debug!(%peer, "client authenticated");
+    }
+
+    // Catch the client up. The broadcast receiver was subscribed before this snapshot, so
+    // events in both are skipped below by `seq`: replay first, then live, no duplicates.
+    let catch_up = state.catch_up();
+    let mut replayed_up_to = 0;
+    if !catch_up.is_empty() {
+        debug!(%peer, events = catch_up.len(), "replaying recent events");
+    }
+    for broadcast in catch_up {
+        replayed_up_to = replayed_up_to.max(broadcast.event.seq);
+        match event_message(format, broadcast).and_then(|m| compressed(&mut deflater, m)) {
+            Ok(message) => {
+                if let Err(e) = ws_sender.send(message).await {
+                    warn!(%peer, error = %e, "failed to replay events, disconnecting");
+                    return Ok(());
+                }
+            }
+            Err(e) => error!(%peer, error = %e, "failed to encode event for client"),
+        }
+    }
+
+    // No events will ever arrive, say so instead of leaving the client waiting
+    let source_stopped = state.source_stopped.lock().unwrap().clone();
+    if let Some(stopped_event) = source_stopped {
+        let _ = ws_sender.send(Message::Text(stopped_event)).await;
+        let _ = ws_sender
+            .send(Message::Close(Some(CloseReason::GoingAway.frame())))
+            .await;
+        let _ = ws_sender.close().await;
+        return Ok(());
+    }
+
+    // The period only matters when pings are enabled (the select branch is disabled otherwise)
+    let ping_interval = state.ping_interval();
+    let ping_period = ping_interval.unwrap_or(Duration::from_secs(3600));
+    let mut ping_timer = interval_at(Instant::now() + ping_period, ping_period);
+    let max_missed_pongs = state
+        .config
+        .max_missed_pongs
+        .unwrap_or(DEFAULT_MAX_MISSED_PONGS)
+        .max(1);
+    // Pings sent since the last pong
+    let mut unanswered_pings: u32 = 0;
+    let mut shutdown = state.config.shutdown.clone();
+    // Event types this client asked for, None for all (see `Subscribe`)
+    let mut subscription: Option<Vec<String>> = None;
+
+    // None when the connection is already unusable and no Close frame can be sent
+    let close_reason: Option<CloseReason> = loop {
         tokio::select! {
+            _ = shutdown_requested(&mut shutdown) => {
+                info!(%peer, "server shutting down, closing connection");
+                break Some(CloseReason::GoingAway);
+            }
             // Forward broadcast messages (serialized UI events) to the client
-            Ok(msg_str) = broadcast_rx.recv() => {
-                if let Err(e) = ws_sender.send(Message::Text(msg_str)).await {
-                    // Error likely means client disconnected
-                    warn!(%peer, error = %e, "failed to send message to client, disconnecting");
-                    break; // Exit loop to close connection
+            result = broadcast_rx.recv() => {
+                match result {
+                    Ok(broadcast) => {
+                        if broadcast.event.seq <= replayed_up_to
+                            || !subscribed(subscription.as_deref(), &broadcast.event_type)
+                        {
+                            continue;
+                        }
+                        let message = match event_message(format, broadcast)
+                            .and_then(|m| compressed(&mut deflater, m))
+                        {
+                            Ok(message) => message,
+                            Err(e) => {
+                                error!(%peer, error = %e, "failed to encode event for client");
+                                continue;
+                            }
+                        };
+                        let send = ws_sender.send(message);
+                        let sent = match state.config.send_timeout {
+                            Some(limit) => timeout(limit, send).await,
+                            None => Ok(send.await),
+                        };
+                        match sent {
+                            Ok(Ok(())) => {}
+                            Ok(Err(e)) => {
+                                // Error likely means client disconnected
+                                warn!(%peer, error = %e, "failed to send message to client, disconnecting");
+                                break None; // Exit loop to close connection
+                            }
+                            Err(_) => {
+                                warn!(%peer, "client is not draining events, disconnecting");
+                                break Some(CloseReason::SlowConsumer);
+                            }
+                        }
+                    }
+                    Err(RecvError::Lagged(skipped)) => {
+                        info!(%peer, skipped, "client lagging behind, events skipped");
+                        match state.config.on_lag {
+                            LagBehavior::Skip => {}
+                            LagBehavior::Notify => {
+                                let notice = serde_json::json!({ "warning": "lagged", "skipped": skipped });
+                                if let Err(e) = ws_sender.send(Message::Text(notice.to_string())).await {
+                                    warn!(%peer, error = %e, "failed to send lag notice, disconnecting");
+                                    break None;
+                                }
+                            }
+                            LagBehavior::Disconnect => {
+                                warn!(%peer, skipped, "client lagged, disconnecting");
+                                break Some(CloseReason::SlowConsumer);
+                            }
+                        }
+                    }
+                    Err(RecvError::Closed) => {
+                        info!(%peer, "event source stopped, closing connection");
+                        break Some(CloseReason::GoingAway);
+                    }
                 }
             }
+            _ = ping_timer.tick(), if ping_interval.is_some() => {
+                if unanswered_pings >= max_missed_pongs {
+                    warn!(%peer, unanswered_pings, "client did not answer pings, disconnecting");
+                    break None;
+                }
+                if let Err(e) = ws_sender.send(Message::Ping(Vec::new())).await {
+                    warn!(%peer, error = %e, "failed to send ping, disconnecting");
+                    break None;
+                }
+                unanswered_pings += 1;
+            }
             // Handle messages *from* the client (e.g., ping/pong, close)
             Some(msg_result) = ws_receiver.next() => {
                 match msg_result {
                     Ok(msg) => {
                         match msg {
-                            Message::Text(_) | Message::Binary(_) => {
-                                // Ignore data messages from client for now
-                                debug!(%peer, "received data message (ignoring)");
+                            Message::Text(text) => {
+                                let reply = match serde_json::from_str::<ClientMessage>(&text) {
+                                    Ok(ClientMessage::Query(query)) => {
+                                        debug!(%peer, ?query, "received client query");
+                                        answer_query(query, state.config.control.as_ref()).await
+                                    }
+                                    Ok(ClientMessage::Subscribe(Subscribe { subscribe })) => {
+                                        debug!(%peer, ?subscribe, "client changed subscription");
+                                        let reply = serde_json::json!({ "subscribed": subscribe });
+                                        subscription = (!subscribe.is_empty()).then_some(subscribe);
+                                        reply
+                                    }
+                                    Ok(ClientMessage::SetFormat(SetFormat { format: requested })) => {
+                                        if requested == WireFormat::Msgpack && !cfg!(feature = "msgpack") {
+                                            serde_json::json!({
+                                                "format": format.as_str(),
+                                                "error": "msgpack is not supported by this server",
+                                            })
+                                        } else {
+                                            debug!(%peer, format = requested.as_str(), "client changed format");
+                                            format = requested;
+                                            serde_json::json!({ "format": format.as_str() })
+                                        }
+                                    }
+                                    Ok(ClientMessage::Command(ServerCommand::Stats)) => state.stats(),
+                                    Ok(ClientMessage::Command(ServerCommand::Version)) => serde_json::json!({
+                                        "cmd": "version",
+                                        "crate_version": env!("CARGO_PKG_VERSION"),
+                                        "schema_version": SCHEMA_VERSION,
+                                    }),
+                                    Ok(ClientMessage::Command(ServerCommand::Metrics)) => {
+                                        let mut reply = serde_json::to_value(metrics::metrics_snapshot())
+                                            .unwrap_or_else(|_| serde_json::json!({}));
+                                        reply["cmd"] = "metrics".into();
+                                        reply
+                                    }
+                                    Err(_) => {
+                                        debug!(%peer, "received unknown text message (ignoring)");
+                                        continue;
+                                    }
+                                };
+                                if let Err(e) = ws_sender.send(Message::Text(reply.to_string())).await {
+                                    warn!(%peer, error = %e, "failed to send reply, disconnecting");
+                                    break None;
+                                }
+                            }
+                            Message::Binary(_) => {
+                                // Ignore binary messages from client for now
+                                debug!(%peer, "received binary message (ignoring)");
                             }
                             Message::Ping(ping_data) => {
                                 debug!(%peer, "received ping, sending pong");
                                 if let Err(e) = ws_sender.send(Message::Pong(ping_data)).await {
                                      warn!(%peer, error = %e, "failed to send pong, disconnecting");
-                                     break;
+                                     break None;
                                 }
                             }
                             Message::Close(_) => {
                                 info!(%peer, "received close frame, closing connection");
-                                break; // Exit loop
+                                // tungstenite already answers with the client's own close code
+                                break None; // Exit loop
                             }
                             Message::Pong(_) => {
-                                // Usually we only send pings and expect pongs
-                                debug!(%peer, "received unsolicited pong (ignoring)");
+                                if unanswered_pings == 0 {
+                                    debug!(%peer, "received unsolicited pong (ignoring)");
+                                }
+                                unanswered_pings = 0;
                             }
                            Message::Frame(_) => {
                                 // Low-level frame, ignore in typical usage
@@ -63,43 +839,311 @@ async fn handle_connection(
                     Err(e) => {
                         // Tungstenite error (connection closed, protocol error, etc.)
                         warn!(%peer, error = %e, "websocket error, closing connection");
-                        break; // Exit loop
+                        break None; // Exit loop
                     }
                 }
             }
             else => {
                 // Both streams have potentially ended
-                break;
+                break None;
             }
         }
-    }
+    };
 
-    info!(%peer, "websocket connection closed");
+    info!(%peer, ?close_reason, "websocket connection closed");
+    if let Some(reason) = close_reason {
+        // Tell the client why, so it can decide between retrying and giving up
+        let _ = timeout(
+            CLOSE_SEND_TIMEOUT,
+            ws_sender.send(Message::Close(Some(reason.frame()))),
+        )
+        .await;
+    }
     // Attempt to close the sender cleanly (optional)
-    let _ = ws_sender.close().await;
+    let _ = timeout(CLOSE_SEND_TIMEOUT, ws_sender.close()).await;
     Ok(())
 }
 
-pub async fn run_server(port: u16, mut rx: mpsc::Receiver<UiEvent>) -> Result<()> {
-    let addr = format!("127.0.0.1:{}", port);
+pub async fn run_server(port: u16, rx: mpsc::Receiver<UiEvent>) -> Result<(), UiEventsError> {
+    run_server_with_config(port, rx, ServerConfig::default()).await
+}
+
+pub async fn run_server_with_config(
+    port: u16,
+    rx: mpsc::Receiver<UiEvent>,
+    config: ServerConfig,
+) -> Result<(), UiEventsError> {
+    let host = config.host.unwrap_or(IpAddr::V4(Ipv4Addr::LOCALHOST));
+    let addr = SocketAddr::new(host, port);
     let listener = TcpListener::bind(&addr)
         .await
-        .context(format!("failed to bind websocket server to {}", addr))?;
-    info!("websocket server listening on ws://{}", addr);
+        .map_err(|source| UiEventsError::Bind { addr, source })?;
+    serve(listener, rx, config).await
+}
+
+/// How [`run_server_from_file`] paces recorded events.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ReplayPace {
+    /// Keep the recorded gaps between events, by their timestamps.
+    #[default]
+    Recorded,
+    /// Send every event as soon as the server takes it.
+    AsFastAsPossible,
+}
+
+/// Serves a recording instead of the live listener, so consumers can be developed on any
+/// platform: reads `UiEvent`s from a JSONL file, as written with `--output ndjson` or an
+/// `--output-file` (see [`crate::sink`]), and serves them like [`run_server_with_config`].
+/// Events keep their recorded timestamps but are numbered (`seq`) anew. Malformed lines are
+/// logged and skipped.
+///
+/// The event source ends after the last line, so with the default `on_source_closed` the
+/// server then shuts down; use [`SourceClosedBehavior::Notify`] to keep it up.
+pub async fn run_server_from_file(
+    port: u16,
+    path: impl AsRef<Path>,
+    pace: ReplayPace,
+    config: ServerConfig,
+) -> Result<(), UiEventsError> {
+    let path = path.as_ref();
+    let file = tokio::fs::File::open(path)
+        .await
+        .with_context(|| format!("failed to open recording {}", path.display()))?;
+    let (tx, rx) = mpsc::channel(100);
+    tokio::spawn(replay_file(file, path.to_owned(), pace, tx));
+    run_server_with_config(port, rx, config).await
+}
+
+// Feed the events of a JSONL recording to the server
+async fn replay_file(
+    file: tokio::fs::File,
+    path: PathBuf,
+    pace: ReplayPace,
+    tx: mpsc::Sender<UiEvent>,
+) {
+    let mut lines = BufReader::new(file).lines();
+    // Recorded time (ns) of the first event and when it was replayed
+    let mut origin: Option<(i64, Instant)> = None;
+    let mut line_number = 0;
+    loop {
+        let line = match lines.next_line().await {
+            Ok(Some(line)) => line,
+            Ok(None) => break,
+            Err(e) => {
+                error!(path = %path.display(), error = %e, "failed to read recording");
+                break;
+            }
+        };
+        line_number += 1;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let mut event = match serde_json::from_str::<UiEvent>(&line) {
+            Ok(event) => event,
+            Err(e) => {
+                warn!(line = line_number, error = %e, "skipping malformed line in recording");
+                continue;
+            }
+        };
+        if pace == ReplayPace::Recorded {
+            let recorded = event
+                .timestamp_nanos
+                .unwrap_or_else(|| event.timestamp.timestamp_millis() * 1_000_000);
+            match origin {
+                None => origin = Some((recorded, Instant::now())),
+                Some((first, started)) => {
+                    let offset = Duration::from_nanos(recorded.saturating_sub(first).max(0) as u64);
+                    tokio::time::sleep_until(started + offset).await;
+                }
+            }
+        }
+        event.seq = 0;
+        if tx.send(event).await.is_err() {
+            // Server stopped
+            return;
+        }
+    }
+    info!(path = %path.display(), lines = line_number, "recording replayed");
+}
+
+/// Like [`run_server`], listening on a Unix domain socket at `path` instead of a TCP port, so
+/// the socket file's permissions decide who may connect. Clients speak websocket over the
+/// socket as they would over TCP.
+#[cfg(unix)]
+pub async fn run_server_uds(
+    path: impl Into<PathBuf>,
+    rx: mpsc::Receiver<UiEvent>,
+) -> Result<(), UiEventsError> {
+    run_server_uds_with_config(path, rx, ServerConfig::default()).await
+}
+
+/// Like [`run_server_with_config`], on a Unix domain socket (see [`run_server_uds`]). A socket
+/// file left behind by a server that is gone is replaced, and the file is removed once the
+/// server stops. `host` only applies to `sse_port`.
+#[cfg(unix)]
+pub async fn run_server_uds_with_config(
+    path: impl Into<PathBuf>,
+    rx: mpsc::Receiver<UiEvent>,
+    config: ServerConfig,
+) -> Result<(), UiEventsError> {
+    let path = path.into();
+    remove_stale_socket(&path);
+    let listener =
+        tokio::net::UnixListener::bind(&path).map_err(|source| UiEventsError::BindSocket {
+            path: path.clone(),
+            source,
+        })?;
+    let result = serve(listener, rx, config).await;
+    if let Err(e) = std::fs::remove_file(&path) {
+        warn!(path = %path.display(), error = %e, "failed to remove socket file");
+    }
+    result
+}
+
+// Remove a socket file nobody listens on anymore (e.g. after a crash), which would make
+// binding fail. A live socket is kept, so binding reports it as in use.
+#[cfg(unix)]
+fn remove_stale_socket(path: &Path) {
+    use std::os::unix::fs::FileTypeExt;
+    let is_socket = std::fs::metadata(path).is_ok_and(|m| m.file_type().is_socket());
+    if is_socket && std::os::unix::net::UnixStream::connect(path).is_err() {
+        debug!(path = %path.display(), "removing stale socket file");
+        let _ = std::fs::remove_file(path);
+    }
+}
+
+// Where the server accepts websocket clients: a TCP port or a Unix domain socket
+trait Acceptor {
+    type Stream: AsyncRead + AsyncWrite + Unpin + Send + 'static;
+
+    // Address clients connect to, for the startup log
+    fn describe(&self, scheme: &str) -> String;
+
+    // The next client, with a label for logs
+    fn accept_client(&self)
+    -> impl Future<Output = std::io::Result<(Self::Stream, String)>> + Send;
+}
+
+impl Acceptor for TcpListener {
+    type Stream = TcpStream;
+
+    fn describe(&self, scheme: &str) -> String {
+        match self.local_addr() {
+            Ok(addr) => format!("{}://{}", scheme, addr),
+            Err(_) => scheme.to_owned(),
+        }
+    }
+
+    async fn accept_client(&self) -> std::io::Result<(TcpStream, String)> {
+        let (stream, peer) = self.accept().await?;
+        Ok((stream, peer.to_string()))
+    }
+}
+
+#[cfg(unix)]
+impl Acceptor for tokio::net::UnixListener {
+    type Stream = tokio::net::UnixStream;
+
+    fn describe(&self, scheme: &str) -> String {
+        let path = self
+            .local_addr()
+            .ok()
+            .and_then(|addr| addr.as_pathname().map(|p| p.display().to_string()))
+            .unwrap_or_default();
+        format!("{}+unix://{}", scheme, path)
+    }
+
+    async fn accept_client(&self) -> std::io::Result<(tokio::net::UnixStream, String)> {
+        let (stream, _) = self.accept().await?;
+        // Socket peers have no address; their pid tells them apart
+        let peer = match stream.peer_cred().ok().and_then(|cred| cred.pid()) {
+            Some(pid) => format!("unix:pid={}", pid),
+            None => "unix".to_owned(),
+        };
+        Ok((stream, peer))
+    }
+}
+
+// Serve a websocket client, counted in `clients` meanwhile
+async fn serve_client(
+    peer: String,
+    stream: impl AsyncRead + AsyncWrite + Unpin,
+    broadcast_rx: broadcast::Receiver<Broadcast>,
+    state: Arc<ServerState>,
+) -> Result<()> {
+    state.clients.fetch_add(1, Ordering::Relaxed);
+    metrics::client_connected();
+    let result = handle_connection(peer, stream, broadcast_rx, state.clone()).await;
+    state.clients.fetch_sub(1, Ordering::Relaxed);
+    metrics::client_disconnected();
+    result
+}
+
+// Run the server on a bound listener until shutdown
+async fn serve(
+    listener: impl Acceptor,
+    mut rx: mpsc::Receiver<UiEvent>,
+    mut config: ServerConfig,
+) -> Result<(), UiEventsError> {
+    // Owned by the broadcaster alone, so sinks see their channel close when it finishes
+    let mut reliable_sinks = std::mem::take(&mut config.reliable_sinks);
+    let state = Arc::new(ServerState::new(config));
+
+    let host = state.config.host.unwrap_or(IpAddr::V4(Ipv4Addr::LOCALHOST));
+    #[cfg(feature = "tls")]
+    let tls = state.config.tls.as_ref().map(tls_acceptor).transpose()?;
+    #[cfg(feature = "tls")]
+    let scheme = if tls.is_some() { "wss" } else { "ws" };
+    #[cfg(not(feature = "tls"))]
+    let scheme = "ws";
+
+    info!(
+        "websocket server listening on {}",
+        listener.describe(scheme)
+    );
+    let sse_listener = match state.config.sse_port {
+        Some(sse_port) => {
+            let addr = SocketAddr::new(host, sse_port);
+            let listener = TcpListener::bind(&addr)
+                .await
+                .map_err(|source| UiEventsError::Bind { addr, source })?;
+            info!("sse server listening on http://{}/events", addr);
+            Some(listener)
+        }
+        None => None,
+    };
 
     // Broadcast channel for distributing serialized events to clients
     // Capacity should be chosen based on expected event volume and client processing speed
-    let (broadcast_tx, _) = broadcast::channel::<String>(100); // Sender and a placeholder receiver
+    let (broadcast_tx, _) = broadcast::channel::<Broadcast>(100); // Sender and a placeholder receiver
+
+    // Fires when the listener's sender is dropped
+    let (source_closed_tx, mut source_closed_rx) = oneshot::channel::<()>();
 
     // Task to receive UI events, serialize them, and broadcast
     let broadcaster_tx = broadcast_tx.clone(); // Clone sender for the task
+    let broadcaster_state = state.clone();
     tokio::spawn(async move {
         info!("event broadcaster task started");
-        while let Some(event) = rx.recv().await {
-            match serde_json::to_string(&event) {
+        let mut encoder = EventEncoder::new(broadcaster_state.config.omit_null_fields);
+        while let Some(mut event) = rx.recv().await {
+            match encoder.encode(&mut event) {
                 Ok(json_str) => {
+                    feed_reliable_sinks(&mut reliable_sinks, &json_str).await;
+                    let encoded = broadcaster_state
+                        .config
+                        .serializer
+                        .as_deref()
+                        .and_then(|serializer| serializer_message(serializer, &event));
+                    let broadcast = Broadcast {
+                        event_type: event.event_type.to_string(),
+                        json: json_str,
+                        event: Arc::new(event),
+                        encoded,
+                    };
+                    broadcaster_state.remember(&broadcast);
                     // Send to broadcast channel. If no clients are listening, the error is ignored.
-                    if let Err(e) = broadcaster_tx.send(json_str) {
+                    if let Err(e) = broadcaster_tx.send(broadcast) {
                         // This error typically means no clients are connected.
                         // It can be noisy, so maybe log only once or use debug level.
                         debug!("broadcast send error (no receivers?): {}", e);
@@ -113,30 +1157,628 @@ pub async fn run_server(port: u16, mut rx: mpsc::Receiver<UiEvent>) -> Result<()
         }
         info!("event broadcaster task finished (mpsc channel closed)");
         // rx is dropped here when the loop finishes (sender in main/listener dropped)
+
+        if broadcaster_state.config.on_source_closed == SourceClosedBehavior::Notify {
+            let mut stopped = UiEvent::new(EventType::SourceStopped);
+            if let Ok(json_str) = encoder.encode(&mut stopped) {
+                feed_reliable_sinks(&mut reliable_sinks, &json_str).await;
+                *broadcaster_state.source_stopped.lock().unwrap() = Some(json_str.clone());
+                let _ = broadcaster_tx.send(Broadcast {
+                    event_type: stopped.event_type.to_string(),
+                    json: json_str,
+                    event: Arc::new(stopped),
+                    encoded: None,
+                });
+            }
+        }
+        let _ = source_closed_tx.send(());
     });
 
+    let mut shutdown = state.config.shutdown.clone();
+    let mut connections = JoinSet::new();
+
     // Main loop to accept incoming connections
     loop {
-        match listener.accept().await {
-            Ok((stream, peer)) => {
-                info!(%peer, "accepting new tcp connection");
-                let broadcast_rx = broadcast_tx.subscribe(); // Create a receiver for this specific client
-                tokio::spawn(async move {
-                    if let Err(e) = handle_connection(peer, stream, broadcast_rx).await {
-                        error!(%peer, error = %e, "error handling connection");
-                    }
-                });
+        tokio::select! {
+            accepted = listener.accept_client() => match accepted {
+                Ok((stream, peer)) => {
+                    info!(%peer, "accepting new connection");
+                    let broadcast_rx = broadcast_tx.subscribe(); // Create a receiver for this specific client
+                    let state = state.clone();
+                    #[cfg(feature = "tls")]
+                    let tls = tls.clone();
+                    connections.spawn(async move {
+                        #[cfg(feature = "tls")]
+                        let result = match tls {
+                            Some(acceptor) => match timeout(TLS_HANDSHAKE_TIMEOUT, acceptor.accept(stream)).await {
+                                Ok(Ok(stream)) => serve_client(peer.clone(), stream, broadcast_rx, state).await,
+                                Ok(Err(e)) => Err(anyhow::Error::from(e).context("error during tls handshake")),
+                                Err(_) => Err(anyhow::anyhow!("tls handshake timed out")),
+                            },
+                            None => serve_client(peer.clone(), stream, broadcast_rx, state).await,
+                        };
+                        #[cfg(not(feature = "tls"))]
+                        let result = serve_client(peer.clone(), stream, broadcast_rx, state).await;
+                        if let Err(e) = result {
+                            error!(%peer, error = %e, "error handling connection");
+                        }
+                    });
+                }
+                Err(e) => {
+                    error!(error = %e, "failed to accept incoming connection");
+                    // Consider if this error is recoverable or requires stopping the server
+                    // For now, just log and continue trying to accept
+                }
+            },
+            accepted = sse::accept(sse_listener.as_ref()) => match accepted {
+                Ok((stream, peer)) => {
+                    info!(%peer, "accepting new sse connection");
+                    let broadcast_rx = broadcast_tx.subscribe();
+                    let state = state.clone();
+                    connections.spawn(async move {
+                        state.clients.fetch_add(1, Ordering::Relaxed);
+                        metrics::client_connected();
+                        if let Err(e) = sse::handle_connection(peer, stream, broadcast_rx, state.clone()).await {
+                            error!(%peer, error = %e, "error handling sse connection");
+                        }
+                        state.clients.fetch_sub(1, Ordering::Relaxed);
+                        metrics::client_disconnected();
+                    });
+                }
+                Err(e) => error!(error = %e, "failed to accept incoming sse connection"),
+            },
+            _ = &mut source_closed_rx, if state.config.on_source_closed == SourceClosedBehavior::Shutdown => {
+                info!("event source closed, shutting down websocket server");
+                break;
             }
-            Err(e) => {
-                error!(error = %e, "failed to accept incoming tcp connection");
-                // Consider if this error is recoverable or requires stopping the server
-                // For now, just log and continue trying to accept
+            _ = shutdown_requested(&mut shutdown) => {
+                info!("shutdown requested, closing websocket server");
+                break;
             }
+            // Reap finished connections
+            Some(_) = connections.join_next() => {}
+        }
+    }
+
+    // Connections see the same shutdown signal, or the broadcast channel closing once the
+    // source is gone, and close themselves with 1001 (going away)
+    drop(listener);
+    drop(sse_listener);
+    drop(broadcast_tx);
+    let drain = async { while connections.join_next().await.is_some() {} };
+    if timeout(SHUTDOWN_GRACE_PERIOD, drain).await.is_err() {
+        warn!("connections did not close in time, aborting them");
+        connections.shutdown().await;
+    }
+    info!("websocket server stopped");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt, DuplexStream};
+    use tokio::task::JoinHandle;
+    use tokio_tungstenite::{MaybeTlsStream, WebSocketStream, client_async, connect_async};
+
+    type Client = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+    // Upper bound on anything a test waits for
+    const WAIT: Duration = Duration::from_secs(5);
+
+    // Serve on an ephemeral loopback port
+    async fn start(
+        rx: mpsc::Receiver<UiEvent>,
+        config: ServerConfig,
+    ) -> (SocketAddr, JoinHandle<Result<(), UiEventsError>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        (addr, tokio::spawn(serve(listener, rx, config)))
+    }
+
+    async fn connect(addr: SocketAddr) -> Client {
+        connect_async(format!("ws://{}", addr)).await.unwrap().0
+    }
+
+    // One connection over an in-memory pipe, fed straight from `broadcast_rx`
+    async fn connect_directly(
+        broadcast_rx: broadcast::Receiver<Broadcast>,
+        config: ServerConfig,
+    ) -> (WebSocketStream<DuplexStream>, JoinHandle<Result<()>>) {
+        let (client, server) = tokio::io::duplex(64 * 1024);
+        let state = Arc::new(ServerState::new(config));
+        let connection = tokio::spawn(handle_connection(
+            "test".to_owned(),
+            server,
+            broadcast_rx,
+            state,
+        ));
+        let client = client_async("ws://localhost/", client).await.unwrap().0;
+        (client, connection)
+    }
+
+    fn broadcast(encoder: &mut EventEncoder, event_type: EventType) -> Broadcast {
+        let mut event = UiEvent::new(event_type);
+        let json = encoder.encode(&mut event).unwrap();
+        Broadcast {
+            event_type: event.event_type.to_string(),
+            json,
+            event: Arc::new(event),
+            encoded: None,
+        }
+    }
+
+    async fn next_message<S: AsyncRead + AsyncWrite + Unpin>(
+        client: &mut WebSocketStream<S>,
+    ) -> Message {
+        timeout(WAIT, client.next())
+            .await
+            .expect("timed out waiting for a message")
+            .expect("connection ended")
+            .expect("websocket error")
+    }
+
+    async fn next_event<S: AsyncRead + AsyncWrite + Unpin>(
+        client: &mut WebSocketStream<S>,
+    ) -> UiEvent {
+        match next_message(client).await {
+            Message::Text(text) => serde_json::from_str(&text).expect("not an event"),
+            other => panic!("expected an event, got {:?}", other),
         }
     }
 
-    // Note: The loop above runs indefinitely. In a real application, you'd
-    // want a mechanism for graceful shutdown (e.g., listening for a signal
-    // or another channel message) to break the loop and allow tasks to finish.
-    // Ok(()) // Unreachable in the current form
+    // Send a text message and parse the reply
+    async fn request(client: &mut Client, text: &str) -> serde_json::Value {
+        client.send(Message::Text(text.to_owned())).await.unwrap();
+        match next_message(client).await {
+            Message::Text(reply) => serde_json::from_str(&reply).unwrap(),
+            other => panic!("expected a reply, got {:?}", other),
+        }
+    }
+
+    fn close_code(message: &Message) -> Option<u16> {
+        match message {
+            Message::Close(Some(frame)) => Some(frame.code.into()),
+            _ => None,
+        }
+    }
+
+    #[tokio::test]
+    async fn shutdown_closes_clients_and_returns() {
+        let (_tx, rx) = mpsc::channel(16);
+        let (shutdown_tx, shutdown_rx) = watch::channel(false);
+        let config = ServerConfig {
+            shutdown: Some(shutdown_rx),
+            ..Default::default()
+        };
+        let (addr, server) = start(rx, config).await;
+        let mut client = connect(addr).await;
+
+        shutdown_tx.send(true).unwrap();
+        let message = next_message(&mut client).await;
+        assert_eq!(close_code(&message), Some(CloseReason::GoingAway.code()));
+        let result = timeout(WAIT, server).await.expect("server did not return");
+        assert!(result.unwrap().is_ok());
+    }
+
+    #[tokio::test]
+    async fn source_closed_shuts_down_by_default() {
+        let (tx, rx) = mpsc::channel(16);
+        let (addr, server) = start(rx, ServerConfig::default()).await;
+        let mut client = connect(addr).await;
+
+        drop(tx);
+        let message = next_message(&mut client).await;
+        assert_eq!(close_code(&message), Some(CloseReason::GoingAway.code()));
+        let result = timeout(WAIT, server).await.expect("server did not return");
+        assert!(result.unwrap().is_ok());
+    }
+
+    #[tokio::test]
+    async fn source_closed_notifies_clients() {
+        let (tx, rx) = mpsc::channel(16);
+        let config = ServerConfig {
+            on_source_closed: SourceClosedBehavior::Notify,
+            ..Default::default()
+        };
+        let (addr, server) = start(rx, config).await;
+        let mut client = connect(addr).await;
+
+        drop(tx);
+        let event = next_event(&mut client).await;
+        assert!(matches!(event.event_type, EventType::SourceStopped));
+
+        // Still up: later clients are told right away, then closed
+        let mut late = connect(addr).await;
+        let event = next_event(&mut late).await;
+        assert!(matches!(event.event_type, EventType::SourceStopped));
+        let message = next_message(&mut late).await;
+        assert_eq!(close_code(&message), Some(CloseReason::GoingAway.code()));
+        assert!(!server.is_finished());
+    }
+
+    #[tokio::test]
+    async fn subscription_filters_out_other_types() {
+        let (tx, rx) = mpsc::channel(16);
+        let (addr, _server) = start(rx, ServerConfig::default()).await;
+        let mut client = connect(addr).await;
+
+        let subscribe = r#"{"subscribe":["ElementFocused"]}"#;
+        client.send(Message::Text(subscribe.into())).await.unwrap();
+        let reply = next_message(&mut client).await;
+        assert_eq!(
+            reply,
+            Message::Text(r#"{"subscribed":["ElementFocused"]}"#.into())
+        );
+
+        tx.send(UiEvent::new(EventType::ValueChanged))
+            .await
+            .unwrap();
+        tx.send(UiEvent::new(EventType::ElementFocused))
+            .await
+            .unwrap();
+        let event = next_event(&mut client).await;
+        assert!(matches!(event.event_type, EventType::ElementFocused));
+        assert_eq!(event.seq, 2);
+    }
+
+    #[tokio::test]
+    async fn lagging_clients_are_notified_or_disconnected() {
+        for on_lag in [LagBehavior::Notify, LagBehavior::Disconnect] {
+            let (broadcast_tx, broadcast_rx) = broadcast::channel(4);
+            let config = ServerConfig {
+                on_lag,
+                ..Default::default()
+            };
+            let (mut client, _connection) = connect_directly(broadcast_rx, config).await;
+
+            // Sent without yielding, so the connection cannot keep up
+            let mut encoder = EventEncoder::new(false);
+            for _ in 0..10 {
+                broadcast_tx
+                    .send(broadcast(&mut encoder, EventType::ValueChanged))
+                    .unwrap();
+            }
+            let message = next_message(&mut client).await;
+            match on_lag {
+                LagBehavior::Notify => {
+                    let notice = serde_json::json!({ "warning": "lagged", "skipped": 6 });
+                    assert_eq!(message, Message::Text(notice.to_string()));
+                    // Then the oldest event still buffered
+                    assert_eq!(next_event(&mut client).await.seq, 7);
+                }
+                _ => assert_eq!(close_code(&message), Some(CloseReason::SlowConsumer.code())),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn clients_not_answering_pings_are_dropped() {
+        let (_broadcast_tx, broadcast_rx) = broadcast::channel(4);
+        let config = ServerConfig {
+            ping_interval: Some(Duration::from_millis(20)),
+            max_missed_pongs: Some(1),
+            ..Default::default()
+        };
+        // Pongs are only sent while reading, so this client never answers
+        let (_client, connection) = connect_directly(broadcast_rx, config).await;
+        let result = timeout(WAIT, connection)
+            .await
+            .expect("connection was not dropped");
+        assert!(result.unwrap().is_ok());
+    }
+
+    #[tokio::test]
+    async fn stats_counts_connected_clients() {
+        let (_tx, rx) = mpsc::channel(16);
+        let (addr, _server) = start(rx, ServerConfig::default()).await;
+        let mut first = connect(addr).await;
+        let _second = connect(addr).await;
+
+        let stats = request(&mut first, r#"{"cmd":"stats"}"#).await;
+        assert_eq!(stats["cmd"], "stats");
+        assert_eq!(stats["clients"], 2);
+        assert_eq!(stats["source_running"], true);
+    }
+
+    #[tokio::test]
+    async fn auth_token_is_required() {
+        use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+
+        let (_tx, rx) = mpsc::channel(16);
+        let config = ServerConfig {
+            auth_token: Some("s3cret".to_owned()),
+            ..Default::default()
+        };
+        let (addr, _server) = start(rx, config).await;
+        let authenticated = serde_json::json!({ "authenticated": true });
+
+        let mut handshake = format!("ws://{}", addr).into_client_request().unwrap();
+        handshake
+            .headers_mut()
+            .insert("authorization", "Bearer s3cret".parse().unwrap());
+        let mut with_header = connect_async(handshake).await.unwrap().0;
+        let ack = next_message(&mut with_header).await;
+        assert_eq!(ack, Message::Text(authenticated.to_string()));
+
+        let mut with_message = connect(addr).await;
+        assert_eq!(
+            request(&mut with_message, r#"{"token":"s3cret"}"#).await,
+            authenticated
+        );
+
+        let mut wrong = connect(addr).await;
+        wrong
+            .send(Message::Text(r#"{"token":"guess"}"#.into()))
+            .await
+            .unwrap();
+        let message = next_message(&mut wrong).await;
+        assert_eq!(
+            close_code(&message),
+            Some(CloseReason::PolicyViolation.code())
+        );
+    }
+
+    // Sends only the event type, failing on ValueChanged
+    #[derive(Debug)]
+    struct TypeNameSerializer;
+
+    impl EventSerializer for TypeNameSerializer {
+        fn serialize(&self, event: &UiEvent) -> Result<Vec<u8>> {
+            if matches!(event.event_type, EventType::ValueChanged) {
+                anyhow::bail!("values not supported");
+            }
+            Ok(event.event_type.to_string().into_bytes())
+        }
+
+        fn content_type(&self) -> ContentType {
+            ContentType::Binary
+        }
+    }
+
+    #[tokio::test]
+    async fn custom_serializer_encodes_events() {
+        let (tx, rx) = mpsc::channel(16);
+        let config = ServerConfig {
+            serializer: Some(Arc::new(TypeNameSerializer)),
+            ..Default::default()
+        };
+        let (addr, _server) = start(rx, config).await;
+        let mut client = connect(addr).await;
+
+        tx.send(UiEvent::new(EventType::ElementFocused))
+            .await
+            .unwrap();
+        let message = next_message(&mut client).await;
+        assert_eq!(message, Message::Binary(b"ElementFocused".to_vec()));
+
+        // Falls back to JSON for what the serializer fails on
+        tx.send(UiEvent::new(EventType::ValueChanged))
+            .await
+            .unwrap();
+        let event = next_event(&mut client).await;
+        assert!(matches!(event.event_type, EventType::ValueChanged));
+    }
+
+    #[tokio::test]
+    async fn each_client_sees_increasing_seq() {
+        let (tx, rx) = mpsc::channel(16);
+        let (addr, _server) = start(rx, ServerConfig::default()).await;
+        let mut first = connect(addr).await;
+        let mut second = connect(addr).await;
+
+        let sender = tokio::spawn(async move {
+            for _ in 0..50 {
+                tx.send(UiEvent::new(EventType::ValueChanged))
+                    .await
+                    .unwrap();
+            }
+        });
+        for client in [&mut first, &mut second] {
+            let mut last = 0;
+            for _ in 0..50 {
+                let seq = next_event(client).await.seq;
+                assert!(seq > last, "seq {} after {}", seq, last);
+                last = seq;
+            }
+        }
+        sender.await.unwrap();
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn unix_socket_clients_receive_events() {
+        let path = std::env::temp_dir().join(format!("ui-events-{}.sock", std::process::id()));
+        let (tx, rx) = mpsc::channel(16);
+        let server = tokio::spawn(run_server_uds(path.clone(), rx));
+
+        // Bound by the server task, so retry until it is up
+        let deadline = Instant::now() + WAIT;
+        let stream = loop {
+            match tokio::net::UnixStream::connect(&path).await {
+                Ok(stream) => break stream,
+                Err(e) if Instant::now() > deadline => panic!("socket not up: {}", e),
+                Err(_) => tokio::time::sleep(Duration::from_millis(10)).await,
+            }
+        };
+        let mut client = client_async("ws://localhost/", stream).await.unwrap().0;
+
+        tx.send(UiEvent::new(EventType::ElementFocused))
+            .await
+            .unwrap();
+        let event = next_event(&mut client).await;
+        assert!(matches!(event.event_type, EventType::ElementFocused));
+
+        // The socket file goes away with the server
+        drop(tx);
+        timeout(WAIT, server).await.unwrap().unwrap().unwrap();
+        assert!(!path.exists());
+    }
+
+    #[cfg(feature = "tls")]
+    #[tokio::test]
+    async fn wss_clients_receive_events() {
+        use tokio_rustls::rustls;
+
+        let generated = rcgen::generate_simple_self_signed(vec!["localhost".to_owned()]).unwrap();
+        let dir = std::env::temp_dir().join(format!("ui-events-wss-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let tls = TlsConfig {
+            cert_path: dir.join("cert.pem"),
+            key_path: dir.join("key.pem"),
+        };
+        std::fs::write(&tls.cert_path, generated.cert.pem()).unwrap();
+        std::fs::write(&tls.key_path, generated.key_pair.serialize_pem()).unwrap();
+        let (tx, rx) = mpsc::channel(16);
+        let config = ServerConfig {
+            tls: Some(tls),
+            ..Default::default()
+        };
+        let (addr, _server) = start(rx, config).await;
+
+        // Trust exactly the generated certificate
+        let mut roots = rustls::RootCertStore::empty();
+        roots.add(generated.cert.der().clone()).unwrap();
+        let client_config = rustls::ClientConfig::builder()
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+        let connector = tokio_rustls::TlsConnector::from(Arc::new(client_config));
+        let tcp = TcpStream::connect(addr).await.unwrap();
+        let server_name = "localhost".try_into().unwrap();
+        let stream = connector.connect(server_name, tcp).await.unwrap();
+        let mut client = client_async("wss://localhost/", stream).await.unwrap().0;
+
+        tx.send(UiEvent::new(EventType::ElementFocused))
+            .await
+            .unwrap();
+        let event = next_event(&mut client).await;
+        assert!(matches!(event.event_type, EventType::ElementFocused));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[cfg(feature = "msgpack")]
+    #[tokio::test]
+    async fn msgpack_clients_get_binary_events() {
+        let (tx, rx) = mpsc::channel(16);
+        let (addr, _server) = start(rx, ServerConfig::default()).await;
+        let url = format!("ws://{}/?format=msgpack", addr);
+        let mut client = connect_async(url).await.unwrap().0;
+
+        tx.send(UiEvent::new(EventType::ElementFocused))
+            .await
+            .unwrap();
+        let Message::Binary(frame) = next_message(&mut client).await else {
+            panic!("expected a binary frame");
+        };
+        let event: UiEvent = rmp_serde::from_slice(&frame).unwrap();
+        assert!(matches!(event.event_type, EventType::ElementFocused));
+        assert_eq!(event.seq, 1);
+    }
+
+    // A websocket handshake offering `extensions`, read up to the end of the response
+    async fn raw_handshake(addr: SocketAddr, extensions: &str) -> (TcpStream, String) {
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        let handshake = format!(
+            "GET / HTTP/1.1\r\nHost: {}\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\
+             Sec-WebSocket-Version: 13\r\nSec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\
+             Sec-WebSocket-Extensions: {}\r\n\r\n",
+            addr, extensions
+        );
+        stream.write_all(handshake.as_bytes()).await.unwrap();
+        // Byte by byte, so no frame is read along with it
+        let mut response = Vec::new();
+        while !response.ends_with(b"\r\n\r\n") {
+            response.push(stream.read_u8().await.unwrap());
+        }
+        (
+            stream,
+            String::from_utf8(response).unwrap().to_ascii_lowercase(),
+        )
+    }
+
+    // First header byte (FIN, RSV1, opcode) and payload of the server's next frame
+    async fn raw_frame(stream: &mut TcpStream) -> (u8, Vec<u8>) {
+        let read = async {
+            let first = stream.read_u8().await?;
+            let len = match stream.read_u8().await? {
+                126 => stream.read_u16().await? as usize,
+                127 => stream.read_u64().await? as usize,
+                len => len as usize,
+            };
+            let mut payload = vec![0; len];
+            stream.read_exact(&mut payload).await?;
+            std::io::Result::Ok((first, payload))
+        };
+        timeout(WAIT, read)
+            .await
+            .expect("timed out waiting for a frame")
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn deflate_clients_get_compressed_events() {
+        let (tx, rx) = mpsc::channel(16);
+        let (addr, _server) = start(rx, ServerConfig::default()).await;
+        let (mut stream, response) =
+            raw_handshake(addr, "permessage-deflate; client_max_window_bits").await;
+        assert!(response.starts_with("http/1.1 101"), "{}", response);
+        assert!(response.contains("\r\nsec-websocket-extensions: permessage-deflate\r\n"));
+
+        // One inflater for the connection, as the server keeps its deflate context
+        let mut inflater = flate2::Decompress::new(false);
+        for event_type in [EventType::ElementFocused, EventType::ValueChanged] {
+            tx.send(UiEvent::new(event_type.clone())).await.unwrap();
+            let (first, mut payload) = raw_frame(&mut stream).await;
+            assert_eq!(first, 0xc1, "expected a compressed text frame");
+            payload.extend_from_slice(&[0x00, 0x00, 0xff, 0xff]);
+            let mut json = Vec::with_capacity(64 * 1024);
+            inflater
+                .decompress_vec(&payload, &mut json, flate2::FlushDecompress::Sync)
+                .unwrap();
+            let event: UiEvent = serde_json::from_slice(&json).unwrap();
+            assert_eq!(event.event_type.to_string(), event_type.to_string());
+        }
+
+        // Clients may compress their own messages too
+        let mut deflater = flate2::Compress::new(flate2::Compression::default(), false);
+        let mut message = Vec::with_capacity(256);
+        deflater
+            .compress_vec(
+                br#"{"subscribe":["ValueChanged"]}"#,
+                &mut message,
+                flate2::FlushCompress::Sync,
+            )
+            .unwrap();
+        message.truncate(message.len() - 4);
+        let mask = [0x12, 0x34, 0x56, 0x78];
+        let mut frame = vec![0xc1, 0x80 | message.len() as u8];
+        frame.extend_from_slice(&mask);
+        frame.extend(message.iter().enumerate().map(|(i, b)| b ^ mask[i % 4]));
+        stream.write_all(&frame).await.unwrap();
+        let (first, reply) = raw_frame(&mut stream).await;
+        assert_eq!(first, 0x81, "expected an uncompressed text frame");
+        let reply: serde_json::Value = serde_json::from_slice(&reply).unwrap();
+        assert_eq!(reply["subscribed"][0], "ValueChanged");
+    }
+
+    #[tokio::test]
+    async fn compression_can_be_turned_off() {
+        let (tx, rx) = mpsc::channel(16);
+        let config = ServerConfig {
+            compression: false,
+            ..Default::default()
+        };
+        let (addr, _server) = start(rx, config).await;
+        let (mut stream, response) = raw_handshake(addr, "permessage-deflate").await;
+        assert!(response.starts_with("http/1.1 101"), "{}", response);
+        assert!(!response.contains("sec-websocket-extensions"));
+
+        tx.send(UiEvent::new(EventType::ElementFocused))
+            .await
+            .unwrap();
+        let (first, payload) = raw_frame(&mut stream).await;
+        assert_eq!(first, 0x81, "expected an uncompressed text frame");
+        let event: UiEvent = serde_json::from_slice(&payload).unwrap();
+        assert!(matches!(event.event_type, EventType::ElementFocused));
+    }
 }
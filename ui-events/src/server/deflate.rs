@@ -0,0 +1,413 @@
+// permessage-deflate (RFC 7692), which tungstenite 0.21 does not implement.
+//
+// The extension is agreed on in the handshake callback (`negotiate`). Event frames are then
+// compressed by `Deflater` into ready-made frames with RSV1 set. tungstenite fails the
+// connection on any frame with RSV1 set, so compressed messages from the client are inflated
+// by `InflateFrames`, which sits between the socket and tungstenite and hands it the same
+// messages uncompressed.
+
+use anyhow::{Context, Result};
+use flate2::{Compress, Compression, Decompress, FlushCompress, FlushDecompress};
+use std::io;
+use std::pin::Pin;
+use std::task::{self, Poll, ready};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio_tungstenite::tungstenite::protocol::Message;
+use tokio_tungstenite::tungstenite::protocol::frame::Frame;
+use tokio_tungstenite::tungstenite::protocol::frame::coding::{Data, OpCode};
+
+// End of a sync flush, left out of every compressed message and added back to inflate it
+const SYNC_FLUSH_TAIL: [u8; 4] = [0x00, 0x00, 0xff, 0xff];
+
+// Largest client message, compressed or inflated (tungstenite's default max_message_size)
+const MAX_MESSAGE_SIZE: usize = 64 << 20;
+
+// Frame header bits
+const FIN: u8 = 0x80;
+const RSV1: u8 = 0x40;
+const MASKED: u8 = 0x80;
+
+/// Extension parameters agreed on with a client.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(super) struct Agreement {
+    // The client wants every message compressed on its own
+    server_no_context_takeover: bool,
+}
+
+impl Agreement {
+    // Value of the Sec-WebSocket-Extensions response header
+    pub(super) fn header(&self) -> &'static str {
+        if self.server_no_context_takeover {
+            "permessage-deflate; server_no_context_takeover"
+        } else {
+            "permessage-deflate"
+        }
+    }
+}
+
+// The first permessage-deflate offer in the client's Sec-WebSocket-Extensions headers that can
+// be accepted. Offers limiting the server's window below 15 bits are declined: the compressor
+// always uses a 32K window. Window limits for the client need nothing, as the inflater takes
+// any window size, so they are not echoed.
+pub(super) fn negotiate<'a>(headers: impl IntoIterator<Item = &'a str>) -> Option<Agreement> {
+    headers
+        .into_iter()
+        .flat_map(|header| header.split(','))
+        .find_map(|offer| {
+            let mut params = offer.split(';').map(str::trim);
+            if params.next() != Some("permessage-deflate") {
+                return None;
+            }
+            let mut agreement = Agreement {
+                server_no_context_takeover: false,
+            };
+            let mut seen = Vec::new();
+            for param in params {
+                let (name, value) = match param.split_once('=') {
+                    Some((name, value)) => (name.trim(), Some(value.trim().trim_matches('"'))),
+                    None => (param, None),
+                };
+                // A repeated parameter makes the offer invalid
+                if seen.contains(&name) {
+                    return None;
+                }
+                seen.push(name);
+                match (name, value) {
+                    ("server_no_context_takeover", None) => {
+                        agreement.server_no_context_takeover = true
+                    }
+                    ("client_no_context_takeover", None)
+                    | ("server_max_window_bits", Some("15"))
+                    | ("client_max_window_bits", None) => {}
+                    ("client_max_window_bits", Some(bits))
+                        if matches!(bits.parse::<u8>(), Ok(8..=15)) => {}
+                    _ => return None,
+                }
+            }
+            Some(agreement)
+        })
+}
+
+/// Compresses the messages sent to one client. The deflate context carries over from one
+/// message to the next, unless the client asked for `server_no_context_takeover`.
+pub(super) struct Deflater {
+    compress: Compress,
+    reset_after_message: bool,
+}
+
+impl Deflater {
+    pub(super) fn new(agreement: Agreement) -> Self {
+        Self {
+            compress: Compress::new(Compression::default(), false),
+            reset_after_message: agreement.server_no_context_takeover,
+        }
+    }
+
+    // A text or binary message as one compressed frame; other messages are left as they are
+    pub(super) fn compress(&mut self, message: Message) -> Result<Message> {
+        let opcode = match message {
+            Message::Text(_) => Data::Text,
+            Message::Binary(_) => Data::Binary,
+            other => return Ok(other),
+        };
+        let input = message.into_data();
+        let mut output = Vec::with_capacity(input.len() / 2 + 64);
+        let mut consumed = 0;
+        loop {
+            let before = self.compress.total_in();
+            self.compress
+                .compress_vec(&input[consumed..], &mut output, FlushCompress::Sync)
+                .context("failed to compress message")?;
+            consumed += (self.compress.total_in() - before) as usize;
+            // Room left in the output means the flush is complete
+            if consumed == input.len() && output.len() < output.capacity() {
+                break;
+            }
+            output.reserve(output.capacity());
+        }
+        if output.ends_with(&SYNC_FLUSH_TAIL) {
+            output.truncate(output.len() - SYNC_FLUSH_TAIL.len());
+        }
+        if self.reset_after_message {
+            self.compress.reset();
+        }
+        let mut frame = Frame::message(output, OpCode::Data(opcode), true);
+        frame.header_mut().rsv1 = true;
+        Ok(Message::Frame(frame))
+    }
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// A client connection whose compressed messages are read back uncompressed, as one frame
+/// each. Reads pass through untouched until [`InflateFrames::enable`], so the handshake is
+/// not affected; writes always pass through.
+pub(super) struct InflateFrames<S> {
+    inner: S,
+    // Set once the extension is agreed on
+    inflater: Option<Decompress>,
+    // Bytes read from `inner`, up to an incomplete frame
+    input: Vec<u8>,
+    // Frames for tungstenite, handed out from `output_pos` on
+    output: Vec<u8>,
+    output_pos: usize,
+    // Opcode and payload of the compressed message being received, until its last frame
+    message: Option<(u8, Vec<u8>)>,
+    eof: bool,
+}
+
+impl<S> InflateFrames<S> {
+    pub(super) fn new(inner: S) -> Self {
+        Self {
+            inner,
+            inflater: None,
+            input: Vec::new(),
+            output: Vec::new(),
+            output_pos: 0,
+            message: None,
+            eof: false,
+        }
+    }
+
+    // Start inflating client messages; call right after the handshake
+    pub(super) fn enable(&mut self) {
+        self.inflater = Some(Decompress::new(false));
+    }
+
+    // Move the complete frames of `input` to `output`
+    fn process_input(&mut self) -> io::Result<()> {
+        let input = std::mem::take(&mut self.input);
+        let mut start = 0;
+        while let Some((header_len, payload_len)) = frame_len(&input[start..])? {
+            let end = start + header_len + payload_len;
+            self.process_frame(&input[start..end], header_len)?;
+            start = end;
+        }
+        self.input = input;
+        self.input.drain(..start);
+        Ok(())
+    }
+
+    fn process_frame(&mut self, frame: &[u8], header_len: usize) -> io::Result<()> {
+        let first = frame[0];
+        let opcode = first & 0x0f;
+        let compressed = first & RSV1 != 0;
+        let control = opcode & 0x08 != 0;
+        if control || (!compressed && self.message.is_none()) {
+            self.output.extend_from_slice(frame);
+            return Ok(());
+        }
+        if frame[1] & MASKED == 0 {
+            return Err(invalid_data("unmasked frame from client"));
+        }
+        let mask = &frame[header_len - 4..header_len];
+        let payload = frame[header_len..]
+            .iter()
+            .enumerate()
+            .map(|(i, byte)| byte ^ mask[i % 4]);
+        if let Some((_, data)) = &mut self.message {
+            // Only continuation frames until the message is complete
+            if compressed || opcode != 0 {
+                return Err(invalid_data("invalid compressed message"));
+            }
+            data.extend(payload);
+        } else if matches!(opcode, 1 | 2) {
+            self.message = Some((opcode, payload.collect()));
+        } else {
+            return Err(invalid_data("invalid compressed message"));
+        }
+        if self.message.as_ref().unwrap().1.len() > MAX_MESSAGE_SIZE {
+            return Err(invalid_data("compressed message too large"));
+        }
+        if first & FIN == 0 {
+            return Ok(());
+        }
+
+        let (opcode, mut data) = self.message.take().unwrap();
+        data.extend_from_slice(&SYNC_FLUSH_TAIL);
+        let inflated = inflate(self.inflater.as_mut().unwrap(), &data)?;
+        // One final frame; client frames must be masked, a zero key leaves the payload as is
+        self.output.push(FIN | opcode);
+        match inflated.len() {
+            len @ ..=125 => self.output.push(MASKED | len as u8),
+            len @ ..=0xffff => {
+                self.output.push(MASKED | 126);
+                self.output.extend_from_slice(&(len as u16).to_be_bytes());
+            }
+            len => {
+                self.output.push(MASKED | 127);
+                self.output.extend_from_slice(&(len as u64).to_be_bytes());
+            }
+        }
+        self.output.extend_from_slice(&[0; 4]);
+        self.output.extend_from_slice(&inflated);
+        Ok(())
+    }
+}
+
+// Header and payload length of the frame starting `bytes`, once all of it has arrived
+fn frame_len(bytes: &[u8]) -> io::Result<Option<(usize, usize)>> {
+    let [_, second, ..] = *bytes else {
+        return Ok(None);
+    };
+    let (header_len, payload_len) = match second & 0x7f {
+        126 => match bytes.get(2..4) {
+            Some(len) => (4, u16::from_be_bytes([len[0], len[1]]) as u64),
+            None => return Ok(None),
+        },
+        127 => match bytes.get(2..10) {
+            Some(len) => (10, u64::from_be_bytes(len.try_into().unwrap())),
+            None => return Ok(None),
+        },
+        len => (2, len as u64),
+    };
+    if payload_len > MAX_MESSAGE_SIZE as u64 {
+        return Err(invalid_data("frame too large"));
+    }
+    let header_len = header_len + if second & MASKED != 0 { 4 } else { 0 };
+    let payload_len = payload_len as usize;
+    Ok((bytes.len() >= header_len + payload_len).then_some((header_len, payload_len)))
+}
+
+fn inflate(inflater: &mut Decompress, input: &[u8]) -> io::Result<Vec<u8>> {
+    let mut output = Vec::with_capacity(input.len() * 4);
+    let mut consumed = 0;
+    loop {
+        let (in_before, out_before) = (inflater.total_in(), output.len());
+        inflater
+            .decompress_vec(&input[consumed..], &mut output, FlushDecompress::Sync)
+            .map_err(|_| invalid_data("invalid compressed message"))?;
+        consumed += (inflater.total_in() - in_before) as usize;
+        let room_left = output.len() < output.capacity();
+        if consumed == input.len() && room_left {
+            return Ok(output);
+        }
+        if room_left && inflater.total_in() == in_before && output.len() == out_before {
+            return Err(invalid_data("invalid compressed message"));
+        }
+        if output.len() > MAX_MESSAGE_SIZE {
+            return Err(invalid_data("compressed message too large"));
+        }
+        output.reserve(output.capacity());
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for InflateFrames<S> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut task::Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        loop {
+            if this.output_pos < this.output.len() {
+                let n = buf.remaining().min(this.output.len() - this.output_pos);
+                buf.put_slice(&this.output[this.output_pos..this.output_pos + n]);
+                this.output_pos += n;
+                return Poll::Ready(Ok(()));
+            }
+            if this.inflater.is_none() {
+                return Pin::new(&mut this.inner).poll_read(cx, buf);
+            }
+            this.output.clear();
+            this.output_pos = 0;
+            this.process_input()?;
+            if !this.output.is_empty() {
+                continue;
+            }
+            if this.eof {
+                // Any incomplete frame left is dropped; tungstenite sees the connection end
+                return Poll::Ready(Ok(()));
+            }
+            let mut chunk = [0; 8192];
+            let mut chunk = ReadBuf::new(&mut chunk);
+            ready!(Pin::new(&mut this.inner).poll_read(cx, &mut chunk))?;
+            this.eof = chunk.filled().is_empty();
+            this.input.extend_from_slice(chunk.filled());
+        }
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for InflateFrames<S> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut task::Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.get_mut().inner).poll_write(cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut task::Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut task::Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().inner).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn negotiation_accepts_the_first_usable_offer() {
+        let accept = |header: &str| negotiate([header]).map(|agreement| agreement.header());
+        assert_eq!(
+            accept("permessage-deflate; client_max_window_bits"),
+            Some("permessage-deflate")
+        );
+        assert_eq!(
+            accept("permessage-deflate; server_no_context_takeover"),
+            Some("permessage-deflate; server_no_context_takeover")
+        );
+        // A smaller server window is declined, the next offer taken
+        assert_eq!(
+            accept("permessage-deflate; server_max_window_bits=10, permessage-deflate"),
+            Some("permessage-deflate")
+        );
+        assert_eq!(accept("permessage-deflate; unknown_param"), None);
+        assert_eq!(
+            accept("permessage-deflate; client_max_window_bits; client_max_window_bits"),
+            None
+        );
+        assert_eq!(accept("x-webkit-deflate-frame"), None);
+    }
+
+    // A client frame: masked, compressed when `rsv1`
+    fn client_frame(first: u8, payload: &[u8]) -> Vec<u8> {
+        let mask = [0x12, 0x34, 0x56, 0x78];
+        let mut frame = vec![first, MASKED | payload.len() as u8];
+        frame.extend_from_slice(&mask);
+        frame.extend(payload.iter().enumerate().map(|(i, b)| b ^ mask[i % 4]));
+        frame
+    }
+
+    #[test]
+    fn fragmented_compressed_messages_are_inflated_into_one_frame() {
+        let mut compress = Compress::new(Compression::default(), false);
+        let mut compressed = Vec::with_capacity(256);
+        compress
+            .compress_vec(b"hello hello hello", &mut compressed, FlushCompress::Sync)
+            .unwrap();
+        compressed.truncate(compressed.len() - SYNC_FLUSH_TAIL.len());
+        let (head, tail) = compressed.split_at(compressed.len() / 2);
+
+        let mut frames = InflateFrames::new(());
+        frames.enable();
+        frames.input.extend(client_frame(RSV1 | 0x1, head));
+        // A ping between the fragments goes through as it is
+        let ping = client_frame(FIN | 0x9, b"");
+        frames.input.extend(&ping);
+        frames.input.extend(client_frame(FIN, tail));
+        frames.process_input().unwrap();
+
+        let mut expected = ping;
+        expected.extend_from_slice(&[FIN | 0x1, MASKED | 17, 0, 0, 0, 0]);
+        expected.extend_from_slice(b"hello hello hello");
+        assert_eq!(frames.output, expected);
+        assert!(frames.input.is_empty());
+    }
+}
@@ -0,0 +1,305 @@
+// Server-Sent Events transport (`ServerConfig::sse_port`): the websocket server's event
+// stream as `text/event-stream` at `/events`. Minimal HTTP/1.1: one GET per connection, the
+// response streams until the client goes away or the server stops.
+
+use super::{
+    Broadcast, CLOSE_SEND_TIMEOUT, LagBehavior, ServerState, shutdown_requested, tokens_match,
+};
+use anyhow::{Context, Result};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{
+    AsyncBufRead, AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader,
+};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::broadcast;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::time::{Instant, interval_at, timeout};
+use tracing::{debug, info, warn};
+
+// Path the stream is served at
+const EVENTS_PATH: &str = "/events";
+
+// Upper bound on the request line and headers, and on the time to send them
+const MAX_REQUEST_HEAD: u64 = 8 * 1024;
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+// Sent when no event went out for a ping interval, so proxies keep the response open
+const KEEPALIVE: &str = ": keepalive\n\n";
+
+// Accepts on the SSE listener; never resolves without one
+pub(super) async fn accept(
+    listener: Option<&TcpListener>,
+) -> std::io::Result<(TcpStream, SocketAddr)> {
+    match listener {
+        Some(listener) => listener.accept().await,
+        None => std::future::pending().await,
+    }
+}
+
+// The parts of the request the server looks at
+#[derive(Debug, Default)]
+struct SseRequest {
+    method: String,
+    path: String,
+    last_event_id: Option<u64>,
+    token: Option<String>,
+}
+
+async fn read_request(reader: &mut (impl AsyncBufRead + Unpin)) -> Result<SseRequest> {
+    let mut line = String::new();
+    reader.read_line(&mut line).await?;
+    let mut parts = line.split_whitespace();
+    let mut request = SseRequest {
+        method: parts.next().unwrap_or_default().to_owned(),
+        // Query strings are ignored
+        path: parts
+            .next()
+            .and_then(|target| target.split('?').next())
+            .unwrap_or_default()
+            .to_owned(),
+        ..Default::default()
+    };
+    loop {
+        line.clear();
+        if reader.read_line(&mut line).await? == 0 {
+            anyhow::bail!("connection closed before the end of the request head");
+        }
+        let header = line.trim_end();
+        if header.is_empty() {
+            return Ok(request);
+        }
+        let Some((name, value)) = header.split_once(':') else {
+            continue;
+        };
+        let value = value.trim();
+        if name.eq_ignore_ascii_case("last-event-id") {
+            request.last_event_id = value.parse().ok();
+        } else if name.eq_ignore_ascii_case("authorization") {
+            request.token = value.strip_prefix("Bearer ").map(str::to_owned);
+        }
+    }
+}
+
+// A complete response without body, for requests that do not get the stream
+async fn reject(stream: &mut (impl AsyncWrite + Unpin), status: &str) -> Result<()> {
+    let response = format!("HTTP/1.1 {status}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n");
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await?;
+    Ok(())
+}
+
+// One SSE record. The JSON is compact (no newlines), so it fits a single `data:` line.
+fn event_record(broadcast: &Broadcast) -> String {
+    format!("id: {}\ndata: {}\n\n", broadcast.event.seq, broadcast.json)
+}
+
+pub(super) async fn handle_connection(
+    peer: SocketAddr,
+    stream: impl AsyncRead + AsyncWrite + Unpin,
+    mut broadcast_rx: broadcast::Receiver<Broadcast>,
+    state: Arc<ServerState>,
+) -> Result<()> {
+    let mut head = BufReader::new(stream).take(MAX_REQUEST_HEAD);
+    let request = timeout(REQUEST_TIMEOUT, read_request(&mut head))
+        .await
+        .context("timed out reading sse request")?
+        .context("failed to read sse request")?;
+    let mut stream = head.into_inner().into_inner();
+
+    if request.method != "GET" {
+        return reject(&mut stream, "405 Method Not Allowed").await;
+    }
+    if request.path != EVENTS_PATH {
+        return reject(&mut stream, "404 Not Found").await;
+    }
+    if let Some(expected) = state.config.auth_token.as_deref() {
+        if !request
+            .token
+            .is_some_and(|token| tokens_match(&token, expected))
+        {
+            warn!(%peer, "sse client failed to authenticate");
+            return reject(&mut stream, "401 Unauthorized").await;
+        }
+    }
+
+    let headers = "HTTP/1.1 200 OK\r\n\
+                   Content-Type: text/event-stream\r\n\
+                   Cache-Control: no-cache\r\n\
+                   Connection: keep-alive\r\n\r\n";
+    stream.write_all(headers.as_bytes()).await?;
+    info!(%peer, last_event_id = ?request.last_event_id, "new sse connection established");
+
+    // Same catch-up as websocket clients, skipping what a resuming client already has. A
+    // `Last-Event-ID` beyond anything sent is from before a server restart (`seq` starts over),
+    // so that client starts from scratch.
+    let catch_up = state.catch_up();
+    let latest = catch_up.iter().map(|b| b.event.seq).max().unwrap_or(0);
+    let mut replayed_up_to = request
+        .last_event_id
+        .filter(|&id| id <= latest)
+        .unwrap_or(0);
+    for broadcast in catch_up {
+        if broadcast.event.seq <= replayed_up_to {
+            continue;
+        }
+        replayed_up_to = broadcast.event.seq;
+        stream
+            .write_all(event_record(&broadcast).as_bytes())
+            .await?;
+    }
+
+    let source_stopped = state.source_stopped.lock().unwrap().clone();
+    if let Some(stopped_event) = source_stopped {
+        let record = format!("data: {}\n\n", stopped_event);
+        let _ = stream.write_all(record.as_bytes()).await;
+        let _ = stream.shutdown().await;
+        return Ok(());
+    }
+
+    let ping_interval = state.ping_interval();
+    let ping_period = ping_interval.unwrap_or(Duration::from_secs(3600));
+    let mut keepalive_timer = interval_at(Instant::now() + ping_period, ping_period);
+    let mut shutdown = state.config.shutdown.clone();
+
+    loop {
+        let record = tokio::select! {
+            _ = shutdown_requested(&mut shutdown) => {
+                info!(%peer, "server shutting down, closing sse connection");
+                break;
+            }
+            result = broadcast_rx.recv() => match result {
+                Ok(broadcast) if broadcast.event.seq <= replayed_up_to => continue,
+                Ok(broadcast) => event_record(&broadcast),
+                Err(RecvError::Lagged(skipped)) => {
+                    info!(%peer, skipped, "sse client lagging behind, events skipped");
+                    match state.config.on_lag {
+                        LagBehavior::Skip => continue,
+                        LagBehavior::Notify => {
+                            let notice = serde_json::json!({ "warning": "lagged", "skipped": skipped });
+                            format!("event: lagged\ndata: {}\n\n", notice)
+                        }
+                        LagBehavior::Disconnect => {
+                            warn!(%peer, skipped, "sse client lagged, disconnecting");
+                            break;
+                        }
+                    }
+                }
+                Err(RecvError::Closed) => {
+                    info!(%peer, "broadcast channel closed, closing sse connection");
+                    break;
+                }
+            },
+            _ = keepalive_timer.tick(), if ping_interval.is_some() => {
+                KEEPALIVE.to_owned()
+            }
+        };
+
+        let write = stream.write_all(record.as_bytes());
+        let written = match state.config.send_timeout {
+            Some(limit) => timeout(limit, write).await,
+            None => Ok(write.await),
+        };
+        match written {
+            Ok(Ok(())) => keepalive_timer.reset(),
+            Ok(Err(e)) => {
+                debug!(%peer, error = %e, "sse client went away");
+                return Ok(());
+            }
+            Err(_) => {
+                warn!(%peer, "sse client is not draining events, disconnecting");
+                return Ok(());
+            }
+        }
+    }
+
+    let _ = timeout(CLOSE_SEND_TIMEOUT, stream.shutdown()).await;
+    info!(%peer, "sse connection closed");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{ServerConfig, serve};
+    use super::*;
+    use crate::event::{EventType, UiEvent};
+    use tokio::sync::mpsc;
+
+    const WAIT: Duration = Duration::from_secs(5);
+
+    // The SSE listener is bound by the server task, so retry until it is up
+    async fn open(port: u16, last_event_id: Option<u64>) -> BufReader<TcpStream> {
+        let deadline = Instant::now() + WAIT;
+        let mut stream = loop {
+            match TcpStream::connect(("127.0.0.1", port)).await {
+                Ok(stream) => break stream,
+                Err(e) if Instant::now() > deadline => panic!("sse server not up: {}", e),
+                Err(_) => tokio::time::sleep(Duration::from_millis(10)).await,
+            }
+        };
+        let mut request = "GET /events HTTP/1.1\r\nHost: localhost\r\n".to_owned();
+        if let Some(id) = last_event_id {
+            request.push_str(&format!("Last-Event-ID: {}\r\n", id));
+        }
+        request.push_str("\r\n");
+        stream.write_all(request.as_bytes()).await.unwrap();
+        BufReader::new(stream)
+    }
+
+    // The id of the next event record
+    async fn next_id(reader: &mut BufReader<TcpStream>) -> u64 {
+        let mut line = String::new();
+        loop {
+            line.clear();
+            let read = timeout(WAIT, reader.read_line(&mut line))
+                .await
+                .expect("timed out waiting for an event")
+                .unwrap();
+            assert!(read > 0, "sse stream ended");
+            if let Some(id) = line.strip_prefix("id: ") {
+                return id.trim().parse().unwrap();
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn sse_streams_events_and_resumes_after_last_event_id() {
+        let sse_port = TcpListener::bind("127.0.0.1:0")
+            .await
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let (tx, rx) = mpsc::channel(16);
+        let config = ServerConfig {
+            sse_port: Some(sse_port),
+            ..Default::default()
+        };
+        tokio::spawn(serve(listener, rx, config));
+
+        let mut first = open(sse_port, None).await;
+        // The first client is streaming once its response headers arrive
+        let mut status = String::new();
+        first.read_line(&mut status).await.unwrap();
+        assert!(status.starts_with("HTTP/1.1 200"), "{}", status);
+        for _ in 0..3 {
+            tx.send(UiEvent::new(EventType::ValueChanged))
+                .await
+                .unwrap();
+        }
+        for expected in 1..=3 {
+            assert_eq!(next_id(&mut first).await, expected);
+        }
+
+        // Replayed from the backlog after id 2, then live
+        let mut resumed = open(sse_port, Some(2)).await;
+        assert_eq!(next_id(&mut resumed).await, 3);
+        tx.send(UiEvent::new(EventType::ValueChanged))
+            .await
+            .unwrap();
+        assert_eq!(next_id(&mut resumed).await, 4);
+        assert_eq!(next_id(&mut first).await, 4);
+    }
+}
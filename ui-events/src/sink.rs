@@ -0,0 +1,190 @@
+// Consumers of the listener's event stream besides websocket clients: line-delimited JSON
+// (NDJSON) on stdout or in a file, and fan-out of the stream to several consumers
+
+use crate::event::UiEvent;
+use std::io::{self, Write};
+use tokio::sync::mpsc;
+use tracing::{error, warn};
+
+// Objects of a serialized UiEvent whose null fields are dropped with `omit_null_fields`
+const SPARSE_OBJECTS: &[&str] = &["application", "window", "element"];
+
+/// Numbers events in arrival order (`UiEvent::seq`, starting at 1) and serializes them.
+/// Every consumer draining the listener channel goes through one, so the wire format is the
+/// same whether events end up on a websocket or in a file.
+#[derive(Debug, Default)]
+pub(crate) struct EventEncoder {
+    seq: u64,
+    omit_null_fields: bool,
+}
+
+impl EventEncoder {
+    pub(crate) fn new(omit_null_fields: bool) -> Self {
+        Self {
+            seq: 0,
+            omit_null_fields,
+        }
+    }
+
+    /// Assigns the next sequence number to `event` and returns its JSON. Leaving out null
+    /// fields makes a typical focus event about a third smaller (777 to 498 bytes).
+    pub(crate) fn encode(&mut self, event: &mut UiEvent) -> serde_json::Result<String> {
+        self.seq += 1;
+        event.seq = self.seq;
+        if !self.omit_null_fields {
+            return serde_json::to_string(event);
+        }
+        let mut value = serde_json::to_value(&*event)?;
+        if let Some(object) = value.as_object_mut() {
+            for key in SPARSE_OBJECTS {
+                if let Some(nested) = object.get_mut(*key).and_then(|v| v.as_object_mut()) {
+                    nested.retain(|_, v| !v.is_null());
+                }
+            }
+            object.retain(|_, v| !v.is_null());
+        }
+        serde_json::to_string(&value)
+    }
+}
+
+// Write one line, flushing once the producer is caught up so nothing sits in the buffer
+// while capture is idle
+fn write_line(writer: &mut impl Write, line: &str, caught_up: bool) -> io::Result<()> {
+    writer.write_all(line.as_bytes())?;
+    writer.write_all(b"\n")?;
+    if caught_up {
+        writer.flush()?;
+    }
+    Ok(())
+}
+
+/// Writes the listener's events as NDJSON, one event per line, until `rx` closes. Blocks, so
+/// run it on its own thread.
+///
+/// Output is buffered but flushed whenever no more events are queued, so at most the events
+/// in flight are lost when the process is killed.
+pub fn run_line_sink(
+    mut rx: mpsc::Receiver<UiEvent>,
+    writer: impl Write,
+    omit_null_fields: bool,
+) -> io::Result<()> {
+    let mut writer = io::BufWriter::new(writer);
+    let mut encoder = EventEncoder::new(omit_null_fields);
+    while let Some(mut event) = rx.blocking_recv() {
+        match encoder.encode(&mut event) {
+            Ok(line) => write_line(&mut writer, &line, rx.is_empty())?,
+            Err(e) => error!(error = %e, "failed to serialize uievent to json"),
+        }
+    }
+    writer.flush()
+}
+
+/// Splits the listener's events into `outputs` receivers, each getting every event in order,
+/// e.g. to feed several `run_server` instances on different ports from one listener. Each
+/// consumer numbers events (`seq`) on its own. Spawns a task, so call it inside a Tokio
+/// runtime.
+///
+/// Outputs are fed one after the other, waiting for room in each: a consumer that stops
+/// draining its receiver stalls the others (servers drain theirs promptly). An output whose
+/// receiver is dropped is left out from then on. The outputs close when `rx` does.
+pub fn fanout(mut rx: mpsc::Receiver<UiEvent>, outputs: usize) -> Vec<mpsc::Receiver<UiEvent>> {
+    let (mut senders, receivers): (Vec<_>, Vec<_>) = (0..outputs)
+        .map(|_| mpsc::channel(rx.max_capacity()))
+        .unzip();
+    tokio::spawn(async move {
+        while let Some(event) = rx.recv().await {
+            let mut open = Vec::with_capacity(senders.len());
+            for sender in senders.drain(..) {
+                match sender.send(event.clone()).await {
+                    Ok(()) => open.push(sender),
+                    Err(_) => warn!("fanout output closed, removing it"),
+                }
+            }
+            senders = open;
+        }
+    });
+    receivers
+}
+
+/// Like [`run_line_sink`], for events already serialized by the server, e.g. as one of its
+/// `ServerConfig::reliable_sinks`.
+pub fn write_lines(mut rx: mpsc::Receiver<String>, writer: impl Write) -> io::Result<()> {
+    let mut writer = io::BufWriter::new(writer);
+    while let Some(line) = rx.blocking_recv() {
+        write_line(&mut writer, &line, rx.is_empty())?;
+    }
+    writer.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::event::{
+        ApplicationInfo, ElementDetails, EventType, NormalizedRole, Position, Size, WindowInfo,
+    };
+
+    #[test]
+    fn encoder_numbers_events_in_order() {
+        for omit_null_fields in [false, true] {
+            let mut encoder = EventEncoder::new(omit_null_fields);
+            for expected in 1..=3 {
+                let mut event = UiEvent::new(EventType::ValueChanged);
+                let json = encoder.encode(&mut event).unwrap();
+                assert_eq!(event.seq, expected);
+                let decoded: UiEvent = serde_json::from_str(&json).unwrap();
+                assert_eq!(decoded.seq, expected);
+            }
+        }
+    }
+
+    // A text field gaining focus, with what the macOS listener reads by default
+    fn focus_event() -> UiEvent {
+        UiEvent {
+            application: Some(ApplicationInfo {
+                name: Some("Safari".to_owned()),
+                pid: Some(812),
+                bundle_id: Some("com.apple.Safari".to_owned()),
+                path: Some("/Applications/Safari.app".to_owned()),
+            }),
+            window: Some(WindowInfo {
+                title: Some("Start Page".to_owned()),
+                position: Some(Position { x: 120.0, y: 80.0 }),
+                size: Some(Size {
+                    width: 1280.0,
+                    height: 800.0,
+                }),
+                ..Default::default()
+            }),
+            element: Some(ElementDetails {
+                role: Some("AXTextField".to_owned()),
+                normalized_role: Some(NormalizedRole::TextField),
+                identifier: Some("Address and Search".to_owned()),
+                position: Some(Position { x: 420.0, y: 92.0 }),
+                size: Some(Size {
+                    width: 480.0,
+                    height: 28.0,
+                }),
+                ..Default::default()
+            }),
+            ..UiEvent::new(EventType::ElementFocused)
+        }
+    }
+
+    #[test]
+    fn sparse_focus_events_are_much_smaller() {
+        let full = EventEncoder::new(false).encode(&mut focus_event()).unwrap();
+        let sparse = EventEncoder::new(true).encode(&mut focus_event()).unwrap();
+        // 777 and 498 bytes when written
+        assert!(
+            sparse.len() * 10 < full.len() * 7,
+            "{} bytes sparse, {} in full",
+            sparse.len(),
+            full.len()
+        );
+        let decoded: UiEvent = serde_json::from_str(&sparse).unwrap();
+        assert_eq!(
+            decoded.element.unwrap().role.as_deref(),
+            Some("AXTextField")
+        );
+    }
+}